@@ -1,7 +1,6 @@
 import { Webhook } from 'svix'
 import { headers } from 'next/headers'
 import { WebhookEvent } from '@clerk/nextjs/server'
-import { createUserWithClerkId } from '@/lib/api-client'
 
 export async function POST(req: Request) {
   // Get the webhook secret from environment
@@ -51,41 +50,9 @@ export async function POST(req: Request) {
   const eventType = evt.type
 
   if (eventType === 'user.created') {
-    const { id, email_addresses, first_name, last_name } = evt.data
-
-    // Get the primary email
-    const primaryEmail = email_addresses.find(
-      (email) => email.id === evt.data.primary_email_address_id
-    )
-
-    if (!primaryEmail) {
-      console.error('No primary email found for user:', id)
-      return new Response('No primary email found', { status: 400 })
-    }
-
-    // Construct the user's name
-    const name = [first_name, last_name].filter(Boolean).join(' ') || 'User'
-
-    try {
-      // Create user in our database via Rust backend
-      const user = await createUserWithClerkId({
-        clerk_id: id,
-        name: name,
-        email: primaryEmail.email_address,
-      })
-
-      console.log('User created in database:', user)
-
-      return new Response(JSON.stringify({ success: true, user }), {
-        status: 200,
-        headers: { 'Content-Type': 'application/json' },
-      })
-    } catch (error) {
-      console.error('Error creating user in database:', error)
-      return new Response('Error creating user in database', {
-        status: 500,
-      })
-    }
+    // The backend only creates users for their own Clerk session, so the
+    // user is set up on their first dashboard visit rather than here.
+    console.log('User created:', evt.data.id)
   }
 
   if (eventType === 'user.updated') {
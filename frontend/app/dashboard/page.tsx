@@ -1,4 +1,4 @@
-import { currentUser } from '@clerk/nextjs/server'
+import { auth, currentUser } from '@clerk/nextjs/server'
 import { APIList } from "@/components/api-list"
 import { UserButton } from '@clerk/nextjs'
 import { getUserByClerkId, createUserWithClerkId } from '@/lib/api-client'
@@ -15,27 +15,22 @@ export default async function DashboardPage() {
     )
   }
 
+  const { getToken } = await auth()
+  const token = await getToken()
   let dbUser = null
   try {
-    dbUser = await getUserByClerkId(user.id)
+    dbUser = await getUserByClerkId(user.id, token)
     
     if (!dbUser) {
-      if (!user.emailAddresses || user.emailAddresses.length === 0) {
-        throw new Error('No email address found for user')
-      }
-      
-      const primaryEmail = user.emailAddresses[0].emailAddress
-      
       try {
         dbUser = await createUserWithClerkId({
           clerk_id: user.id,
           name: user.firstName && user.lastName 
             ? `${user.firstName} ${user.lastName}`
             : user.firstName || user.username || 'User',
-          email: primaryEmail,
-        })
+        }, token)
       } catch (createError) {
-        dbUser = await getUserByClerkId(user.id)
+        dbUser = await getUserByClerkId(user.id, token)
         
         if (!dbUser) {
           throw new Error('Failed to create or find user')
@@ -9,6 +9,7 @@ import { Tabs, TabsContent, TabsList, TabsTrigger } from "@/components/ui/tabs"
 import { Input } from "@/components/ui/input"
 import { Search, Plus, Trash2, Loader2 } from "lucide-react"
 import { toast } from "sonner"
+import { useAuth } from "@clerk/nextjs"
 import {
   listUserApis,
   listAllApis,
@@ -102,6 +103,7 @@ interface APIListProps {
 }
 
 export function APIList({ userId }: APIListProps) {
+  const { getToken } = useAuth()
   const [apis, setApis] = useState<API[]>([])
   const [loading, setLoading] = useState(true)
   const [selectedAPI, setSelectedAPI] = useState<API | null>(null)
@@ -303,7 +305,7 @@ export function APIList({ userId }: APIListProps) {
         });
       }
 
-      const createdApi = await createApi(userId, request);
+      const createdApi = await createApi(userId, request, await getToken());
         
       const newApi: API = {
         id: createdApi.id,
@@ -7,10 +7,10 @@ export interface User {
   created_at: string;
 }
 
+/** The email address is taken from the Clerk session token. */
 export interface CreateUserWithClerkIdRequest {
   clerk_id: string;
   name: string;
-  email: string;
 }
 
 export interface ApiError {
@@ -49,6 +49,25 @@ export interface ApiEndpoint {
   headers?: Record<string, any>;
   body_schema?: Record<string, any>;
   query_params?: Record<string, any>;
+  cache?: EndpointCache;
+}
+
+export interface EndpointCache {
+  ttl_seconds: number;
+  vary_headers?: string[];
+  hit_price?: number;
+}
+
+export type ApiKind = 'Rest' | 'GraphQL';
+
+export interface GraphQLConfig {
+  path?: string;
+  sdl?: string;
+  introspection?: unknown;
+  operation_prices?: Record<string, number>;
+  complexity_price?: number;
+  max_complexity?: number;
+  max_depth?: number;
 }
 
 export interface PaymentConfig {
@@ -63,31 +82,66 @@ export interface Api {
   name: string;
   description?: string;
   category: ApiCategory;
+  kind: ApiKind;
   base_url: string;
   endpoints: ApiEndpoint[];
   payment_config?: PaymentConfig;
+  graphql?: GraphQLConfig;
+  limits?: ApiLimits;
+  health?: HealthState;
   created_at: string;
   updated_at: string;
 }
 
+export interface ApiLimits {
+  max_request_bytes: number;
+  max_response_bytes: number;
+}
+
+export type HealthState = 'Healthy' | 'Degraded' | 'Unknown';
+
+export interface ApiStatus {
+  api_id: string;
+  state: HealthState;
+  last_checked_at?: string;
+  last_status_code?: number;
+  last_error?: string;
+  window_hours: number;
+  checks: number;
+  uptime_percent?: number;
+  latency_ms: {
+    p50?: number;
+    p95?: number;
+    p99?: number;
+  };
+}
+
 export interface CreateApiRequest {
   name: string;
   description?: string;
   category: ApiCategory;
+  kind?: ApiKind;
   base_url: string;
   endpoints: ApiEndpoint[];
   payment_config?: PaymentConfig;
+  graphql?: GraphQLConfig;
 }
 
 
+/** Request headers, with the Clerk session token for routes that need one. */
+function jsonHeaders(token?: string | null): HeadersInit {
+  return token
+    ? { 'Content-Type': 'application/json', Authorization: `Bearer ${token}` }
+    : { 'Content-Type': 'application/json' };
+}
+
 export async function createUserWithClerkId(
-  request: CreateUserWithClerkIdRequest
+  request: CreateUserWithClerkIdRequest,
+  token?: string | null
 ): Promise<User> {
   const response = await fetch(`${API_URL}/users/clerk`, {
     method: 'POST',
-    headers: {
-      'Content-Type': 'application/json',
-    },
+    headers: jsonHeaders(token),
     body: JSON.stringify(request),
   });
 
@@ -102,12 +156,13 @@ export async function createUserWithClerkId(
   return response.json();
 }
 
-export async function getUserById(userId: string): Promise<User | null> {
+export async function getUserById(
+  userId: string,
+  token?: string | null
+): Promise<User | null> {
   const response = await fetch(`${API_URL}/users/${userId}`, {
     method: 'GET',
-    headers: {
-      'Content-Type': 'application/json',
-    },
+    headers: jsonHeaders(token),
   });
 
   if (response.status === 404) {
@@ -126,13 +181,14 @@ export async function getUserById(userId: string): Promise<User | null> {
 }
 
 
-export async function getUserByClerkId(clerkId: string): Promise<User | null> {
+export async function getUserByClerkId(
+  clerkId: string,
+  token?: string | null
+): Promise<User | null> {
   try {
     const response = await fetch(`${API_URL}/users/clerk/${clerkId}`, {
       method: 'GET',
-      headers: {
-        'Content-Type': 'application/json',
-      },
+      headers: jsonHeaders(token),
       cache: 'no-store',
     });
 
@@ -158,7 +214,8 @@ export async function getUserByClerkId(clerkId: string): Promise<User | null> {
 
 export async function createApi(
   userId: string,
-  request: CreateApiRequest
+  request: CreateApiRequest,
+  token?: string | null
 ): Promise<Api> {
   try {
     const validRequest = {
@@ -172,9 +229,7 @@ export async function createApi(
     
     const response = await fetch(`${API_URL}/users/${userId}/apis`, {
       method: 'POST',
-      headers: {
-        'Content-Type': 'application/json',
-      },
+      headers: jsonHeaders(token),
       body: JSON.stringify(validRequest),
     });
 
@@ -197,7 +252,7 @@ export async function createApi(
         } else {
           errorMsg = typeof errorBody === 'string' 
             ? errorBody 
-            : (errorBody?.message || errorBody?.error || response.statusText);
+            : (errorBody?.detail || errorBody?.message || errorBody?.error || response.statusText);
         }
       } catch (e) {
         errorMsg = response.statusText || `Error code: ${response.status}`;
@@ -283,13 +338,12 @@ export async function getApi(userId: string, apiId: string): Promise<Api | null>
 export async function updateApi(
   userId: string,
   apiId: string,
-  request: CreateApiRequest
+  request: CreateApiRequest,
+  token?: string | null
 ): Promise<Api> {
   const response = await fetch(`${API_URL}/users/${userId}/apis/${apiId}`, {
     method: 'PUT',
-    headers: {
-      'Content-Type': 'application/json',
-    },
+    headers: jsonHeaders(token),
     body: JSON.stringify(request),
   });
 
@@ -305,12 +359,14 @@ export async function updateApi(
 }
 
 
-export async function deleteApi(userId: string, apiId: string): Promise<void> {
+export async function deleteApi(
+  userId: string,
+  apiId: string,
+  token?: string | null
+): Promise<void> {
   const response = await fetch(`${API_URL}/users/${userId}/apis/${apiId}`, {
     method: 'DELETE',
-    headers: {
-      'Content-Type': 'application/json',
-    },
+    headers: jsonHeaders(token),
   });
 
   if (!response.ok) {
@@ -360,3 +416,28 @@ export async function listAllApis(): Promise<Api[]> {
     throw error;
   }
 }
+
+export async function getApiStatus(apiId: string, windowHours?: number): Promise<ApiStatus | null> {
+  const query = windowHours ? `?window_hours=${windowHours}` : '';
+  const response = await fetch(`${API_URL}/apis/${apiId}/status${query}`, {
+    method: 'GET',
+    headers: {
+      'Content-Type': 'application/json',
+    },
+    cache: 'no-store',
+  });
+
+  if (response.status === 404) {
+    return null;
+  }
+
+  if (!response.ok) {
+    const error: ApiError = {
+      message: `Failed to fetch API status: ${response.statusText}`,
+      status: response.status,
+    };
+    throw error;
+  }
+
+  return response.json();
+}
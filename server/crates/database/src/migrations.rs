@@ -55,5 +55,227 @@ pub async fn run_migrations(pool: &PgPool) -> Result<()> {
         .execute(pool)
         .await?;
 
+    sqlx::query("ALTER TABLE apis ADD COLUMN IF NOT EXISTS proxy_config JSONB")
+        .execute(pool)
+        .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS webhooks (
+            id UUID PRIMARY KEY,
+            user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            api_id UUID REFERENCES apis(id) ON DELETE CASCADE,
+            url TEXT NOT NULL,
+            secret TEXT NOT NULL,
+            events TEXT[] NOT NULL DEFAULT '{}',
+            enabled BOOLEAN NOT NULL DEFAULT TRUE,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_webhooks_user_id ON webhooks(user_id)")
+        .execute(pool)
+        .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS webhook_deliveries (
+            id UUID PRIMARY KEY,
+            webhook_id UUID NOT NULL REFERENCES webhooks(id) ON DELETE CASCADE,
+            event TEXT NOT NULL,
+            payload JSONB NOT NULL,
+            status TEXT NOT NULL DEFAULT 'pending',
+            attempts INTEGER NOT NULL DEFAULT 0,
+            last_status_code INTEGER,
+            last_error TEXT,
+            next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+            delivered_at TIMESTAMPTZ,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        "CREATE INDEX IF NOT EXISTS idx_webhook_deliveries_due ON webhook_deliveries(next_attempt_at) WHERE status = 'pending'",
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        "CREATE INDEX IF NOT EXISTS idx_webhook_deliveries_webhook_id ON webhook_deliveries(webhook_id, created_at)",
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS api_secrets (
+            id UUID PRIMARY KEY,
+            api_id UUID NOT NULL REFERENCES apis(id) ON DELETE CASCADE,
+            location TEXT NOT NULL,
+            name TEXT NOT NULL,
+            ciphertext BYTEA NOT NULL,
+            nonce BYTEA NOT NULL,
+            key_id TEXT NOT NULL,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+            updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+            UNIQUE (api_id, location, name)
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query("ALTER TABLE apis ADD COLUMN IF NOT EXISTS kind TEXT NOT NULL DEFAULT 'rest'")
+        .execute(pool)
+        .await?;
+
+    sqlx::query("ALTER TABLE apis ADD COLUMN IF NOT EXISTS graphql_config JSONB")
+        .execute(pool)
+        .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS api_health_checks (
+            id UUID PRIMARY KEY,
+            api_id UUID NOT NULL REFERENCES apis(id) ON DELETE CASCADE,
+            healthy BOOLEAN NOT NULL,
+            status_code INTEGER,
+            latency_ms INTEGER,
+            error TEXT,
+            checked_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        "CREATE INDEX IF NOT EXISTS idx_api_health_checks_api_id ON api_health_checks(api_id, checked_at)",
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query("ALTER TABLE api_health_checks ADD COLUMN IF NOT EXISTS target TEXT")
+        .execute(pool)
+        .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS api_mocks (
+            id UUID PRIMARY KEY,
+            api_id UUID NOT NULL REFERENCES apis(id) ON DELETE CASCADE,
+            method TEXT NOT NULL,
+            path TEXT NOT NULL,
+            status INTEGER NOT NULL,
+            headers JSONB NOT NULL DEFAULT '{}',
+            body TEXT NOT NULL,
+            source TEXT NOT NULL,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+            updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+            UNIQUE (api_id, method, path)
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS call_logs (
+            id UUID PRIMARY KEY,
+            api_id UUID NOT NULL REFERENCES apis(id) ON DELETE CASCADE,
+            method TEXT NOT NULL,
+            path TEXT NOT NULL,
+            endpoint TEXT,
+            status INTEGER NOT NULL,
+            upstream_status INTEGER,
+            latency_ms BIGINT NOT NULL,
+            request_bytes BIGINT,
+            response_bytes BIGINT,
+            payer TEXT,
+            payment_id TEXT,
+            request_body TEXT,
+            response_body TEXT,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        "CREATE INDEX IF NOT EXISTS idx_call_logs_api_id ON call_logs(api_id, created_at DESC)",
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_call_logs_created_at ON call_logs(created_at)")
+        .execute(pool)
+        .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS rate_limit_buckets (
+            key TEXT PRIMARY KEY,
+            tokens DOUBLE PRECISION NOT NULL,
+            updated_at TIMESTAMPTZ NOT NULL,
+            full_at TIMESTAMPTZ NOT NULL
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        "CREATE INDEX IF NOT EXISTS idx_rate_limit_buckets_full_at ON rate_limit_buckets(full_at)",
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS api_tls_configs (
+            api_id UUID PRIMARY KEY REFERENCES apis(id) ON DELETE CASCADE,
+            has_ca_bundle BOOLEAN NOT NULL,
+            has_client_certificate BOOLEAN NOT NULL,
+            ciphertext BYTEA NOT NULL,
+            nonce BYTEA NOT NULL,
+            key_id TEXT NOT NULL,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+            updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS api_keys (
+            id UUID PRIMARY KEY,
+            user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            name TEXT NOT NULL,
+            key_hash TEXT NOT NULL UNIQUE,
+            prefix TEXT NOT NULL,
+            scopes TEXT[] NOT NULL DEFAULT '{}',
+            last_used_at TIMESTAMPTZ,
+            revoked_at TIMESTAMPTZ,
+            created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_api_keys_user_id ON api_keys(user_id)")
+        .execute(pool)
+        .await?;
+
     Ok(())
 }
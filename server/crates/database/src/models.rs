@@ -1,5 +1,9 @@
 use chrono::{DateTime, Utc};
-use shared::{Api, ApiCategory, User};
+use shared::{
+    Api, ApiCategory, ApiKey, ApiKeyScope, ApiKind, ApiMock, ApiSecret, ApiTlsConfig, CallLog,
+    DeliveryStatus, HttpMethod, MockSource, SecretLocation, User, Webhook, WebhookDelivery,
+    WebhookEvent,
+};
 use sqlx::FromRow;
 use uuid::Uuid;
 
@@ -29,9 +33,12 @@ pub struct ApiRow {
     pub name: String,
     pub description: Option<String>,
     pub category: String,
+    pub kind: String,
     pub base_url: String,
     pub endpoints: serde_json::Value,
     pub payment_config: Option<serde_json::Value>,
+    pub proxy_config: Option<serde_json::Value>,
+    pub graphql_config: Option<serde_json::Value>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
@@ -44,13 +51,285 @@ impl From<ApiRow> for Api {
             name: row.name,
             description: row.description,
             category: serde_json::from_str(&row.category).unwrap_or(ApiCategory::Other),
+            kind: ApiKind::parse(&row.kind).unwrap_or_default(),
             base_url: row.base_url,
             endpoints: serde_json::from_value(row.endpoints).unwrap_or_default(),
             payment_config: row
                 .payment_config
                 .and_then(|v| serde_json::from_value(v).ok()),
+            proxy_config: row
+                .proxy_config
+                .and_then(|v| serde_json::from_value(v).ok()),
+            graphql: row
+                .graphql_config
+                .and_then(|v| serde_json::from_value(v).ok()),
+            limits: None,
+            health: None,
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+        }
+    }
+}
+
+#[derive(Debug, Clone, FromRow)]
+pub struct WebhookRow {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub api_id: Option<Uuid>,
+    pub url: String,
+    pub secret: String,
+    pub events: Vec<String>,
+    pub enabled: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<WebhookRow> for Webhook {
+    fn from(row: WebhookRow) -> Self {
+        Webhook {
+            id: row.id,
+            user_id: row.user_id,
+            api_id: row.api_id,
+            url: row.url,
+            events: row
+                .events
+                .iter()
+                .filter_map(|e| WebhookEvent::parse(e))
+                .collect(),
+            secret: None,
+            enabled: row.enabled,
+            created_at: row.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Clone, FromRow)]
+pub struct WebhookDeliveryRow {
+    pub id: Uuid,
+    pub webhook_id: Uuid,
+    pub event: String,
+    pub payload: serde_json::Value,
+    pub status: String,
+    pub attempts: i32,
+    pub last_status_code: Option<i32>,
+    pub last_error: Option<String>,
+    pub next_attempt_at: DateTime<Utc>,
+    pub delivered_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Fails for rows of an event this version doesn't know, rather than
+/// reporting them as some other event.
+impl TryFrom<WebhookDeliveryRow> for WebhookDelivery {
+    type Error = String;
+
+    fn try_from(row: WebhookDeliveryRow) -> Result<Self, String> {
+        let event = WebhookEvent::parse(&row.event)
+            .ok_or_else(|| format!("unknown webhook event {}", row.event))?;
+        Ok(WebhookDelivery {
+            id: row.id,
+            webhook_id: row.webhook_id,
+            event,
+            payload: row.payload,
+            status: DeliveryStatus::parse(&row.status),
+            attempts: row.attempts,
+            last_status_code: row.last_status_code,
+            last_error: row.last_error,
+            next_attempt_at: row.next_attempt_at,
+            delivered_at: row.delivered_at,
+            created_at: row.created_at,
+        })
+    }
+}
+
+/// A pending delivery joined with the target it has to be sent to.
+#[derive(Debug, Clone, FromRow)]
+pub struct DueDeliveryRow {
+    pub id: Uuid,
+    pub event: String,
+    pub payload: serde_json::Value,
+    pub attempts: i32,
+    pub url: String,
+    pub secret: String,
+}
+
+#[derive(Debug, Clone, FromRow)]
+pub struct ApiSecretRow {
+    pub id: Uuid,
+    pub api_id: Uuid,
+    pub location: String,
+    pub name: String,
+    pub ciphertext: Vec<u8>,
+    pub nonce: Vec<u8>,
+    pub key_id: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl From<ApiSecretRow> for ApiSecret {
+    fn from(row: ApiSecretRow) -> Self {
+        ApiSecret {
+            id: row.id,
+            api_id: row.api_id,
+            location: SecretLocation::parse(&row.location).unwrap_or(SecretLocation::Header),
+            name: row.name,
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+        }
+    }
+}
+
+/// An API's upstream TLS settings. The ciphertext holds the PEM material as
+/// JSON, encrypted like secrets.
+#[derive(Debug, Clone, FromRow)]
+pub struct ApiTlsConfigRow {
+    pub api_id: Uuid,
+    pub has_ca_bundle: bool,
+    pub has_client_certificate: bool,
+    pub ciphertext: Vec<u8>,
+    pub nonce: Vec<u8>,
+    pub key_id: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl From<ApiTlsConfigRow> for ApiTlsConfig {
+    fn from(row: ApiTlsConfigRow) -> Self {
+        ApiTlsConfig {
+            api_id: row.api_id,
+            has_ca_bundle: row.has_ca_bundle,
+            has_client_certificate: row.has_client_certificate,
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+        }
+    }
+}
+
+/// One probe of an API. `target` is set for probes of a single load balancing
+/// target, and unset for the API as a whole.
+#[derive(Debug, Clone, FromRow)]
+pub struct HealthCheckRow {
+    pub id: Uuid,
+    pub api_id: Uuid,
+    pub target: Option<String>,
+    pub healthy: bool,
+    pub status_code: Option<i32>,
+    pub latency_ms: Option<i32>,
+    pub error: Option<String>,
+    pub checked_at: DateTime<Utc>,
+}
+
+/// Aggregates over an API's health checks in a time window. Percentiles only
+/// cover checks that got a response.
+#[derive(Debug, Clone, FromRow)]
+pub struct HealthSummaryRow {
+    pub checks: i64,
+    pub healthy_checks: i64,
+    pub p50: Option<f64>,
+    pub p95: Option<f64>,
+    pub p99: Option<f64>,
+}
+
+#[derive(Debug, Clone, FromRow)]
+pub struct ApiMockRow {
+    pub id: Uuid,
+    pub api_id: Uuid,
+    pub method: String,
+    pub path: String,
+    pub status: i32,
+    pub headers: serde_json::Value,
+    pub body: String,
+    pub source: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl From<ApiMockRow> for ApiMock {
+    fn from(row: ApiMockRow) -> Self {
+        ApiMock {
+            id: row.id,
+            api_id: row.api_id,
+            method: HttpMethod::parse(&row.method).unwrap_or(HttpMethod::GET),
+            path: row.path,
+            status: u16::try_from(row.status).unwrap_or(200),
+            headers: serde_json::from_value(row.headers).unwrap_or_default(),
+            body: row.body,
+            source: MockSource::parse(&row.source),
             created_at: row.created_at,
             updated_at: row.updated_at,
         }
     }
 }
+
+#[derive(Debug, Clone, FromRow)]
+pub struct CallLogRow {
+    pub id: Uuid,
+    pub api_id: Uuid,
+    pub method: String,
+    pub path: String,
+    pub endpoint: Option<String>,
+    pub status: i32,
+    pub upstream_status: Option<i32>,
+    pub latency_ms: i64,
+    pub request_bytes: Option<i64>,
+    pub response_bytes: Option<i64>,
+    pub payer: Option<String>,
+    pub payment_id: Option<String>,
+    pub request_body: Option<String>,
+    pub response_body: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<CallLogRow> for CallLog {
+    fn from(row: CallLogRow) -> Self {
+        CallLog {
+            id: row.id,
+            api_id: row.api_id,
+            method: row.method,
+            path: row.path,
+            endpoint: row.endpoint,
+            status: u16::try_from(row.status).unwrap_or_default(),
+            upstream_status: row.upstream_status.and_then(|s| u16::try_from(s).ok()),
+            latency_ms: u64::try_from(row.latency_ms).unwrap_or_default(),
+            request_bytes: row.request_bytes.and_then(|b| u64::try_from(b).ok()),
+            response_bytes: row.response_bytes.and_then(|b| u64::try_from(b).ok()),
+            payer: row.payer,
+            payment_id: row.payment_id,
+            request_body: row.request_body,
+            response_body: row.response_body,
+            created_at: row.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Clone, FromRow)]
+pub struct ApiKeyRow {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub name: String,
+    pub key_hash: String,
+    pub prefix: String,
+    pub scopes: Vec<String>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<ApiKeyRow> for ApiKey {
+    fn from(row: ApiKeyRow) -> Self {
+        ApiKey {
+            id: row.id,
+            user_id: row.user_id,
+            name: row.name,
+            prefix: row.prefix,
+            scopes: row
+                .scopes
+                .iter()
+                .filter_map(|s| ApiKeyScope::parse(s))
+                .collect(),
+            key: None,
+            last_used_at: row.last_used_at,
+            revoked_at: row.revoked_at,
+            created_at: row.created_at,
+        }
+    }
+}
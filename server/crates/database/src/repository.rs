@@ -1,9 +1,23 @@
-use crate::models::{ApiRow, UserRow};
+use crate::models::{
+    ApiKeyRow, ApiMockRow, ApiRow, ApiSecretRow, ApiTlsConfigRow, CallLogRow, DueDeliveryRow,
+    HealthCheckRow, HealthSummaryRow, UserRow, WebhookDeliveryRow, WebhookRow,
+};
 use anyhow::Result;
-use shared::{Api, CreateApiRequest, CreateUserRequest, User};
+use shared::{
+    Api, ApiKey, ApiMock, ApiSecret, ApiTlsConfig, CallLog, CallLogFilter, CreateApiRequest, SecretLocation, CreateUserRequest, CreateWebhookRequest,
+    CreateApiKeyRequest, HttpMethod, MockSource, User, Webhook, WebhookDelivery, WebhookEvent,
+};
 use sqlx::PgPool;
+use std::collections::HashMap;
 use uuid::Uuid;
 
+/// The user id for a Clerk user. Clerk ids like `user_xxx` are mapped to a
+/// deterministic UUIDv5, while ids that already are UUIDs are used as they are.
+pub fn user_id_for_clerk_id(clerk_id: &str) -> Uuid {
+    Uuid::parse_str(clerk_id)
+        .unwrap_or_else(|_| Uuid::new_v5(&Uuid::NAMESPACE_OID, clerk_id.as_bytes()))
+}
+
 #[derive(Clone)]
 pub struct UserRepository {
     pool: PgPool,
@@ -14,6 +28,46 @@ pub struct ApiRepository {
     pool: PgPool,
 }
 
+#[derive(Clone, Debug)]
+pub struct WebhookRepository {
+    pool: PgPool,
+}
+
+#[derive(Clone, Debug)]
+pub struct ApiSecretRepository {
+    pool: PgPool,
+}
+
+#[derive(Clone, Debug)]
+pub struct ApiTlsRepository {
+    pool: PgPool,
+}
+
+#[derive(Clone, Debug)]
+pub struct ApiKeyRepository {
+    pool: PgPool,
+}
+
+#[derive(Clone, Debug)]
+pub struct HealthCheckRepository {
+    pool: PgPool,
+}
+
+#[derive(Clone, Debug)]
+pub struct ApiMockRepository {
+    pool: PgPool,
+}
+
+#[derive(Clone, Debug)]
+pub struct CallLogRepository {
+    pool: PgPool,
+}
+
+#[derive(Clone, Debug)]
+pub struct RateLimitRepository {
+    pool: PgPool,
+}
+
 impl UserRepository {
     pub fn new(pool: PgPool) -> Self {
         Self { pool }
@@ -26,43 +80,42 @@ impl UserRepository {
         let user = sqlx::query_as::<_, UserRow>(
             "INSERT INTO users (id, name, email, created_at) VALUES ($1, $2, $3, $4) RETURNING *",
         )
-        .bind(&id)
+        .bind(id)
         .bind(&request.name)
         .bind(&request.email)
-        .bind(&now)
+        .bind(now)
         .fetch_one(&self.pool)
         .await?;
 
         Ok(user.into())
     }
 
-    pub async fn create_user_with_clerk_id(&self, clerk_id: &str, name: String, email: String) -> Result<User> {
-        // Parse Clerk ID as UUID (Clerk user IDs like "user_xxx" need to be converted)
-        // For now, we'll use the clerk_id directly if it's a valid UUID, otherwise generate one
-        let id = Uuid::parse_str(clerk_id).unwrap_or_else(|_| {
-            // If Clerk ID is not a valid UUID, create a deterministic UUID from the clerk_id
-            Uuid::new_v5(&Uuid::NAMESPACE_OID, clerk_id.as_bytes())
-        });
+    /// Creates the user for a Clerk account, or renames it when it already
+    /// exists. Returns `None` when the email belongs to another user, which is
+    /// left untouched.
+    pub async fn create_user_with_clerk_id(&self, clerk_id: &str, name: String, email: String) -> Result<Option<User>> {
+        let id = user_id_for_clerk_id(clerk_id);
         let now = chrono::Utc::now();
 
         let user = sqlx::query_as::<_, UserRow>(
             "INSERT INTO users (id, name, email, created_at) VALUES ($1, $2, $3, $4)
              ON CONFLICT (email) DO UPDATE SET name = EXCLUDED.name
+             WHERE users.id = EXCLUDED.id
              RETURNING *",
         )
-        .bind(&id)
+        .bind(id)
         .bind(&name)
         .bind(&email)
-        .bind(&now)
-        .fetch_one(&self.pool)
+        .bind(now)
+        .fetch_optional(&self.pool)
         .await?;
 
-        Ok(user.into())
+        Ok(user.map(Into::into))
     }
 
     pub async fn get_user_by_id(&self, id: Uuid) -> Result<Option<User>> {
         let user = sqlx::query_as::<_, UserRow>("SELECT * FROM users WHERE id = $1")
-            .bind(&id)
+            .bind(id)
             .fetch_optional(&self.pool)
             .await?;
 
@@ -90,24 +143,35 @@ impl ApiRepository {
         let endpoints_json = serde_json::to_value(&request.endpoints)?;
         let payment_config_json = request
             .payment_config
-            .map(|config| serde_json::to_value(config))
+            .map(serde_json::to_value)
+            .transpose()?;
+        let proxy_config_json = request
+            .proxy_config
+            .map(serde_json::to_value)
+            .transpose()?;
+        let graphql_config_json = request
+            .graphql
+            .map(serde_json::to_value)
             .transpose()?;
         let category_str = serde_json::to_string(&request.category)?;
 
         let api = sqlx::query_as::<_, ApiRow>(
-            "INSERT INTO apis (id, user_id, name, description, category, base_url, endpoints, payment_config, created_at, updated_at) 
-             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10) RETURNING *"
+            "INSERT INTO apis (id, user_id, name, description, category, base_url, endpoints, payment_config, proxy_config, kind, graphql_config, created_at, updated_at) 
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13) RETURNING *"
         )
-        .bind(&id)
-        .bind(&user_id)
+        .bind(id)
+        .bind(user_id)
         .bind(&request.name)
         .bind(&request.description)
         .bind(&category_str)
         .bind(&request.base_url)
         .bind(&endpoints_json)
         .bind(&payment_config_json)
-        .bind(&now)
-        .bind(&now)
+        .bind(&proxy_config_json)
+        .bind(request.kind.as_str())
+        .bind(&graphql_config_json)
+        .bind(now)
+        .bind(now)
         .fetch_one(&self.pool)
         .await?;
 
@@ -116,7 +180,7 @@ impl ApiRepository {
 
     pub async fn get_api_by_id(&self, id: Uuid) -> Result<Option<Api>> {
         let api = sqlx::query_as::<_, ApiRow>("SELECT * FROM apis WHERE id = $1")
-            .bind(&id)
+            .bind(id)
             .fetch_optional(&self.pool)
             .await?;
 
@@ -127,7 +191,7 @@ impl ApiRepository {
         let apis = sqlx::query_as::<_, ApiRow>(
             "SELECT * FROM apis WHERE user_id = $1 ORDER BY created_at DESC",
         )
-        .bind(&user_id)
+        .bind(user_id)
         .fetch_all(&self.pool)
         .await?;
 
@@ -139,22 +203,33 @@ impl ApiRepository {
         let endpoints_json = serde_json::to_value(&request.endpoints)?;
         let payment_config_json = request
             .payment_config
-            .map(|config| serde_json::to_value(config))
+            .map(serde_json::to_value)
+            .transpose()?;
+        let proxy_config_json = request
+            .proxy_config
+            .map(serde_json::to_value)
+            .transpose()?;
+        let graphql_config_json = request
+            .graphql
+            .map(serde_json::to_value)
             .transpose()?;
         let category_str = serde_json::to_string(&request.category)?;
 
         let api = sqlx::query_as::<_, ApiRow>(
-            "UPDATE apis SET name = $2, description = $3, category = $4, base_url = $5, endpoints = $6, payment_config = $7, updated_at = $8 
+            "UPDATE apis SET name = $2, description = $3, category = $4, base_url = $5, endpoints = $6, payment_config = $7, proxy_config = $8, kind = $9, graphql_config = $10, updated_at = $11 
              WHERE id = $1 RETURNING *"
         )
-        .bind(&id)
+        .bind(id)
         .bind(&request.name)
         .bind(&request.description)
         .bind(&category_str)
         .bind(&request.base_url)
         .bind(&endpoints_json)
         .bind(&payment_config_json)
-        .bind(&now)
+        .bind(&proxy_config_json)
+        .bind(request.kind.as_str())
+        .bind(&graphql_config_json)
+        .bind(now)
         .fetch_optional(&self.pool)
         .await?;
 
@@ -163,7 +238,7 @@ impl ApiRepository {
 
     pub async fn delete_api(&self, id: Uuid) -> Result<bool> {
         let result = sqlx::query("DELETE FROM apis WHERE id = $1")
-            .bind(&id)
+            .bind(id)
             .execute(&self.pool)
             .await?;
 
@@ -182,3 +257,831 @@ impl ApiRepository {
         Ok(result)
     }
 }
+
+impl WebhookRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    pub async fn create_webhook(
+        &self,
+        user_id: Uuid,
+        secret: &str,
+        request: CreateWebhookRequest,
+    ) -> Result<Webhook> {
+        let id = Uuid::new_v4();
+        let now = chrono::Utc::now();
+        let events: Vec<String> = request
+            .events
+            .iter()
+            .map(|e| e.as_str().to_string())
+            .collect();
+
+        let webhook = sqlx::query_as::<_, WebhookRow>(
+            "INSERT INTO webhooks (id, user_id, api_id, url, secret, events, enabled, created_at)
+             VALUES ($1, $2, $3, $4, $5, $6, TRUE, $7) RETURNING *",
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(request.api_id)
+        .bind(&request.url)
+        .bind(secret)
+        .bind(&events)
+        .bind(now)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(webhook.into())
+    }
+
+    pub async fn get_webhooks_by_user_id(&self, user_id: Uuid) -> Result<Vec<Webhook>> {
+        let webhooks = sqlx::query_as::<_, WebhookRow>(
+            "SELECT * FROM webhooks WHERE user_id = $1 ORDER BY created_at DESC",
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(webhooks.into_iter().map(|w| w.into()).collect())
+    }
+
+    pub async fn get_webhook_by_id(&self, id: Uuid) -> Result<Option<Webhook>> {
+        let webhook = sqlx::query_as::<_, WebhookRow>("SELECT * FROM webhooks WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(webhook.map(|w| w.into()))
+    }
+
+    pub async fn delete_webhook(&self, id: Uuid) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM webhooks WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn get_deliveries_by_webhook_id(
+        &self,
+        webhook_id: Uuid,
+        limit: i64,
+    ) -> Result<Vec<WebhookDelivery>> {
+        let deliveries = sqlx::query_as::<_, WebhookDeliveryRow>(
+            "SELECT * FROM webhook_deliveries WHERE webhook_id = $1 ORDER BY created_at DESC LIMIT $2",
+        )
+        .bind(webhook_id)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        // Deliveries of events this version doesn't know are left out.
+        Ok(deliveries
+            .into_iter()
+            .filter_map(|d| d.try_into().ok())
+            .collect())
+    }
+
+    /// Queues one delivery per enabled webhook of `user_id` subscribed to `event`,
+    /// either for every API of the user or for `api_id` specifically.
+    pub async fn enqueue_event(
+        &self,
+        user_id: Uuid,
+        api_id: Option<Uuid>,
+        event: WebhookEvent,
+        payload: serde_json::Value,
+    ) -> Result<u64> {
+        let webhook_ids: Vec<Uuid> = sqlx::query_scalar(
+            "SELECT id FROM webhooks
+             WHERE user_id = $1 AND enabled AND $2 = ANY(events)
+               AND (api_id IS NULL OR api_id = $3)",
+        )
+        .bind(user_id)
+        .bind(event.as_str())
+        .bind(api_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let now = chrono::Utc::now();
+        for webhook_id in &webhook_ids {
+            sqlx::query(
+                "INSERT INTO webhook_deliveries (id, webhook_id, event, payload, status, attempts, next_attempt_at, created_at)
+                 VALUES ($1, $2, $3, $4, 'pending', 0, $5, $5)",
+            )
+            .bind(Uuid::new_v4())
+            .bind(webhook_id)
+            .bind(event.as_str())
+            .bind(&payload)
+            .bind(now)
+            .execute(&self.pool)
+            .await?;
+        }
+
+        Ok(webhook_ids.len() as u64)
+    }
+
+    /// Claims up to `limit` due deliveries by pushing their next attempt `lease_seconds`
+    /// into the future, so concurrent dispatchers never send the same delivery twice.
+    pub async fn claim_due_deliveries(
+        &self,
+        limit: i64,
+        lease_seconds: i64,
+    ) -> Result<Vec<DueDeliveryRow>> {
+        let deliveries = sqlx::query_as::<_, DueDeliveryRow>(
+            "WITH due AS (
+                 SELECT id FROM webhook_deliveries
+                 WHERE status = 'pending' AND next_attempt_at <= NOW()
+                 ORDER BY next_attempt_at
+                 LIMIT $1
+                 FOR UPDATE SKIP LOCKED
+             )
+             UPDATE webhook_deliveries d
+             SET next_attempt_at = NOW() + make_interval(secs => $2)
+             FROM due, webhooks w
+             WHERE d.id = due.id AND w.id = d.webhook_id
+             RETURNING d.id, d.event, d.payload, d.attempts, w.url, w.secret",
+        )
+        .bind(limit)
+        .bind(lease_seconds as f64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(deliveries)
+    }
+
+    pub async fn mark_delivered(&self, id: Uuid, status_code: i32) -> Result<()> {
+        sqlx::query(
+            "UPDATE webhook_deliveries
+             SET status = 'delivered', attempts = attempts + 1, last_status_code = $2,
+                 last_error = NULL, delivered_at = NOW()
+             WHERE id = $1",
+        )
+        .bind(id)
+        .bind(status_code)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Records a failed attempt. The delivery is retried at `retry_at`, or marked
+    /// as failed for good when `retry_at` is `None`.
+    pub async fn mark_attempt_failed(
+        &self,
+        id: Uuid,
+        status_code: Option<i32>,
+        error: &str,
+        retry_at: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> Result<()> {
+        sqlx::query(
+            "UPDATE webhook_deliveries
+             SET attempts = attempts + 1, last_status_code = $2, last_error = $3,
+                 status = CASE WHEN $4::timestamptz IS NULL THEN 'failed' ELSE 'pending' END,
+                 next_attempt_at = COALESCE($4, next_attempt_at)
+             WHERE id = $1",
+        )
+        .bind(id)
+        .bind(status_code)
+        .bind(error)
+        .bind(retry_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+impl ApiSecretRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Stores an encrypted secret, replacing any existing one at the same location and name.
+    pub async fn upsert_secret(
+        &self,
+        api_id: Uuid,
+        location: SecretLocation,
+        name: &str,
+        ciphertext: &[u8],
+        nonce: &[u8],
+        key_id: &str,
+    ) -> Result<ApiSecret> {
+        let id = Uuid::new_v4();
+        let now = chrono::Utc::now();
+
+        let secret = sqlx::query_as::<_, ApiSecretRow>(
+            "INSERT INTO api_secrets (id, api_id, location, name, ciphertext, nonce, key_id, created_at, updated_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $8)
+             ON CONFLICT (api_id, location, name) DO UPDATE
+             SET ciphertext = EXCLUDED.ciphertext, nonce = EXCLUDED.nonce,
+                 key_id = EXCLUDED.key_id, updated_at = EXCLUDED.updated_at
+             RETURNING *",
+        )
+        .bind(id)
+        .bind(api_id)
+        .bind(location.as_str())
+        .bind(name)
+        .bind(ciphertext)
+        .bind(nonce)
+        .bind(key_id)
+        .bind(now)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(secret.into())
+    }
+
+    pub async fn get_secrets_by_api_id(&self, api_id: Uuid) -> Result<Vec<ApiSecret>> {
+        let secrets = self.get_encrypted_secrets(api_id).await?;
+        Ok(secrets.into_iter().map(|s| s.into()).collect())
+    }
+
+    pub async fn get_encrypted_secrets(&self, api_id: Uuid) -> Result<Vec<ApiSecretRow>> {
+        let secrets = sqlx::query_as::<_, ApiSecretRow>(
+            "SELECT * FROM api_secrets WHERE api_id = $1 ORDER BY created_at",
+        )
+        .bind(api_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(secrets)
+    }
+
+    pub async fn delete_secret(&self, api_id: Uuid, id: Uuid) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM api_secrets WHERE id = $1 AND api_id = $2")
+            .bind(id)
+            .bind(api_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn get_secrets_not_under_key(&self, key_id: &str) -> Result<Vec<ApiSecretRow>> {
+        let secrets =
+            sqlx::query_as::<_, ApiSecretRow>("SELECT * FROM api_secrets WHERE key_id <> $1")
+                .bind(key_id)
+                .fetch_all(&self.pool)
+                .await?;
+
+        Ok(secrets)
+    }
+
+    pub async fn update_ciphertext(
+        &self,
+        id: Uuid,
+        ciphertext: &[u8],
+        nonce: &[u8],
+        key_id: &str,
+    ) -> Result<()> {
+        sqlx::query(
+            "UPDATE api_secrets SET ciphertext = $2, nonce = $3, key_id = $4 WHERE id = $1",
+        )
+        .bind(id)
+        .bind(ciphertext)
+        .bind(nonce)
+        .bind(key_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+impl ApiTlsRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Stores an API's encrypted TLS material, replacing what it had before.
+    pub async fn upsert_tls_config(
+        &self,
+        api_id: Uuid,
+        has_ca_bundle: bool,
+        has_client_certificate: bool,
+        ciphertext: &[u8],
+        nonce: &[u8],
+        key_id: &str,
+    ) -> Result<ApiTlsConfig> {
+        let now = chrono::Utc::now();
+
+        let config = sqlx::query_as::<_, ApiTlsConfigRow>(
+            "INSERT INTO api_tls_configs (api_id, has_ca_bundle, has_client_certificate, ciphertext, nonce, key_id, created_at, updated_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $7)
+             ON CONFLICT (api_id) DO UPDATE
+             SET has_ca_bundle = EXCLUDED.has_ca_bundle,
+                 has_client_certificate = EXCLUDED.has_client_certificate,
+                 ciphertext = EXCLUDED.ciphertext, nonce = EXCLUDED.nonce,
+                 key_id = EXCLUDED.key_id, updated_at = EXCLUDED.updated_at
+             RETURNING *",
+        )
+        .bind(api_id)
+        .bind(has_ca_bundle)
+        .bind(has_client_certificate)
+        .bind(ciphertext)
+        .bind(nonce)
+        .bind(key_id)
+        .bind(now)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(config.into())
+    }
+
+    pub async fn get_tls_config(&self, api_id: Uuid) -> Result<Option<ApiTlsConfig>> {
+        let config = self.get_encrypted_tls_config(api_id).await?;
+        Ok(config.map(|c| c.into()))
+    }
+
+    pub async fn get_encrypted_tls_config(&self, api_id: Uuid) -> Result<Option<ApiTlsConfigRow>> {
+        let config =
+            sqlx::query_as::<_, ApiTlsConfigRow>("SELECT * FROM api_tls_configs WHERE api_id = $1")
+                .bind(api_id)
+                .fetch_optional(&self.pool)
+                .await?;
+
+        Ok(config)
+    }
+
+    pub async fn delete_tls_config(&self, api_id: Uuid) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM api_tls_configs WHERE api_id = $1")
+            .bind(api_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn get_tls_configs_not_under_key(
+        &self,
+        key_id: &str,
+    ) -> Result<Vec<ApiTlsConfigRow>> {
+        let configs = sqlx::query_as::<_, ApiTlsConfigRow>(
+            "SELECT * FROM api_tls_configs WHERE key_id <> $1",
+        )
+        .bind(key_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(configs)
+    }
+
+    /// Replaces the ciphertext without touching `updated_at`, which identifies
+    /// the material itself.
+    pub async fn update_ciphertext(
+        &self,
+        api_id: Uuid,
+        ciphertext: &[u8],
+        nonce: &[u8],
+        key_id: &str,
+    ) -> Result<()> {
+        sqlx::query(
+            "UPDATE api_tls_configs SET ciphertext = $2, nonce = $3, key_id = $4 WHERE api_id = $1",
+        )
+        .bind(api_id)
+        .bind(ciphertext)
+        .bind(nonce)
+        .bind(key_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+impl HealthCheckRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    pub async fn record_check(
+        &self,
+        api_id: Uuid,
+        target: Option<&str>,
+        healthy: bool,
+        status_code: Option<i32>,
+        latency_ms: Option<i32>,
+        error: Option<&str>,
+    ) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO api_health_checks (id, api_id, target, healthy, status_code, latency_ms, error, checked_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, NOW())",
+        )
+        .bind(Uuid::new_v4())
+        .bind(api_id)
+        .bind(target)
+        .bind(healthy)
+        .bind(status_code)
+        .bind(latency_ms)
+        .bind(error)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// The most recent check of every API that has been checked.
+    pub async fn get_latest_checks(&self) -> Result<Vec<HealthCheckRow>> {
+        let checks = sqlx::query_as::<_, HealthCheckRow>(
+            "SELECT DISTINCT ON (api_id) * FROM api_health_checks
+             WHERE target IS NULL
+             ORDER BY api_id, checked_at DESC",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(checks)
+    }
+
+    pub async fn get_latest_check(&self, api_id: Uuid) -> Result<Option<HealthCheckRow>> {
+        let check = sqlx::query_as::<_, HealthCheckRow>(
+            "SELECT * FROM api_health_checks WHERE api_id = $1 AND target IS NULL
+             ORDER BY checked_at DESC LIMIT 1",
+        )
+        .bind(api_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(check)
+    }
+
+    pub async fn summarize(
+        &self,
+        api_id: Uuid,
+        since: chrono::DateTime<chrono::Utc>,
+    ) -> Result<HealthSummaryRow> {
+        let summary = sqlx::query_as::<_, HealthSummaryRow>(
+            "SELECT COUNT(*) AS checks,
+                    COUNT(*) FILTER (WHERE healthy) AS healthy_checks,
+                    percentile_cont(0.5) WITHIN GROUP (ORDER BY latency_ms) AS p50,
+                    percentile_cont(0.95) WITHIN GROUP (ORDER BY latency_ms) AS p95,
+                    percentile_cont(0.99) WITHIN GROUP (ORDER BY latency_ms) AS p99
+             FROM api_health_checks
+             WHERE api_id = $1 AND target IS NULL AND checked_at >= $2",
+        )
+        .bind(api_id)
+        .bind(since)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(summary)
+    }
+
+    /// Load balancing targets whose latest check since `since` failed.
+    pub async fn get_unhealthy_targets(
+        &self,
+        since: chrono::DateTime<chrono::Utc>,
+    ) -> Result<Vec<(Uuid, String)>> {
+        let targets = sqlx::query_as::<_, (Uuid, String)>(
+            "SELECT api_id, target FROM (
+                 SELECT DISTINCT ON (api_id, target) api_id, target, healthy
+                 FROM api_health_checks
+                 WHERE target IS NOT NULL AND checked_at >= $1
+                 ORDER BY api_id, target, checked_at DESC
+             ) latest
+             WHERE NOT healthy",
+        )
+        .bind(since)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(targets)
+    }
+
+    /// Deletes checks older than `before` and returns how many were removed.
+    pub async fn prune_checks(&self, before: chrono::DateTime<chrono::Utc>) -> Result<u64> {
+        let result = sqlx::query("DELETE FROM api_health_checks WHERE checked_at < $1")
+            .bind(before)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+impl ApiMockRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Stores a mock, replacing any existing one for the same method and path.
+    pub async fn upsert_mock(
+        &self,
+        api_id: Uuid,
+        method: &HttpMethod,
+        path: &str,
+        status: u16,
+        headers: &HashMap<String, String>,
+        body: &str,
+    ) -> Result<ApiMock> {
+        let now = chrono::Utc::now();
+
+        let mock = sqlx::query_as::<_, ApiMockRow>(
+            "INSERT INTO api_mocks (id, api_id, method, path, status, headers, body, source, created_at, updated_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $9)
+             ON CONFLICT (api_id, method, path) DO UPDATE
+             SET status = EXCLUDED.status, headers = EXCLUDED.headers, body = EXCLUDED.body,
+                 source = EXCLUDED.source, updated_at = EXCLUDED.updated_at
+             RETURNING *",
+        )
+        .bind(Uuid::new_v4())
+        .bind(api_id)
+        .bind(method.as_str())
+        .bind(path)
+        .bind(i32::from(status))
+        .bind(serde_json::to_value(headers)?)
+        .bind(body)
+        .bind(MockSource::Uploaded.as_str())
+        .bind(now)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(mock.into())
+    }
+
+    /// Stores a recorded mock unless the method and path already have one.
+    pub async fn insert_recorded_mock(
+        &self,
+        api_id: Uuid,
+        method: &HttpMethod,
+        path: &str,
+        status: u16,
+        headers: &HashMap<String, String>,
+        body: &str,
+    ) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO api_mocks (id, api_id, method, path, status, headers, body, source, created_at, updated_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, NOW(), NOW())
+             ON CONFLICT (api_id, method, path) DO NOTHING",
+        )
+        .bind(Uuid::new_v4())
+        .bind(api_id)
+        .bind(method.as_str())
+        .bind(path)
+        .bind(i32::from(status))
+        .bind(serde_json::to_value(headers)?)
+        .bind(body)
+        .bind(MockSource::Recorded.as_str())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn get_mocks_by_api_id(&self, api_id: Uuid) -> Result<Vec<ApiMock>> {
+        let mocks = sqlx::query_as::<_, ApiMockRow>(
+            "SELECT * FROM api_mocks WHERE api_id = $1 ORDER BY path, method",
+        )
+        .bind(api_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(mocks.into_iter().map(|m| m.into()).collect())
+    }
+
+    pub async fn get_mock(
+        &self,
+        api_id: Uuid,
+        method: &HttpMethod,
+        path: &str,
+    ) -> Result<Option<ApiMock>> {
+        let mock = sqlx::query_as::<_, ApiMockRow>(
+            "SELECT * FROM api_mocks WHERE api_id = $1 AND method = $2 AND path = $3",
+        )
+        .bind(api_id)
+        .bind(method.as_str())
+        .bind(path)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(mock.map(|m| m.into()))
+    }
+
+    pub async fn delete_mock(&self, api_id: Uuid, id: Uuid) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM api_mocks WHERE id = $1 AND api_id = $2")
+            .bind(id)
+            .bind(api_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+impl CallLogRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Calls to APIs that don't exist are dropped.
+    pub async fn insert_call_log(&self, log: &CallLog) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO call_logs (id, api_id, method, path, endpoint, status, upstream_status, latency_ms,
+                                    request_bytes, response_bytes, payer, payment_id, request_body, response_body, created_at)
+             SELECT $1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15
+             WHERE EXISTS (SELECT 1 FROM apis WHERE id = $2)",
+        )
+        .bind(log.id)
+        .bind(log.api_id)
+        .bind(&log.method)
+        .bind(&log.path)
+        .bind(&log.endpoint)
+        .bind(i32::from(log.status))
+        .bind(log.upstream_status.map(i32::from))
+        .bind(i64::try_from(log.latency_ms).unwrap_or(i64::MAX))
+        .bind(log.request_bytes.and_then(|b| i64::try_from(b).ok()))
+        .bind(log.response_bytes.and_then(|b| i64::try_from(b).ok()))
+        .bind(&log.payer)
+        .bind(&log.payment_id)
+        .bind(&log.request_body)
+        .bind(&log.response_body)
+        .bind(log.created_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// The API's calls matching `filter`, newest first.
+    pub async fn get_call_logs(
+        &self,
+        api_id: Uuid,
+        filter: &CallLogFilter,
+        limit: i64,
+    ) -> Result<Vec<CallLog>> {
+        let logs = sqlx::query_as::<_, CallLogRow>(
+            "SELECT * FROM call_logs
+             WHERE api_id = $1
+               AND ($2::INTEGER IS NULL OR status = $2)
+               AND ($3::TEXT IS NULL OR payer = $3)
+               AND ($4::TEXT IS NULL OR endpoint = $4)
+               AND ($5::TIMESTAMPTZ IS NULL OR created_at >= $5)
+               AND ($6::TIMESTAMPTZ IS NULL OR created_at < $6)
+             ORDER BY created_at DESC
+             LIMIT $7",
+        )
+        .bind(api_id)
+        .bind(filter.status.map(i32::from))
+        .bind(&filter.payer)
+        .bind(&filter.endpoint)
+        .bind(filter.since)
+        .bind(filter.until)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(logs.into_iter().map(|l| l.into()).collect())
+    }
+
+    pub async fn prune_call_logs(&self, before: chrono::DateTime<chrono::Utc>) -> Result<u64> {
+        let result = sqlx::query("DELETE FROM call_logs WHERE created_at < $1")
+            .bind(before)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+impl RateLimitRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Takes a token from the bucket `key`, which holds up to `capacity` tokens
+    /// and refills at `refill_per_second`. Returns the seconds until the next
+    /// token when the bucket is empty.
+    pub async fn take_token(
+        &self,
+        key: &str,
+        capacity: f64,
+        refill_per_second: f64,
+    ) -> Result<Option<f64>> {
+        let taken: Option<f64> = sqlx::query_scalar(
+            "INSERT INTO rate_limit_buckets AS b (key, tokens, updated_at, full_at)
+             VALUES ($1, $2 - 1, NOW(), NOW() + make_interval(secs => 1 / $3))
+             ON CONFLICT (key) DO UPDATE
+             SET tokens = LEAST($2, b.tokens + EXTRACT(EPOCH FROM NOW() - b.updated_at)::DOUBLE PRECISION * $3) - 1,
+                 updated_at = NOW(),
+                 full_at = NOW() + make_interval(secs => ($2 - LEAST($2, b.tokens + EXTRACT(EPOCH FROM NOW() - b.updated_at)::DOUBLE PRECISION * $3) + 1) / $3)
+             WHERE LEAST($2, b.tokens + EXTRACT(EPOCH FROM NOW() - b.updated_at)::DOUBLE PRECISION * $3) >= 1
+             RETURNING tokens",
+        )
+        .bind(key)
+        .bind(capacity)
+        .bind(refill_per_second)
+        .fetch_optional(&self.pool)
+        .await?;
+        if taken.is_some() {
+            return Ok(None);
+        }
+
+        let wait: Option<f64> = sqlx::query_scalar(
+            "SELECT (1 - LEAST($2, tokens + EXTRACT(EPOCH FROM NOW() - updated_at)::DOUBLE PRECISION * $3)) / $3
+             FROM rate_limit_buckets WHERE key = $1",
+        )
+        .bind(key)
+        .bind(capacity)
+        .bind(refill_per_second)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(Some(wait.unwrap_or_default().max(0.0)))
+    }
+
+    /// Deletes buckets that have refilled completely, which behave the same as
+    /// buckets that don't exist.
+    pub async fn prune_buckets(&self) -> Result<u64> {
+        let result = sqlx::query("DELETE FROM rate_limit_buckets WHERE full_at < NOW()")
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+impl ApiKeyRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    pub async fn create_api_key(
+        &self,
+        user_id: Uuid,
+        key_hash: &str,
+        prefix: &str,
+        request: CreateApiKeyRequest,
+    ) -> Result<ApiKey> {
+        let scopes: Vec<String> = request
+            .scopes
+            .iter()
+            .map(|s| s.as_str().to_string())
+            .collect();
+
+        let key = sqlx::query_as::<_, ApiKeyRow>(
+            "INSERT INTO api_keys (id, user_id, name, key_hash, prefix, scopes, created_at)
+             VALUES ($1, $2, $3, $4, $5, $6, NOW()) RETURNING *",
+        )
+        .bind(Uuid::new_v4())
+        .bind(user_id)
+        .bind(request.name.trim())
+        .bind(key_hash)
+        .bind(prefix)
+        .bind(&scopes)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(key.into())
+    }
+
+    pub async fn get_api_keys_by_user_id(&self, user_id: Uuid) -> Result<Vec<ApiKey>> {
+        let keys = sqlx::query_as::<_, ApiKeyRow>(
+            "SELECT * FROM api_keys WHERE user_id = $1 ORDER BY created_at DESC",
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(keys.into_iter().map(Into::into).collect())
+    }
+
+    /// The unrevoked key with this hash.
+    pub async fn get_active_api_key_by_hash(&self, key_hash: &str) -> Result<Option<ApiKey>> {
+        let key = sqlx::query_as::<_, ApiKeyRow>(
+            "SELECT * FROM api_keys WHERE key_hash = $1 AND revoked_at IS NULL",
+        )
+        .bind(key_hash)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(key.map(Into::into))
+    }
+
+    /// Records that a key was used. `last_used_at` is only kept to the minute,
+    /// so busy keys don't cost a write per call.
+    pub async fn touch_api_key(&self, id: Uuid) -> Result<()> {
+        sqlx::query(
+            "UPDATE api_keys SET last_used_at = NOW()
+             WHERE id = $1 AND (last_used_at IS NULL OR last_used_at < NOW() - INTERVAL '1 minute')",
+        )
+        .bind(id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn revoke_api_key(&self, user_id: Uuid, id: Uuid) -> Result<bool> {
+        let result = sqlx::query(
+            "UPDATE api_keys SET revoked_at = NOW()
+             WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL",
+        )
+        .bind(id)
+        .bind(user_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
@@ -1,15 +1,16 @@
 use crate::facilitator_client::{FacilitatorClient, FacilitatorClientError};
-use crate::price::PriceTag;
+use crate::price::{PriceOverride, PriceTag};
 use axum::Json;
 use axum_core::body::Body;
 use axum_core::{
     extract::Request,
     response::{IntoResponse, Response},
 };
-use database::ApiRepository;
+use database::{ApiRepository, WebhookRepository};
 use http::{HeaderMap, HeaderValue, StatusCode, Uri};
 use once_cell::sync::Lazy;
 use serde_json::json;
+use shared::{Api, WebhookEvent};
 use solana_sdk::pubkey::Pubkey;
 use std::collections::HashSet;
 use std::fmt::{Debug, Display};
@@ -45,6 +46,7 @@ pub struct X402Middleware<F> {
     payment_offers: Arc<PaymentOffers>,
     api_id: Option<String>,
     api_repo: Option<ApiRepository>,
+    webhook_repo: Option<WebhookRepository>,
 }
 
 impl TryFrom<&str> for X402Middleware<FacilitatorClient> {
@@ -80,6 +82,7 @@ where
             payment_offers: Arc::new(PaymentOffers::Ready(Arc::new(Vec::new()))),
             api_id: None,
             api_repo: None,
+            webhook_repo: None,
         }
     }
 
@@ -157,6 +160,12 @@ where
         this
     }
 
+    pub fn with_webhook_repo(&self, webhook_repo: WebhookRepository) -> Self {
+        let mut this = self.clone();
+        this.webhook_repo = Some(webhook_repo);
+        this
+    }
+
     fn recompute_offers(mut self) -> Self {
         let base_url = self.base_url();
         let description = self.description.clone().unwrap_or_default();
@@ -238,6 +247,7 @@ pub struct X402MiddlewareService<F> {
     inner: BoxCloneSyncService<Request, Response, Infallible>,
     api_id: Option<String>,
     api_repo: Option<ApiRepository>,
+    webhook_repo: Option<WebhookRepository>,
 }
 
 impl<S, F> Layer<S> for X402Middleware<F>
@@ -249,13 +259,13 @@ where
     type Service = X402MiddlewareService<F>;
 
     fn layer(&self, inner: S) -> Self::Service {
-        if self.base_url.is_none() && self.resource.is_none() {}
         X402MiddlewareService {
             facilitator: self.facilitator.clone(),
             payment_offers: self.payment_offers.clone(),
             inner: BoxCloneSyncService::new(inner),
             api_id: self.api_id.clone(),
             api_repo: self.api_repo.clone(),
+            webhook_repo: self.webhook_repo.clone(),
         }
     }
 }
@@ -280,6 +290,7 @@ where
             payment_requirements,
             api_id: self.api_id.clone(),
             api_repo: self.api_repo.clone(),
+            webhook_repo: self.webhook_repo.clone(),
         };
         let inner = self.inner.clone();
         Box::pin(gate.call(inner, req))
@@ -373,6 +384,7 @@ pub struct X402Paygate<F> {
     pub payment_requirements: Arc<Vec<PaymentRequirements>>,
     pub api_id: Option<String>,
     pub api_repo: Option<ApiRepository>,
+    pub webhook_repo: Option<WebhookRepository>,
 }
 
 impl<F> X402Paygate<F>
@@ -502,6 +514,19 @@ where
         }
     }
 
+    /// Queues `event` for the webhooks of the API owner. Delivery happens in the
+    /// background, so failures here never affect the paid response.
+    async fn notify(&self, api: Option<&Api>, event: WebhookEvent, mut data: serde_json::Value) {
+        let (Some(api), Some(webhook_repo)) = (api, self.webhook_repo.as_ref()) else {
+            return;
+        };
+        data["api_id"] = json!(api.id);
+        data["user_id"] = json!(api.user_id);
+        let _ = webhook_repo
+            .enqueue_event(api.user_id, Some(api.id), event, data)
+            .await;
+    }
+
     pub async fn call<
         ReqBody,
         ResBody,
@@ -535,6 +560,8 @@ where
         S::Response: IntoResponse,
         S::Error: IntoResponse,
     {
+        let mut api: Option<Api> = None;
+        let price_override = req.extensions().get::<PriceOverride>().copied();
         let api_id = req.extensions().get::<String>().cloned();
         if let Some(api_id) = api_id {
             let response = match self
@@ -555,14 +582,17 @@ where
                 .as_ref()
                 .map(|c| c.sol_public_key.clone())
                 .unwrap_or_else(|| "8hAVK73RZdtyP2kE82ohAsAGgKaxffS6pU7B9bxRg2RL".to_string());
-            let amount = TokenAmount::from(
-                response
-                    .0
-                    .payment_config
-                    .as_ref()
-                    .map(|c| (c.cost_per_request * 1_000_000.0) as u64)
-                    .unwrap_or(50000u64),
-            );
+            let amount = match price_override {
+                Some(price) => price.to_token_amount(),
+                None => TokenAmount::from(
+                    response
+                        .0
+                        .payment_config
+                        .as_ref()
+                        .map(|c| (c.cost_per_request * 1_000_000.0) as u64)
+                        .unwrap_or(50000u64),
+                ),
+            };
 
             let updated_requirements = self
                 .payment_requirements
@@ -576,6 +606,14 @@ where
                 .collect::<Vec<_>>();
 
             self.payment_requirements = Arc::new(updated_requirements);
+            api = Some(response.0);
+        }
+
+        if price_override.is_some_and(|price| price.is_free()) {
+            return match inner.call(req).await {
+                Ok(response) => response.into_response(),
+                Err(err) => err.into_response(),
+            };
         }
 
         let payment_payload = match self.extract_payment_payload(req.headers()).await {
@@ -603,7 +641,34 @@ where
         }
         let settlement = match self.settle_payment(&verify_request).await {
             Ok(settlement) => settlement,
-            Err(err) => return err.into_response(),
+            Err(err) => {
+                self.notify(
+                    api.as_ref(),
+                    WebhookEvent::SettlementFailed,
+                    json!({
+                        "amount": verify_request.payment_requirements.max_amount_required,
+                        "network": verify_request.payment_requirements.network,
+                        "error": err.to_string(),
+                    }),
+                )
+                .await;
+                return err.into_response();
+            }
+        };
+        self.notify(
+            api.as_ref(),
+            WebhookEvent::PaymentSettled,
+            json!({
+                "amount": verify_request.payment_requirements.max_amount_required,
+                "network": settlement.network,
+                "payer": settlement.payer,
+                "transaction": settlement.transaction,
+            }),
+        )
+        .await;
+        let receipt = PaymentReceipt {
+            payer: settlement.payer.to_string(),
+            transaction: settlement.transaction.as_ref().map(|tx| tx.to_string()),
         };
         let payment_header: Base64Bytes = match settlement.try_into() {
             Ok(payment_header) => payment_header,
@@ -625,12 +690,21 @@ where
                 .into_response();
             }
         };
-        let mut res = response;
+        let mut res = response.into_response();
         res.headers_mut().insert("X-Payment-Response", header_value);
-        res.into_response()
+        res.extensions_mut().insert(receipt);
+        res
     }
 }
 
+/// Who paid for a request, added to the response extensions once the payment
+/// has been settled.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PaymentReceipt {
+    pub payer: String,
+    pub transaction: Option<String>,
+}
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct PaymentRequirementsNoResource {
     pub scheme: Scheme,
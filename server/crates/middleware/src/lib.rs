@@ -2,5 +2,5 @@ pub mod facilitator_client;
 pub mod layer;
 pub mod price;
 
-pub use layer::X402Middleware;
+pub use layer::{PaymentReceipt, X402Middleware};
 pub use price::*;
\ No newline at end of file
@@ -244,3 +244,19 @@ impl IntoPriceTag for USDCDeployment {
         self.0.pay_to(address)
     }
 }
+
+/// A price for a single request, in USDC, set by a layer in front of the paygate
+/// through a request extension. It replaces the API's `cost_per_request`, and a
+/// price of zero lets the request through without asking for payment.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PriceOverride(pub f64);
+
+impl PriceOverride {
+    pub fn is_free(&self) -> bool {
+        self.0 <= 0.0
+    }
+
+    pub fn to_token_amount(&self) -> TokenAmount {
+        TokenAmount::from((self.0 * 1_000_000.0) as u64)
+    }
+}
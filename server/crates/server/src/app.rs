@@ -1,13 +1,32 @@
+use crate::auth::ClerkAuth;
+use crate::balancer::LoadBalancer;
+use crate::cache::ResponseCache;
+use crate::call_log::{log_call, CallLoggerConfig};
+use crate::error::request_id;
+use crate::graphql::SchemaCache;
 use crate::handlers::*;
-use crate::proxy::proxy_request;
+use crate::health::HealthCheckerConfig;
+use crate::proxy::{
+    prepare_proxy_request, prepare_transparent_request, proxy_request, TransparentPath,
+};
+use crate::rate_limit::{RateLimitBackend, RateLimiter};
+use crate::sandbox::sandbox_request;
+use crate::secrets::SecretKeyring;
+use crate::ssrf::UpstreamPolicy;
+use crate::tls::UpstreamTls;
+use crate::upstream::{UpstreamClients, UpstreamDefaults};
 use axum::{
     extract::{Path, Request},
     middleware::Next,
     response::Response,
-    routing::{delete, get, post, put},
+    routing::{any, delete, get, post, put},
     Router,
 };
-use database::{ApiRepository, UserRepository};
+use database::{
+    ApiKeyRepository, ApiMockRepository, ApiRepository, ApiSecretRepository, ApiTlsRepository,
+    CallLogRepository, HealthCheckRepository, RateLimitRepository, UserRepository,
+    WebhookRepository,
+};
 use middleware::{IntoPriceTag, X402Middleware};
 use sqlx::PgPool;
 use std::env;
@@ -26,9 +45,38 @@ async fn inject_api_id(
     next.run(req).await
 }
 
+async fn inject_proxy_api_id(
+    Path(params): Path<TransparentPath>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    req.extensions_mut().insert(params.api_id.to_string());
+    next.run(req).await
+}
+
 pub fn create_app(pool: PgPool) -> Router {
     let user_repo = UserRepository::new(pool.clone());
-    let api_repo = ApiRepository::new(pool);
+    let api_repo = ApiRepository::new(pool.clone());
+    let webhook_repo = WebhookRepository::new(pool.clone());
+    let secret_repo = ApiSecretRepository::new(pool.clone());
+    let health_repo = HealthCheckRepository::new(pool.clone());
+    let mock_repo = ApiMockRepository::new(pool.clone());
+    let call_log_repo = CallLogRepository::new(pool.clone());
+    let tls_repo = ApiTlsRepository::new(pool.clone());
+    let api_key_repo = ApiKeyRepository::new(pool.clone());
+    let rate_limiter = RateLimiter::new(
+        RateLimitBackend::from_env().expect("Invalid RATE_LIMIT_BACKEND"),
+        RateLimitRepository::new(pool),
+        webhook_repo.clone(),
+    );
+    let health = HealthCheckerConfig::from_env();
+    let balancer = LoadBalancer::new(health_repo.clone(), health.interval, health.stale_after());
+    let secrets = SecretKeyring::from_env().expect("Invalid SECRETS_MASTER_KEYS");
+    let tls = UpstreamTls::new(tls_repo.clone(), secrets.clone());
+    let auth = ClerkAuth::from_env().expect("Invalid CLERK_JWKS_URL");
+    if !auth.is_configured() {
+        tracing::warn!("CLERK_JWKS_URL is not set, so authenticated routes will answer 503");
+    }
 
     let facilitator_url =
         env::var("FACILITATOR_URL").unwrap_or_else(|_| "https://facilitator.x402.rs".to_string());
@@ -43,42 +91,152 @@ pub fn create_app(pool: PgPool) -> Router {
     let usdc_solana = USDCDeployment::by_network(Network::SolanaDevnet)
         .pay_to(address_sol!("8hAVK73RZdtyP2kE82ohAsAGgKaxffS6pU7B9bxRg2RL"));
 
+    let paygate = x402
+        .with_description("Protected API Proxy")
+        .with_mime_type("application/json")
+        .with_price_tag(usdc_solana.amount(0).unwrap())
+        .with_api_repo(api_repo.clone())
+        .with_webhook_repo(webhook_repo.clone());
+
+    let state = AppState {
+        user_repo,
+        api_repo,
+        webhook_repo,
+        secret_repo,
+        health_repo: health_repo.clone(),
+        mock_repo,
+        call_log_repo,
+        tls_repo,
+        api_key_repo,
+        secrets,
+        cache: ResponseCache::from_env(),
+        graphql: SchemaCache::default(),
+        health,
+        balancer,
+        call_logs: CallLoggerConfig::from_env(),
+        rate_limiter,
+        upstream: UpstreamClients::new(
+            UpstreamDefaults::from_env(),
+            UpstreamPolicy::from_env().expect("Invalid UPSTREAM_ALLOWLIST"),
+        ),
+        tls,
+        auth,
+        facilitator_url,
+        base_url,
+    };
+
     Router::new()
-        .route("/users", post(create_user))
         .route("/users/clerk", post(create_user_with_clerk_id))
         .route("/users/clerk/{clerk_id}", get(get_user_by_clerk_id))
         .route("/users/{user_id}", get(get_user))
         .route("/apis", get(list_all_apis))
+        .route("/apis/{api_id}/status", get(get_api_status))
         .route("/users/{user_id}/apis", post(create_api))
         .route("/users/{user_id}/apis", get(list_user_apis))
         .route("/users/{user_id}/apis/{api_id}", get(get_api))
         .route("/users/{user_id}/apis/{api_id}", put(update_api))
         .route("/users/{user_id}/apis/{api_id}", delete(delete_api))
+        .route("/users/{user_id}/apis/{api_id}/secrets", post(put_api_secret))
+        .route("/users/{user_id}/apis/{api_id}/secrets", get(list_api_secrets))
+        .route(
+            "/users/{user_id}/apis/{api_id}/secrets/{secret_id}",
+            delete(delete_api_secret),
+        )
+        .route("/users/{user_id}/apis/{api_id}/mocks", post(put_api_mock))
+        .route("/users/{user_id}/apis/{api_id}/mocks", get(list_api_mocks))
+        .route(
+            "/users/{user_id}/apis/{api_id}/mocks/{mock_id}",
+            delete(delete_api_mock),
+        )
+        .route("/users/{user_id}/apis/{api_id}/tls", put(put_api_tls))
+        .route("/users/{user_id}/apis/{api_id}/tls", get(get_api_tls))
+        .route("/users/{user_id}/apis/{api_id}/tls", delete(delete_api_tls))
+        .route("/users/{user_id}/apis/{api_id}/calls", get(list_call_logs))
+        .route("/users/{user_id}/api-keys", post(create_api_key))
+        .route("/users/{user_id}/api-keys", get(list_api_keys))
+        .route(
+            "/users/{user_id}/api-keys/{key_id}",
+            delete(revoke_api_key),
+        )
+        .route("/users/{user_id}/webhooks", post(create_webhook))
+        .route("/users/{user_id}/webhooks", get(list_webhooks))
+        .route("/users/{user_id}/webhooks/{webhook_id}", delete(delete_webhook))
+        .route(
+            "/users/{user_id}/webhooks/{webhook_id}/deliveries",
+            get(list_webhook_deliveries),
+        )
         .route(
             "/users/{user_id}/apis/{api_id}",
             post(proxy_request)
-                .layer(
-                    x402.with_description("Protected API Proxy")
-                        .with_mime_type("application/json")
-                        .with_price_tag(usdc_solana.amount(0).unwrap())
-                        .with_api_repo(api_repo.clone()),
-                )
-                .layer(axum::middleware::from_fn(inject_api_id)),
+                .layer(paygate.clone())
+                .layer(axum::middleware::from_fn_with_state(
+                    state.clone(),
+                    prepare_proxy_request,
+                ))
+                .layer(axum::middleware::from_fn(inject_api_id))
+                .layer(axum::middleware::from_fn_with_state(
+                    state.clone(),
+                    log_call,
+                )),
+        )
+        .route(
+            "/proxy/{api_id}",
+            any(proxy_request)
+                .layer(paygate.clone())
+                .layer(axum::middleware::from_fn_with_state(
+                    state.clone(),
+                    prepare_transparent_request,
+                ))
+                .layer(axum::middleware::from_fn(inject_proxy_api_id))
+                .layer(axum::middleware::from_fn_with_state(
+                    state.clone(),
+                    log_call,
+                )),
+        )
+        .route(
+            "/proxy/{api_id}/{*path}",
+            any(proxy_request)
+                .layer(paygate)
+                .layer(axum::middleware::from_fn_with_state(
+                    state.clone(),
+                    prepare_transparent_request,
+                ))
+                .layer(axum::middleware::from_fn(inject_proxy_api_id))
+                .layer(axum::middleware::from_fn_with_state(
+                    state.clone(),
+                    log_call,
+                )),
         )
+        // Sandbox calls are free, so they skip the paygate.
+        .route("/sandbox/{api_id}", any(sandbox_request))
+        .route("/sandbox/{api_id}/{*path}", any(sandbox_request))
         .layer(CorsLayer::permissive())
         .layer(TraceLayer::new_for_http())
-        .with_state(AppState {
-            user_repo,
-            api_repo,
-            facilitator_url,
-            base_url,
-        })
+        .layer(axum::middleware::from_fn(request_id))
+        .with_state(state)
 }
 
 #[derive(Clone)]
 pub struct AppState {
     pub user_repo: UserRepository,
     pub api_repo: ApiRepository,
+    pub webhook_repo: WebhookRepository,
+    pub secret_repo: ApiSecretRepository,
+    pub health_repo: HealthCheckRepository,
+    pub mock_repo: ApiMockRepository,
+    pub call_log_repo: CallLogRepository,
+    pub tls_repo: ApiTlsRepository,
+    pub api_key_repo: ApiKeyRepository,
+    pub secrets: Option<SecretKeyring>,
+    pub cache: ResponseCache,
+    pub graphql: SchemaCache,
+    pub health: HealthCheckerConfig,
+    pub balancer: LoadBalancer,
+    pub call_logs: CallLoggerConfig,
+    pub rate_limiter: RateLimiter,
+    pub upstream: UpstreamClients,
+    pub tls: UpstreamTls,
+    pub auth: ClerkAuth,
     pub facilitator_url: String,
     pub base_url: Url,
 }
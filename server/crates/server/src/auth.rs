@@ -0,0 +1,430 @@
+use crate::app::AppState;
+use crate::error::AppError;
+use anyhow::{Context, Result};
+use axum::extract::FromRequestParts;
+use axum::http::{header, request::Parts};
+use database::user_id_for_clerk_id;
+use jsonwebtoken::jwk::{Jwk, JwkSet};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use shared::ApiKeyScope;
+use std::env;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use url::Url;
+use uuid::Uuid;
+
+/// How often a token signed with an unknown key may trigger a JWKS refetch, so
+/// forged tokens can't make us hammer Clerk.
+const MIN_REFETCH_INTERVAL: Duration = Duration::from_secs(30);
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Verifies Clerk session tokens against the signing keys published at
+/// `CLERK_JWKS_URL`. The keys are cached for `CLERK_JWKS_CACHE_SECS` (3600) and
+/// refetched early when a token names a key that isn't cached, as happens after
+/// Clerk rotates them.
+#[derive(Clone, Debug)]
+pub struct ClerkAuth {
+    config: Option<Arc<ClerkConfig>>,
+    jwks: Arc<RwLock<Option<CachedJwks>>>,
+    client: reqwest::Client,
+}
+
+#[derive(Debug)]
+struct ClerkConfig {
+    jwks_url: Url,
+    issuer: Option<String>,
+    authorized_parties: Vec<String>,
+    cache_ttl: Duration,
+}
+
+#[derive(Debug)]
+struct CachedJwks {
+    fetched_at: Instant,
+    keys: JwkSet,
+}
+
+#[derive(Debug, Deserialize)]
+struct SessionClaims {
+    sub: String,
+    azp: Option<String>,
+    /// Only present when the Clerk session token is customized to carry the
+    /// user's primary email address.
+    email: Option<String>,
+}
+
+impl ClerkAuth {
+    /// Reads `CLERK_JWKS_URL`, plus the optional `CLERK_ISSUER` and
+    /// `CLERK_AUTHORIZED_PARTIES` (the frontend origins tokens may be issued
+    /// for). Without a JWKS URL every authenticated route answers 503.
+    pub fn from_env() -> Result<Self> {
+        let config = match env::var("CLERK_JWKS_URL") {
+            Ok(jwks_url) => {
+                let cache_secs = env::var("CLERK_JWKS_CACHE_SECS")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(3600);
+                Some(Arc::new(ClerkConfig {
+                    jwks_url: Url::parse(&jwks_url).context("CLERK_JWKS_URL is not a URL")?,
+                    issuer: env::var("CLERK_ISSUER").ok().filter(|v| !v.is_empty()),
+                    authorized_parties: env::var("CLERK_AUTHORIZED_PARTIES")
+                        .unwrap_or_default()
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|v| !v.is_empty())
+                        .map(str::to_string)
+                        .collect(),
+                    cache_ttl: Duration::from_secs(cache_secs),
+                }))
+            }
+            Err(_) => None,
+        };
+        Ok(Self {
+            config,
+            jwks: Arc::default(),
+            client: reqwest::Client::new(),
+        })
+    }
+
+    pub fn is_configured(&self) -> bool {
+        self.config.is_some()
+    }
+
+    /// Checks a session token's signature, lifetime, issuer and authorized
+    /// party, and returns the Clerk user id it was issued to and the email
+    /// address in its `email` claim.
+    pub async fn verify(&self, token: &str) -> Result<(String, Option<String>), AppError> {
+        let Some(config) = self.config.as_deref() else {
+            return Err(AppError::service_unavailable(
+                "Authentication is not configured on this server",
+            ));
+        };
+
+        let header = jsonwebtoken::decode_header(token)
+            .map_err(|e| AppError::unauthorized("The session token is malformed").with_source(e))?;
+        let kid = header
+            .kid
+            .ok_or_else(|| AppError::unauthorized("The session token names no signing key"))?;
+        let key = self.key(config, &kid).await?;
+
+        let mut validation = Validation::new(Algorithm::RS256);
+        validation.set_required_spec_claims(&["exp", "nbf", "sub"]);
+        if let Some(issuer) = &config.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        let claims = jsonwebtoken::decode::<SessionClaims>(token, &key, &validation)
+            .map_err(|e| {
+                AppError::unauthorized("The session token is invalid or has expired").with_source(e)
+            })?
+            .claims;
+
+        let authorized = config.authorized_parties.is_empty()
+            || claims
+                .azp
+                .as_ref()
+                .is_some_and(|azp| config.authorized_parties.contains(azp));
+        if !authorized {
+            return Err(AppError::unauthorized(
+                "The session token was issued for another origin",
+            ));
+        }
+        Ok((claims.sub, claims.email))
+    }
+
+    /// The key `kid` from the cached JWKS, refetching it when it is stale or
+    /// doesn't have the key.
+    async fn key(&self, config: &ClerkConfig, kid: &str) -> Result<DecodingKey, AppError> {
+        let (cached, refetch) = {
+            let jwks = self.jwks.read().expect("JWKS cache poisoned");
+            match jwks.as_ref() {
+                Some(jwks) => {
+                    let age = jwks.fetched_at.elapsed();
+                    let jwk = jwks.keys.find(kid).cloned();
+                    let refetch =
+                        age >= config.cache_ttl || (jwk.is_none() && age >= MIN_REFETCH_INTERVAL);
+                    (jwk, refetch)
+                }
+                None => (None, true),
+            }
+        };
+
+        let jwk = if refetch {
+            match self.fetch(config).await {
+                Ok(keys) => {
+                    let jwk = keys.find(kid).cloned();
+                    *self.jwks.write().expect("JWKS cache poisoned") = Some(CachedJwks {
+                        fetched_at: Instant::now(),
+                        keys,
+                    });
+                    jwk
+                }
+                // Keep trusting the keys we have while Clerk is unreachable.
+                Err(e) if cached.is_some() => {
+                    tracing::warn!("Failed to refresh the Clerk JWKS: {e:#}");
+                    cached
+                }
+                Err(e) => {
+                    return Err(AppError::service_unavailable(
+                        "Session tokens can't be verified right now",
+                    )
+                    .with_source(e))
+                }
+            }
+        } else {
+            cached
+        };
+
+        let jwk = jwk.ok_or_else(|| {
+            AppError::unauthorized("The session token is signed with an unknown key")
+        })?;
+        decoding_key(&jwk)
+    }
+
+    async fn fetch(&self, config: &ClerkConfig) -> Result<JwkSet> {
+        let keys = self
+            .client
+            .get(config.jwks_url.clone())
+            .timeout(FETCH_TIMEOUT)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .context("The JWKS is not valid JSON")?;
+        Ok(keys)
+    }
+}
+
+fn decoding_key(jwk: &Jwk) -> Result<DecodingKey, AppError> {
+    DecodingKey::from_jwk(jwk).map_err(|e| {
+        AppError::unauthorized("The session token is signed with an unusable key").with_source(e)
+    })
+}
+
+/// Provider API keys start with this, which is how they are told apart from
+/// session tokens.
+pub const API_KEY_PREFIX: &str = "enigma_sk_";
+/// How much of a key is stored in the clear, to tell keys apart in listings.
+const API_KEY_DISPLAY_LEN: usize = API_KEY_PREFIX.len() + 6;
+
+/// A new random provider API key.
+pub fn generate_api_key() -> String {
+    format!(
+        "{}{}{}",
+        API_KEY_PREFIX,
+        Uuid::new_v4().simple(),
+        Uuid::new_v4().simple()
+    )
+}
+
+/// The part of `key` that is shown in listings.
+pub fn api_key_prefix(key: &str) -> &str {
+    &key[..API_KEY_DISPLAY_LEN.min(key.len())]
+}
+
+/// The hex SHA-256 of `key`. Only this is stored, so a database leak doesn't
+/// leak usable keys.
+pub fn hash_api_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+/// How the caller of a management route proved who they are.
+#[derive(Clone, Debug)]
+pub enum Principal {
+    /// A Clerk session, which may do anything on its user's account.
+    Session {
+        clerk_id: String,
+        email: Option<String>,
+    },
+    /// A provider API key, limited to its scopes.
+    ApiKey {
+        key_id: Uuid,
+        scopes: Vec<ApiKeyScope>,
+    },
+}
+
+/// The caller of a management route, authenticated with a Clerk session token
+/// or a provider API key in `Authorization: Bearer`.
+#[derive(Clone, Debug)]
+pub struct AuthUser {
+    pub user_id: Uuid,
+    pub principal: Principal,
+}
+
+impl AuthUser {
+    /// Allows the request only when it acts on the caller's own account.
+    pub fn require_owner(&self, user_id: Uuid) -> Result<(), AppError> {
+        if self.user_id == user_id {
+            Ok(())
+        } else {
+            Err(AppError::forbidden("You can only manage your own account"))
+        }
+    }
+
+    /// Allows the request when it acts on the caller's own account and, for
+    /// API keys, the key has `scope`.
+    pub fn authorize(&self, user_id: Uuid, scope: ApiKeyScope) -> Result<(), AppError> {
+        self.require_owner(user_id)?;
+        match &self.principal {
+            Principal::Session { .. } => Ok(()),
+            Principal::ApiKey { scopes, .. } if scopes.contains(&scope) => Ok(()),
+            Principal::ApiKey { .. } => Err(AppError::forbidden(format!(
+                "This API key lacks the {} scope",
+                scope.as_str()
+            ))),
+        }
+    }
+
+    /// Allows the request only with a session token issued to the Clerk user
+    /// `clerk_id`, for routes that look users up by their Clerk id.
+    pub fn require_clerk_user(&self, clerk_id: &str) -> Result<(), AppError> {
+        match &self.principal {
+            Principal::Session {
+                clerk_id: session, ..
+            } if session == clerk_id => Ok(()),
+            Principal::Session { .. } => {
+                Err(AppError::forbidden("You can only manage your own account"))
+            }
+            Principal::ApiKey { .. } => Err(AppError::forbidden(
+                "Users can only be set up with a session",
+            )),
+        }
+    }
+
+    /// The email address Clerk vouches for in the caller's session token.
+    pub fn session_email(&self) -> Result<&str, AppError> {
+        match &self.principal {
+            Principal::Session {
+                email: Some(email), ..
+            } => Ok(email),
+            Principal::Session { email: None, .. } => Err(AppError::forbidden(
+                "The session token carries no email address",
+            )),
+            Principal::ApiKey { .. } => Err(AppError::forbidden(
+                "Users can only be set up with a session",
+            )),
+        }
+    }
+
+    /// Allows the request only with a Clerk session for the caller's own
+    /// account, so API keys can't be used to mint or revoke keys.
+    pub fn require_session(&self, user_id: Uuid) -> Result<(), AppError> {
+        self.require_owner(user_id)?;
+        match self.principal {
+            Principal::Session { .. } => Ok(()),
+            Principal::ApiKey { .. } => Err(AppError::forbidden(
+                "API keys can only be managed with a session",
+            )),
+        }
+    }
+}
+
+impl FromRequestParts<AppState> for AuthUser {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, AppError> {
+        let token = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::trim)
+            .filter(|token| !token.is_empty())
+            .ok_or_else(|| {
+                AppError::unauthorized("A bearer session token or API key is required")
+            })?;
+
+        if token.starts_with(API_KEY_PREFIX) {
+            let key = state
+                .api_key_repo
+                .get_active_api_key_by_hash(&hash_api_key(token))
+                .await?
+                .ok_or_else(|| AppError::unauthorized("The API key is invalid or was revoked"))?;
+            if let Err(e) = state.api_key_repo.touch_api_key(key.id).await {
+                tracing::warn!("Failed to record the use of API key {}: {e:#}", key.id);
+            }
+            return Ok(Self {
+                user_id: key.user_id,
+                principal: Principal::ApiKey {
+                    key_id: key.id,
+                    scopes: key.scopes,
+                },
+            });
+        }
+
+        let (clerk_id, email) = state.auth.verify(token).await?;
+        Ok(Self {
+            user_id: user_id_for_clerk_id(&clerk_id),
+            principal: Principal::Session { clerk_id, email },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(clerk_id: &str) -> AuthUser {
+        AuthUser {
+            user_id: user_id_for_clerk_id(clerk_id),
+            principal: Principal::Session {
+                clerk_id: clerk_id.to_string(),
+                email: Some(format!("{}@example.com", clerk_id)),
+            },
+        }
+    }
+
+    #[test]
+    fn clerk_routes_need_the_users_own_session() {
+        assert!(session("user_a").require_clerk_user("user_a").is_ok());
+        assert!(session("user_b").require_clerk_user("user_a").is_err());
+
+        let key = AuthUser {
+            user_id: user_id_for_clerk_id("user_a"),
+            principal: Principal::ApiKey {
+                key_id: Uuid::new_v4(),
+                scopes: vec![ApiKeyScope::ApisWrite],
+            },
+        };
+        assert!(key.require_clerk_user("user_a").is_err());
+        assert!(key.authorize(key.user_id, ApiKeyScope::ApisWrite).is_ok());
+        assert!(key.authorize(key.user_id, ApiKeyScope::ApisRead).is_err());
+        assert!(key.require_session(key.user_id).is_err());
+    }
+
+    #[test]
+    fn emails_come_from_the_session_token() {
+        assert_eq!(
+            session("user_a").session_email().unwrap(),
+            "user_a@example.com"
+        );
+
+        let without_email = AuthUser {
+            principal: Principal::Session {
+                clerk_id: "user_a".to_string(),
+                email: None,
+            },
+            ..session("user_a")
+        };
+        assert!(without_email.session_email().is_err());
+
+        let key = AuthUser {
+            user_id: user_id_for_clerk_id("user_a"),
+            principal: Principal::ApiKey {
+                key_id: Uuid::new_v4(),
+                scopes: vec![ApiKeyScope::ApisWrite],
+            },
+        };
+        assert!(key.session_email().is_err());
+    }
+
+    #[test]
+    fn api_keys_are_prefixed_and_hashed() {
+        let key = generate_api_key();
+        assert!(key.starts_with(API_KEY_PREFIX));
+        assert_eq!(api_key_prefix(&key).len(), API_KEY_PREFIX.len() + 6);
+        assert_eq!(hash_api_key(&key), hash_api_key(&key));
+        assert_ne!(hash_api_key(&key), hash_api_key(&generate_api_key()));
+    }
+}
@@ -0,0 +1,378 @@
+use crate::upstream::UpstreamClients;
+use database::HealthCheckRepository;
+use shared::{Api, BalancingStrategy, LoadBalancingConfig, ProxyConfig, UpstreamTarget};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use url::Url;
+use uuid::Uuid;
+
+/// Weight of the newest sample in a target's moving average latency.
+const LATENCY_SMOOTHING: f64 = 0.3;
+
+/// Picks the upstream target for calls to load balanced APIs.
+///
+/// Targets are taken out of rotation while their origin's circuit breaker is
+/// open or their latest health check failed. The failed checks are loaded from
+/// the health checker's results and reused for `refresh_interval`.
+#[derive(Clone, Debug)]
+pub struct LoadBalancer {
+    health_repo: HealthCheckRepository,
+    refresh_interval: Duration,
+    stale_after: Duration,
+    state: Arc<Mutex<BalancerState>>,
+}
+
+#[derive(Debug, Default)]
+struct BalancerState {
+    turns: HashMap<Uuid, u64>,
+    latencies: HashMap<(Uuid, String), f64>,
+    unhealthy: HashSet<(Uuid, String)>,
+    loaded_at: Option<Instant>,
+}
+
+impl LoadBalancer {
+    /// `stale_after` is how old a failed health check may be and still eject
+    /// its target.
+    pub fn new(
+        health_repo: HealthCheckRepository,
+        refresh_interval: Duration,
+        stale_after: Duration,
+    ) -> Self {
+        Self {
+            health_repo,
+            refresh_interval,
+            stale_after,
+            state: Arc::new(Mutex::new(BalancerState::default())),
+        }
+    }
+
+    /// The base URL to use for one call to `api`, or `None` when the API isn't
+    /// load balanced. If every target is out of rotation, all of them are
+    /// considered again so the call fails the way it would for a single target.
+    pub async fn pick(&self, api: &Api, upstream: &UpstreamClients) -> Option<String> {
+        let config = config(api.proxy_config.as_ref())?;
+        self.refresh().await;
+
+        let settings = upstream.settings(api.proxy_config.as_ref());
+        let breakers = upstream.breakers();
+        let mut state = self.state.lock().expect("load balancer poisoned");
+        let in_rotation: Vec<&UpstreamTarget> = config
+            .targets
+            .iter()
+            .filter(|target| !state.unhealthy.contains(&(api.id, target.url.clone())))
+            .filter(|target| {
+                Url::parse(&target.url).is_ok_and(|url| {
+                    let origin = url.origin().ascii_serialization();
+                    breakers
+                        .retry_after(api.id, &origin, &settings.breaker)
+                        .is_none()
+                })
+            })
+            .collect();
+        let candidates = if in_rotation.is_empty() {
+            config.targets.iter().collect()
+        } else {
+            in_rotation
+        };
+
+        let target = match config.strategy {
+            BalancingStrategy::Failover => candidates[0],
+            BalancingStrategy::RoundRobin => {
+                let total: u64 = candidates.iter().map(|target| weight(target)).sum();
+                let turn = state.turns.entry(api.id).or_default();
+                let mut slot = *turn % total;
+                *turn = turn.wrapping_add(1);
+                candidates
+                    .iter()
+                    .find(|target| {
+                        let found = slot < weight(target);
+                        slot = slot.saturating_sub(weight(target));
+                        found
+                    })
+                    .copied()
+                    .unwrap_or(candidates[0])
+            }
+            // Targets without a sample yet score zero, so each gets tried.
+            BalancingStrategy::LeastLatency => candidates
+                .iter()
+                .min_by(|a, b| {
+                    let score = |target: &UpstreamTarget| {
+                        let latency = state.latencies.get(&(api.id, target.url.clone()));
+                        latency.copied().unwrap_or(0.0) / weight(target) as f64
+                    };
+                    score(a).total_cmp(&score(b))
+                })
+                .copied()
+                .unwrap_or(candidates[0]),
+        };
+        Some(target.url.clone())
+    }
+
+    /// Records how long `api`'s current target took to answer. Only kept for
+    /// APIs balanced by latency.
+    pub fn record_latency(&self, api: &Api, latency: Duration) {
+        let Some(config) = config(api.proxy_config.as_ref()) else {
+            return;
+        };
+        if config.strategy != BalancingStrategy::LeastLatency {
+            return;
+        }
+
+        let sample = latency.as_secs_f64() * 1000.0;
+        let mut state = self.state.lock().expect("load balancer poisoned");
+        state
+            .latencies
+            .entry((api.id, api.base_url.clone()))
+            .and_modify(|average| {
+                *average = LATENCY_SMOOTHING * sample + (1.0 - LATENCY_SMOOTHING) * *average
+            })
+            .or_insert(sample);
+    }
+
+    async fn refresh(&self) {
+        {
+            let state = self.state.lock().expect("load balancer poisoned");
+            if state
+                .loaded_at
+                .is_some_and(|loaded_at| loaded_at.elapsed() < self.refresh_interval)
+            {
+                return;
+            }
+        }
+
+        let since = chrono::Utc::now()
+            - chrono::Duration::from_std(self.stale_after).unwrap_or(chrono::Duration::MAX);
+        let unhealthy = match self.health_repo.get_unhealthy_targets(since).await {
+            Ok(targets) => Some(targets.into_iter().collect()),
+            Err(e) => {
+                tracing::warn!("Failed to load unhealthy upstream targets: {e:#}");
+                None
+            }
+        };
+
+        let mut state = self.state.lock().expect("load balancer poisoned");
+        if let Some(unhealthy) = unhealthy {
+            state.unhealthy = unhealthy;
+        }
+        state.loaded_at = Some(Instant::now());
+    }
+}
+
+fn weight(target: &UpstreamTarget) -> u64 {
+    u64::from(target.weight.max(1))
+}
+
+/// The API's load balancing config, if it has any targets.
+pub fn config(config: Option<&ProxyConfig>) -> Option<&LoadBalancingConfig> {
+    config?
+        .load_balancing
+        .as_ref()
+        .filter(|balancing| !balancing.targets.is_empty())
+}
+
+/// Checks an API's load balancing config before it is saved. The targets'
+/// hosts are checked against the upstream policy separately.
+pub fn validate(config: Option<&ProxyConfig>) -> Result<(), String> {
+    let Some(balancing) = config.and_then(|config| config.load_balancing.as_ref()) else {
+        return Ok(());
+    };
+    if balancing.targets.is_empty() {
+        return Err("load_balancing needs at least one target".to_string());
+    }
+    for target in &balancing.targets {
+        if target.weight == 0 {
+            return Err(format!("{} has a weight of 0", target.url));
+        }
+        match Url::parse(&target.url) {
+            Ok(url) if !url.cannot_be_a_base() => {}
+            _ => return Err(format!("{} is not a valid target URL", target.url)),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssrf::UpstreamPolicy;
+    use crate::upstream::UpstreamDefaults;
+    use serde_json::{json, Value};
+    use sqlx::postgres::PgPoolOptions;
+
+    /// A balancer that never loads health checks, with `unhealthy` out of
+    /// rotation.
+    fn balancer(unhealthy: &[(Uuid, &str)]) -> LoadBalancer {
+        let pool = PgPoolOptions::new()
+            .connect_lazy("postgres://localhost/unused")
+            .unwrap();
+        let balancer = LoadBalancer::new(
+            HealthCheckRepository::new(pool),
+            Duration::from_secs(3600),
+            Duration::from_secs(60),
+        );
+        {
+            let mut state = balancer.state.lock().unwrap();
+            state.loaded_at = Some(Instant::now());
+            state.unhealthy = unhealthy
+                .iter()
+                .map(|(api_id, url)| (*api_id, url.to_string()))
+                .collect();
+        }
+        balancer
+    }
+
+    fn api(strategy: &str, targets: Value) -> Api {
+        serde_json::from_value(json!({
+            "id": Uuid::new_v4(),
+            "user_id": Uuid::new_v4(),
+            "name": "Balanced",
+            "description": null,
+            "category": "Data",
+            "kind": "Rest",
+            "base_url": "https://a.test",
+            "endpoints": [],
+            "payment_config": null,
+            "proxy_config": {
+                "load_balancing": { "strategy": strategy, "targets": targets },
+            },
+            "graphql": null,
+            "created_at": chrono::Utc::now(),
+            "updated_at": chrono::Utc::now(),
+        }))
+        .unwrap()
+    }
+
+    fn upstream() -> UpstreamClients {
+        UpstreamClients::new(UpstreamDefaults::from_env(), UpstreamPolicy::default())
+    }
+
+    async fn picks(
+        balancer: &LoadBalancer,
+        api: &Api,
+        upstream: &UpstreamClients,
+        n: usize,
+    ) -> Vec<String> {
+        let mut picked = Vec::new();
+        for _ in 0..n {
+            picked.push(balancer.pick(api, upstream).await.unwrap());
+        }
+        picked
+    }
+
+    #[tokio::test]
+    async fn takes_turns_by_weight() {
+        let api = api(
+            "RoundRobin",
+            json!([{ "url": "https://a.test", "weight": 2 }, { "url": "https://b.test" }]),
+        );
+        let picked = picks(&balancer(&[]), &api, &upstream(), 6).await;
+        assert_eq!(
+            picked,
+            ["https://a.test", "https://a.test", "https://b.test"].repeat(2)
+        );
+    }
+
+    #[tokio::test]
+    async fn skips_unhealthy_targets_unless_all_are() {
+        let api = api(
+            "RoundRobin",
+            json!([{ "url": "https://a.test" }, { "url": "https://b.test" }]),
+        );
+        let upstream = upstream();
+        let picked = picks(&balancer(&[(api.id, "https://a.test")]), &api, &upstream, 3).await;
+        assert_eq!(picked, ["https://b.test"; 3]);
+
+        let all = [(api.id, "https://a.test"), (api.id, "https://b.test")];
+        let picked = picks(&balancer(&all), &api, &upstream, 2).await;
+        assert_eq!(picked, ["https://a.test", "https://b.test"]);
+    }
+
+    #[tokio::test]
+    async fn fails_over_when_a_breaker_opens() {
+        let api = api(
+            "Failover",
+            json!([{ "url": "https://a.test/v1" }, { "url": "https://b.test/v1" }]),
+        );
+        let upstream = upstream();
+        let balancer = balancer(&[]);
+        assert_eq!(
+            picks(&balancer, &api, &upstream, 2).await,
+            ["https://a.test/v1"; 2]
+        );
+
+        let settings = upstream.settings(api.proxy_config.as_ref());
+        for _ in 0..settings.breaker.failure_threshold {
+            upstream
+                .breakers()
+                .record_failure(api.id, "https://a.test", &settings.breaker);
+        }
+        assert_eq!(
+            picks(&balancer, &api, &upstream, 2).await,
+            ["https://b.test/v1"; 2]
+        );
+    }
+
+    #[tokio::test]
+    async fn prefers_the_fastest_target() {
+        let mut api = api(
+            "LeastLatency",
+            json!([{ "url": "https://a.test" }, { "url": "https://b.test", "weight": 2 }]),
+        );
+        let upstream = upstream();
+        let balancer = balancer(&[]);
+
+        api.base_url = "https://a.test".to_string();
+        balancer.record_latency(&api, Duration::from_millis(100));
+        // b is untried, so it is picked first.
+        assert_eq!(
+            picks(&balancer, &api, &upstream, 1).await,
+            ["https://b.test"]
+        );
+
+        api.base_url = "https://b.test".to_string();
+        balancer.record_latency(&api, Duration::from_millis(300));
+        // 100ms against 300ms / weight 2.
+        assert_eq!(
+            picks(&balancer, &api, &upstream, 1).await,
+            ["https://a.test"]
+        );
+
+        balancer.record_latency(&api, Duration::from_millis(100));
+        // b's average is now 0.3 * 100 + 0.7 * 300 = 240ms, 120ms by weight.
+        assert_eq!(
+            picks(&balancer, &api, &upstream, 1).await,
+            ["https://a.test"]
+        );
+    }
+
+    #[tokio::test]
+    async fn ignores_apis_that_arent_balanced() {
+        let mut api = api("RoundRobin", json!([]));
+        assert_eq!(balancer(&[]).pick(&api, &upstream()).await, None);
+        api.proxy_config = None;
+        assert_eq!(balancer(&[]).pick(&api, &upstream()).await, None);
+    }
+
+    #[test]
+    fn validates_targets() {
+        let config = |balancing: Value| ProxyConfig {
+            load_balancing: Some(serde_json::from_value(balancing).unwrap()),
+            ..Default::default()
+        };
+        assert!(validate(Some(&config(
+            json!({ "targets": [{ "url": "https://a.test" }] })
+        )))
+        .is_ok());
+        assert!(validate(Some(&config(json!({ "targets": [] })))).is_err());
+        assert!(validate(Some(&config(
+            json!({ "targets": [{ "url": "https://a.test", "weight": 0 }] })
+        )))
+        .is_err());
+        assert!(validate(Some(&config(
+            json!({ "targets": [{ "url": "mailto:a@b.test" }] })
+        )))
+        .is_err());
+        assert!(validate(None).is_ok());
+    }
+}
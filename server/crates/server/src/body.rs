@@ -0,0 +1,197 @@
+use axum::body::Bytes;
+use axum::http::HeaderValue;
+use base64::Engine;
+use serde_json::Value;
+use shared::{BodyEncoding, MultipartField};
+use uuid::Uuid;
+
+/// An upstream request body built from a `ProxyRequest`.
+#[derive(Debug)]
+pub struct EncodedBody {
+    pub bytes: Bytes,
+    /// The content type the encoding implies; unset when there is no body.
+    pub content_type: Option<HeaderValue>,
+}
+
+/// Encodes a `ProxyRequest` body as its `body_encoding` describes. Errors say
+/// what is wrong with the body and are meant for the caller.
+pub fn encode(body: Option<&Value>, encoding: BodyEncoding) -> Result<EncodedBody, String> {
+    let Some(body) = body else {
+        return Ok(EncodedBody {
+            bytes: Bytes::new(),
+            content_type: None,
+        });
+    };
+    let (bytes, content_type) = match encoding {
+        BodyEncoding::Json => (
+            serde_json::to_vec(body).map_err(|e| format!("body: {}", e))?,
+            HeaderValue::from_static("application/json"),
+        ),
+        BodyEncoding::Text => (
+            body.as_str()
+                .ok_or("body must be a string for the Text encoding")?
+                .as_bytes()
+                .to_vec(),
+            HeaderValue::from_static("text/plain; charset=utf-8"),
+        ),
+        BodyEncoding::Base64 => (
+            decode(
+                body.as_str()
+                    .ok_or("body must be a string for the Base64 encoding")?,
+            )
+            .ok_or("body is not valid base64")?,
+            HeaderValue::from_static("application/octet-stream"),
+        ),
+        BodyEncoding::Multipart => {
+            let fields: Vec<MultipartField> = serde_json::from_value(body.clone())
+                .map_err(|e| format!("body must be a list of multipart fields ({})", e))?;
+            return multipart(&fields);
+        }
+    };
+    Ok(EncodedBody {
+        bytes: Bytes::from(bytes),
+        content_type: Some(content_type),
+    })
+}
+
+fn decode(value: &str) -> Option<Vec<u8>> {
+    base64::engine::general_purpose::STANDARD.decode(value).ok()
+}
+
+/// Builds a multipart/form-data body under a fresh boundary.
+fn multipart(fields: &[MultipartField]) -> Result<EncodedBody, String> {
+    let boundary = format!("enigma-{}", Uuid::new_v4().simple());
+    let mut bytes = Vec::new();
+    for field in fields {
+        let value = if field.base64 {
+            decode(&field.value).ok_or_else(|| {
+                format!("multipart field {}: value is not valid base64", field.name)
+            })?
+        } else {
+            field.value.clone().into_bytes()
+        };
+
+        let mut head = format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"{}\"",
+            boundary,
+            escape(&field.name)
+        );
+        if let Some(filename) = &field.filename {
+            head.push_str(&format!("; filename=\"{}\"", escape(filename)));
+        }
+        if let Some(content_type) = &field.content_type {
+            if HeaderValue::try_from(content_type.as_str()).is_err() {
+                return Err(format!(
+                    "multipart field {}: invalid content_type",
+                    field.name
+                ));
+            }
+            head.push_str(&format!("\r\nContent-Type: {}", content_type));
+        }
+        head.push_str("\r\n\r\n");
+
+        bytes.extend_from_slice(head.as_bytes());
+        bytes.extend_from_slice(&value);
+        bytes.extend_from_slice(b"\r\n");
+    }
+    bytes.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+
+    let content_type = HeaderValue::try_from(format!("multipart/form-data; boundary={}", boundary))
+        .map_err(|e| e.to_string())?;
+    Ok(EncodedBody {
+        bytes: Bytes::from(bytes),
+        content_type: Some(content_type),
+    })
+}
+
+/// Escapes a field name or filename for a `Content-Disposition` header, the
+/// way browsers do.
+fn escape(value: &str) -> String {
+    value
+        .replace('"', "%22")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn multipart_body(fields: Value) -> (String, String) {
+        let encoded = encode(Some(&fields), BodyEncoding::Multipart).unwrap();
+        let content_type = encoded.content_type.unwrap().to_str().unwrap().to_string();
+        let boundary = content_type
+            .strip_prefix("multipart/form-data; boundary=")
+            .unwrap()
+            .to_string();
+        (boundary, String::from_utf8(encoded.bytes.to_vec()).unwrap())
+    }
+
+    #[test]
+    fn encodes_json_text_and_base64() {
+        let json_body = encode(Some(&json!({ "a": 1 })), BodyEncoding::Json).unwrap();
+        assert_eq!(json_body.bytes, r#"{"a":1}"#);
+        assert_eq!(json_body.content_type.unwrap(), "application/json");
+
+        let text = encode(Some(&json!("hello")), BodyEncoding::Text).unwrap();
+        assert_eq!(text.bytes, "hello");
+        assert_eq!(text.content_type.unwrap(), "text/plain; charset=utf-8");
+
+        let binary = encode(Some(&json!("AAEC/w==")), BodyEncoding::Base64).unwrap();
+        assert_eq!(binary.bytes.as_ref(), [0, 1, 2, 255]);
+        assert_eq!(binary.content_type.unwrap(), "application/octet-stream");
+
+        let empty = encode(None, BodyEncoding::Text).unwrap();
+        assert!(empty.bytes.is_empty() && empty.content_type.is_none());
+    }
+
+    #[test]
+    fn refuses_bodies_that_dont_fit_the_encoding() {
+        assert!(encode(Some(&json!({ "a": 1 })), BodyEncoding::Text).is_err());
+        assert!(encode(Some(&json!(1)), BodyEncoding::Base64).is_err());
+        assert!(encode(Some(&json!("not base64!")), BodyEncoding::Base64).is_err());
+        assert!(encode(Some(&json!({ "name": "a" })), BodyEncoding::Multipart).is_err());
+    }
+
+    #[test]
+    fn encodes_multipart_fields() {
+        let (boundary, body) = multipart_body(json!([
+            { "name": "title", "value": "Hello" },
+            {
+                "name": "file",
+                "value": "aGk=",
+                "base64": true,
+                "filename": "hi.txt",
+                "content_type": "text/plain",
+            },
+        ]));
+        assert_eq!(
+            body,
+            format!(
+                "--{b}\r\nContent-Disposition: form-data; name=\"title\"\r\n\r\nHello\r\n\
+                 --{b}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"hi.txt\"\r\n\
+                 Content-Type: text/plain\r\n\r\nhi\r\n\
+                 --{b}--\r\n",
+                b = boundary
+            )
+        );
+    }
+
+    #[test]
+    fn escapes_multipart_names() {
+        let (_, body) = multipart_body(json!([
+            { "name": "a\"b\r\nX-Injected: 1", "value": "v", "filename": "x\".txt" },
+        ]));
+        assert!(body.contains("name=\"a%22b%0D%0AX-Injected: 1\"; filename=\"x%22.txt\""));
+        assert!(!body.contains("\r\nX-Injected"));
+    }
+
+    #[test]
+    fn refuses_bad_multipart_fields() {
+        let bad_base64 = json!([{ "name": "f", "value": "%%%", "base64": true }]);
+        assert!(encode(Some(&bad_base64), BodyEncoding::Multipart).is_err());
+        let bad_type = json!([{ "name": "f", "value": "v", "content_type": "text/plain\r\nX: 1" }]);
+        assert!(encode(Some(&bad_type), BodyEncoding::Multipart).is_err());
+    }
+}
@@ -0,0 +1,226 @@
+use shared::ProxyConfig;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// Upper bounds on an API's `CircuitBreakerConfig`.
+pub const MAX_FAILURE_THRESHOLD: u32 = 1_000;
+pub const MAX_OPEN_SECONDS: u64 = 3_600;
+
+#[derive(Clone, Debug)]
+pub struct BreakerSettings {
+    pub failure_threshold: u32,
+    pub open_duration: Duration,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum BreakerState {
+    Closed {
+        failures: u32,
+    },
+    Open {
+        until: Instant,
+    },
+    /// One probe request is in flight. If it never reports back, for example
+    /// because the caller went away, another probe is allowed after `open_duration`.
+    HalfOpen {
+        since: Instant,
+    },
+}
+
+/// Circuit breakers keyed by API and upstream origin (`scheme://host:port`).
+/// Thresholds are set per API, so one API's failures never cut off another
+/// API that points at the same origin.
+#[derive(Clone, Debug, Default)]
+pub struct CircuitBreakers {
+    states: Arc<Mutex<HashMap<(Uuid, String), BreakerState>>>,
+}
+
+impl CircuitBreakers {
+    /// How long callers should wait before trying `origin` again, or `None` if
+    /// requests may go through. Does not change the breaker's state.
+    pub fn retry_after(
+        &self,
+        api_id: Uuid,
+        origin: &str,
+        settings: &BreakerSettings,
+    ) -> Option<Duration> {
+        let states = self.states.lock().expect("circuit breakers poisoned");
+        let now = Instant::now();
+        match states.get(&(api_id, origin.to_string()))? {
+            BreakerState::Closed { .. } => None,
+            BreakerState::Open { until } => until.checked_duration_since(now),
+            BreakerState::HalfOpen { since } => {
+                (since.elapsed() < settings.open_duration).then_some(Duration::from_secs(1))
+            }
+        }
+    }
+
+    /// Asks to send one request to `origin`. Once the open period is over, the
+    /// first caller is let through as the probe and the rest keep failing fast.
+    pub fn try_acquire(&self, api_id: Uuid, origin: &str, settings: &BreakerSettings) -> bool {
+        let mut states = self.states.lock().expect("circuit breakers poisoned");
+        let now = Instant::now();
+        let Some(state) = states.get_mut(&(api_id, origin.to_string())) else {
+            return true;
+        };
+        match *state {
+            BreakerState::Closed { .. } => true,
+            BreakerState::Open { until } if until > now => false,
+            BreakerState::HalfOpen { since } if since.elapsed() < settings.open_duration => false,
+            BreakerState::Open { .. } | BreakerState::HalfOpen { .. } => {
+                *state = BreakerState::HalfOpen { since: now };
+                true
+            }
+        }
+    }
+
+    pub fn record_success(&self, api_id: Uuid, origin: &str) {
+        let mut states = self.states.lock().expect("circuit breakers poisoned");
+        states.remove(&(api_id, origin.to_string()));
+    }
+
+    /// Counts a failed call, and returns whether it opened the breaker.
+    pub fn record_failure(&self, api_id: Uuid, origin: &str, settings: &BreakerSettings) -> bool {
+        let now = Instant::now();
+        let open = BreakerState::Open {
+            until: now
+                .checked_add(settings.open_duration)
+                .unwrap_or(now + Duration::from_secs(MAX_OPEN_SECONDS)),
+        };
+        let mut states = self.states.lock().expect("circuit breakers poisoned");
+        let state = states
+            .entry((api_id, origin.to_string()))
+            .or_insert(BreakerState::Closed { failures: 0 });
+        let (next, opened) = match *state {
+            BreakerState::Closed { failures }
+                if failures.saturating_add(1) < settings.failure_threshold =>
+            {
+                let failures = failures + 1;
+                (BreakerState::Closed { failures }, false)
+            }
+            BreakerState::Closed { .. } => {
+                tracing::warn!(
+                    "Circuit breaker opened for API {} upstream {}",
+                    api_id,
+                    origin
+                );
+                (open, true)
+            }
+            BreakerState::HalfOpen { .. } => (open, false),
+            BreakerState::Open { until } => (BreakerState::Open { until }, false),
+        };
+        *state = next;
+        opened
+    }
+}
+
+/// Checks an API's circuit breaker overrides before they are saved.
+pub fn validate(config: Option<&ProxyConfig>) -> Result<(), String> {
+    let Some(breaker) = config.and_then(|config| config.circuit_breaker.as_ref()) else {
+        return Ok(());
+    };
+    if breaker
+        .failure_threshold
+        .is_some_and(|threshold| threshold == 0 || threshold > MAX_FAILURE_THRESHOLD)
+    {
+        return Err(format!(
+            "failure_threshold must be between 1 and {}",
+            MAX_FAILURE_THRESHOLD
+        ));
+    }
+    if breaker
+        .open_seconds
+        .is_some_and(|seconds| seconds == 0 || seconds > MAX_OPEN_SECONDS)
+    {
+        return Err(format!(
+            "open_seconds must be between 1 and {}",
+            MAX_OPEN_SECONDS
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn settings(failure_threshold: u32, open_duration: Duration) -> BreakerSettings {
+        BreakerSettings {
+            failure_threshold,
+            open_duration,
+        }
+    }
+
+    #[test]
+    fn opens_after_the_threshold_and_fails_fast() {
+        let breakers = CircuitBreakers::default();
+        let api = Uuid::new_v4();
+        let settings = settings(2, Duration::from_secs(30));
+
+        assert!(!breakers.record_failure(api, "https://a.test", &settings));
+        assert!(breakers
+            .retry_after(api, "https://a.test", &settings)
+            .is_none());
+        assert!(breakers.record_failure(api, "https://a.test", &settings));
+        assert!(breakers
+            .retry_after(api, "https://a.test", &settings)
+            .is_some());
+        // Only the failure that opened the breaker reports it.
+        assert!(!breakers.record_failure(api, "https://a.test", &settings));
+        assert!(!breakers.try_acquire(api, "https://a.test", &settings));
+
+        breakers.record_success(api, "https://a.test");
+        assert!(breakers.try_acquire(api, "https://a.test", &settings));
+    }
+
+    #[test]
+    fn keeps_apis_on_the_same_origin_apart() {
+        let breakers = CircuitBreakers::default();
+        let (tripped, other) = (Uuid::new_v4(), Uuid::new_v4());
+        let settings = settings(1, Duration::from_secs(30));
+
+        breakers.record_failure(tripped, "https://a.test", &settings);
+        assert!(!breakers.try_acquire(tripped, "https://a.test", &settings));
+        assert!(breakers
+            .retry_after(other, "https://a.test", &settings)
+            .is_none());
+        assert!(breakers.try_acquire(other, "https://a.test", &settings));
+    }
+
+    #[test]
+    fn survives_huge_open_durations() {
+        let breakers = CircuitBreakers::default();
+        let api = Uuid::new_v4();
+        let settings = settings(1, Duration::MAX);
+
+        breakers.record_failure(api, "https://a.test", &settings);
+        assert!(breakers
+            .retry_after(api, "https://a.test", &settings)
+            .is_some());
+        breakers.record_failure(api, "https://a.test", &settings);
+        assert!(!breakers.try_acquire(api, "https://a.test", &settings));
+    }
+
+    #[test]
+    fn validates_overrides() {
+        let config = |breaker: serde_json::Value| -> ProxyConfig {
+            serde_json::from_value(json!({ "circuit_breaker": breaker })).unwrap()
+        };
+        assert!(validate(None).is_ok());
+        assert!(validate(Some(&config(
+            json!({ "failure_threshold": 3, "open_seconds": 60 })
+        )))
+        .is_ok());
+        for breaker in [
+            json!({ "failure_threshold": 0 }),
+            json!({ "failure_threshold": MAX_FAILURE_THRESHOLD + 1 }),
+            json!({ "open_seconds": 0 }),
+            json!({ "open_seconds": u64::MAX }),
+        ] {
+            assert!(validate(Some(&config(breaker))).is_err());
+        }
+    }
+}
@@ -0,0 +1,296 @@
+use crate::proxy::UpstreamRequest;
+use axum::body::Bytes;
+use axum::http::{header, HeaderMap, StatusCode};
+use shared::ApiEndpoint;
+use std::collections::HashMap;
+use std::env;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+pub const CACHE_STATUS_HEADER: &str = "X-Cache";
+
+/// Longest `ttl_seconds` an endpoint may configure.
+pub const MAX_TTL_SECONDS: u64 = 86_400;
+
+/// Whether a proxied response came from the cache. `Bypass` is used for
+/// endpoints that don't cache at all.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CacheStatus {
+    Hit,
+    Miss,
+    Bypass,
+}
+
+impl CacheStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CacheStatus::Hit => "HIT",
+            CacheStatus::Miss => "MISS",
+            CacheStatus::Bypass => "BYPASS",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CachedResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+    stored_at: Instant,
+    expires_at: Instant,
+}
+
+impl CachedResponse {
+    pub fn age(&self) -> Duration {
+        self.stored_at.elapsed()
+    }
+}
+
+/// The cache lookup done for a request before the paygate, carried through to
+/// the proxy so a hit is served from the same entry it was priced for.
+#[derive(Clone, Debug)]
+pub struct CachePlan {
+    pub key: String,
+    pub ttl: Duration,
+    pub hit: Option<CachedResponse>,
+}
+
+/// In-memory cache of upstream responses for endpoints that opt in through
+/// `EndpointCache`. Sized with `RESPONSE_CACHE_MAX_ENTRIES` and
+/// `RESPONSE_CACHE_MAX_ENTRY_BYTES`; larger responses are proxied but not stored.
+#[derive(Clone, Debug)]
+pub struct ResponseCache {
+    max_entries: usize,
+    max_entry_bytes: usize,
+    entries: Arc<RwLock<HashMap<String, CachedResponse>>>,
+}
+
+impl ResponseCache {
+    pub fn from_env() -> Self {
+        let size = |name: &str, default: usize| {
+            env::var(name)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default)
+        };
+        Self {
+            max_entries: size("RESPONSE_CACHE_MAX_ENTRIES", 10_000),
+            max_entry_bytes: size("RESPONSE_CACHE_MAX_ENTRY_BYTES", 1024 * 1024),
+            entries: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    pub fn max_entry_bytes(&self) -> usize {
+        self.max_entry_bytes
+    }
+
+    /// Keys an entry by API, method, path, query string and the values of the
+    /// endpoint's `vary_headers`.
+    pub fn key(api_id: Uuid, request: &UpstreamRequest, vary_headers: &[String]) -> String {
+        let mut names: Vec<String> = vary_headers
+            .iter()
+            .map(|name| name.to_ascii_lowercase())
+            .collect();
+        names.sort();
+        names.dedup();
+
+        let mut key = format!(
+            "{}\n{}\n{}\n{}",
+            api_id,
+            request.method,
+            request.path,
+            request.query.as_deref().unwrap_or_default()
+        );
+        for name in names {
+            let values: Vec<&str> = request
+                .headers
+                .get_all(name.as_str())
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .collect();
+            key.push_str(&format!("\n{}={}", name, values.join(",")));
+        }
+        key
+    }
+
+    pub fn get(&self, key: &str) -> Option<CachedResponse> {
+        let entries = self.entries.read().expect("response cache poisoned");
+        entries
+            .get(key)
+            .filter(|entry| entry.expires_at > Instant::now())
+            .cloned()
+    }
+
+    pub fn insert(
+        &self,
+        key: String,
+        status: StatusCode,
+        headers: HeaderMap,
+        body: Bytes,
+        ttl: Duration,
+    ) {
+        if body.len() > self.max_entry_bytes || ttl.is_zero() || self.max_entries == 0 {
+            return;
+        }
+
+        let now = Instant::now();
+        let Some(expires_at) = now.checked_add(ttl) else {
+            return;
+        };
+        let mut entries = self.entries.write().expect("response cache poisoned");
+        if entries.len() >= self.max_entries && !entries.contains_key(&key) {
+            entries.retain(|_, entry| entry.expires_at > now);
+        }
+        if entries.len() >= self.max_entries && !entries.contains_key(&key) {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.expires_at)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(
+            key,
+            CachedResponse {
+                status,
+                headers,
+                body,
+                stored_at: now,
+                expires_at,
+            },
+        );
+    }
+}
+
+/// How long a response may be cached: the endpoint's TTL, shortened by an
+/// upstream `s-maxage` or `max-age`. Responses marked `no-store`, `no-cache` or
+/// `private`, and responses that set cookies, are not cached.
+pub fn cacheable_ttl(headers: &HeaderMap, ttl: Duration) -> Option<Duration> {
+    if headers.contains_key(header::SET_COOKIE) {
+        return None;
+    }
+
+    let mut max_age = None;
+    let mut shared_max_age = None;
+    for directive in headers
+        .get_all(header::CACHE_CONTROL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+    {
+        let directive = directive.trim().to_ascii_lowercase();
+        let (name, value) = match directive.split_once('=') {
+            Some((name, value)) => (name.trim(), Some(value.trim().trim_matches('"'))),
+            None => (directive.as_str(), None),
+        };
+        match name {
+            "no-store" | "no-cache" | "private" => return None,
+            "max-age" => max_age = value.and_then(|v| v.parse::<u64>().ok()),
+            "s-maxage" => shared_max_age = value.and_then(|v| v.parse::<u64>().ok()),
+            _ => {}
+        }
+    }
+
+    let ttl = match shared_max_age.or(max_age) {
+        Some(secs) => ttl.min(Duration::from_secs(secs)),
+        None => ttl,
+    };
+    (!ttl.is_zero()).then_some(ttl)
+}
+
+/// Checks the endpoints' cache settings before they are saved.
+pub fn validate(endpoints: &[ApiEndpoint]) -> Result<(), String> {
+    for endpoint in endpoints {
+        let Some(cache) = &endpoint.cache else {
+            continue;
+        };
+        if cache.ttl_seconds > MAX_TTL_SECONDS {
+            return Err(format!(
+                "{} has a ttl_seconds above {}",
+                endpoint.path, MAX_TTL_SECONDS
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn cache() -> ResponseCache {
+        ResponseCache {
+            max_entries: 2,
+            max_entry_bytes: 16,
+            entries: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    fn insert(cache: &ResponseCache, key: &str, body: &'static str, ttl: Duration) {
+        cache.insert(
+            key.to_string(),
+            StatusCode::OK,
+            HeaderMap::new(),
+            Bytes::from_static(body.as_bytes()),
+            ttl,
+        );
+    }
+
+    #[test]
+    fn stores_responses_until_they_expire() {
+        let cache = cache();
+        insert(&cache, "a", "{}", Duration::from_secs(60));
+        insert(&cache, "b", "{}", Duration::from_nanos(1));
+        insert(
+            &cache,
+            "large",
+            "a body over sixteen bytes",
+            Duration::from_secs(60),
+        );
+
+        assert_eq!(cache.get("a").unwrap().body, "{}");
+        std::thread::sleep(Duration::from_millis(1));
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("large").is_none());
+    }
+
+    #[test]
+    fn evicts_the_entry_closest_to_expiry() {
+        let cache = cache();
+        insert(&cache, "a", "{}", Duration::from_secs(10));
+        insert(&cache, "b", "{}", Duration::from_secs(60));
+        insert(&cache, "c", "{}", Duration::from_secs(60));
+
+        assert!(cache.get("a").is_none());
+        assert!(cache.get("b").is_some());
+        assert!(cache.get("c").is_some());
+    }
+
+    #[test]
+    fn skips_ttls_past_the_clock() {
+        let cache = cache();
+        insert(&cache, "a", "{}", Duration::MAX);
+        assert!(cache.get("a").is_none());
+
+        insert(&cache, "b", "{}", Duration::from_secs(60));
+        assert!(cache.get("b").is_some());
+    }
+
+    #[test]
+    fn validates_ttls() {
+        let endpoint = |ttl_seconds: u64| -> ApiEndpoint {
+            serde_json::from_value(json!({
+                "path": "/items",
+                "method": "GET",
+                "cache": { "ttl_seconds": ttl_seconds },
+            }))
+            .unwrap()
+        };
+        assert!(validate(&[endpoint(60), endpoint(MAX_TTL_SECONDS)]).is_ok());
+        assert!(validate(&[endpoint(60), endpoint(MAX_TTL_SECONDS + 1)]).is_err());
+        assert!(validate(&[endpoint(u64::MAX)]).is_err());
+    }
+}
@@ -0,0 +1,210 @@
+use crate::app::AppState;
+use crate::proxy::{TransparentPath, UpstreamRequest};
+use axum::{
+    body::{Bytes, HttpBody},
+    extract::{Path, Request, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use database::CallLogRepository;
+use middleware::PaymentReceipt;
+use shared::{Api, CallLog, ProxyConfig};
+use std::env;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+/// How often entries past the retention period are deleted.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Clone, Debug)]
+pub struct CallLoggerConfig {
+    pub retention_days: u32,
+    /// Sampled bodies are cut off after this many bytes.
+    pub max_body_bytes: usize,
+}
+
+impl CallLoggerConfig {
+    pub fn from_env() -> Self {
+        let number = |name: &str, default: u64| {
+            env::var(name)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default)
+        };
+        Self {
+            retention_days: number("CALL_LOG_RETENTION_DAYS", 30).max(1) as u32,
+            max_body_bytes: number("CALL_LOG_MAX_BODY_BYTES", 4096) as usize,
+        }
+    }
+}
+
+/// What the proxy learns about a call on its way through, shared between
+/// `log_call` and the layers it wraps through the request extensions.
+#[derive(Clone, Debug)]
+pub struct CallRecord {
+    max_body_bytes: usize,
+    details: Arc<Mutex<CallDetails>>,
+}
+
+#[derive(Debug, Default)]
+struct CallDetails {
+    method: Option<String>,
+    path: Option<String>,
+    endpoint: Option<String>,
+    request_bytes: Option<u64>,
+    upstream_status: Option<StatusCode>,
+    sampled: bool,
+    request_body: Option<Bytes>,
+    response_body: Option<Bytes>,
+}
+
+impl CallRecord {
+    fn new(max_body_bytes: usize) -> Self {
+        Self {
+            max_body_bytes,
+            details: Arc::new(Mutex::new(CallDetails::default())),
+        }
+    }
+
+    fn details(&self) -> std::sync::MutexGuard<'_, CallDetails> {
+        self.details.lock().expect("call record poisoned")
+    }
+
+    /// Notes the upstream request as the caller made it, and decides whether
+    /// the call's bodies are kept.
+    pub fn request(&self, api: &Api, request: &UpstreamRequest) {
+        let rate = sample_rate(api.proxy_config.as_ref());
+        let sampled = rate > 0.0 && rand::random::<f64>() < rate;
+
+        let mut details = self.details();
+        details.method = Some(request.method.to_string());
+        details.path = Some(request.path.clone());
+        details.request_bytes = Some(request.body.len() as u64);
+        details.sampled = sampled;
+        if sampled {
+            let end = request.body.len().min(self.max_body_bytes);
+            details.request_body = Some(request.body.slice(..end));
+        }
+    }
+
+    pub fn endpoint(&self, path: &str) {
+        self.details().endpoint = Some(path.to_string());
+    }
+
+    /// Whether the response body should be handed to `response_body`.
+    pub fn is_sampled(&self) -> bool {
+        self.details().sampled
+    }
+
+    pub fn upstream_status(&self, status: StatusCode) {
+        self.details().upstream_status = Some(status);
+    }
+
+    pub fn response_body(&self, body: &[u8]) {
+        let mut details = self.details();
+        if details.sampled {
+            let end = body.len().min(self.max_body_bytes);
+            details.response_body = Some(Bytes::copy_from_slice(&body[..end]));
+        }
+    }
+}
+
+fn sample_rate(config: Option<&ProxyConfig>) -> f64 {
+    config
+        .and_then(|config| config.call_logs.as_ref())
+        .map(|logs| logs.body_sample_rate)
+        .unwrap_or_default()
+}
+
+/// Checks an API's call log settings before they are saved.
+pub fn validate(config: Option<&ProxyConfig>) -> Result<(), String> {
+    let rate = sample_rate(config);
+    if !(0.0..=1.0).contains(&rate) {
+        return Err(format!("body_sample_rate {} is not between 0 and 1", rate));
+    }
+    Ok(())
+}
+
+/// Adds every call to a proxy route to the API's call log, including calls
+/// refused before payment. Entries are written in the background so logging
+/// never delays or fails a call.
+pub async fn log_call(
+    State(state): State<AppState>,
+    Path(params): Path<TransparentPath>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    let started = Instant::now();
+    let method = req.method().to_string();
+    let path = req.uri().path().to_string();
+    let request_length = content_length(req.headers());
+    let call = CallRecord::new(state.call_logs.max_body_bytes);
+    req.extensions_mut().insert(call.clone());
+
+    let response = next.run(req).await;
+
+    let latency_ms = u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX);
+    let receipt = response.extensions().get::<PaymentReceipt>().cloned();
+    let response_bytes = response
+        .body()
+        .size_hint()
+        .exact()
+        .or_else(|| content_length(response.headers()));
+    let details = std::mem::take(&mut *call.details());
+    let body_text = |body: Option<Bytes>| body.map(|b| String::from_utf8_lossy(&b).into_owned());
+
+    let log = CallLog {
+        id: Uuid::new_v4(),
+        api_id: params.api_id,
+        method: details.method.unwrap_or(method),
+        path: details.path.unwrap_or(path),
+        endpoint: details.endpoint,
+        status: response.status().as_u16(),
+        upstream_status: details.upstream_status.map(|status| status.as_u16()),
+        latency_ms,
+        request_bytes: details.request_bytes.or(request_length),
+        response_bytes,
+        payer: receipt.as_ref().map(|receipt| receipt.payer.clone()),
+        payment_id: receipt.and_then(|receipt| receipt.transaction),
+        request_body: body_text(details.request_body),
+        response_body: body_text(details.response_body),
+        created_at: chrono::Utc::now(),
+    };
+    let call_log_repo = state.call_log_repo.clone();
+    tokio::spawn(async move {
+        if let Err(e) = call_log_repo.insert_call_log(&log).await {
+            tracing::warn!("Failed to log a call to API {}: {e:#}", log.api_id);
+        }
+    });
+
+    response
+}
+
+fn content_length(headers: &HeaderMap) -> Option<u64> {
+    headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+}
+
+/// Deletes call log entries older than the retention period once an hour.
+pub fn spawn_call_log_pruner(
+    call_log_repo: CallLogRepository,
+    config: CallLoggerConfig,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+        loop {
+            interval.tick().await;
+            let cutoff = chrono::Utc::now() - chrono::Duration::days(config.retention_days.into());
+            match call_log_repo.prune_call_logs(cutoff).await {
+                Ok(0) => {}
+                Ok(pruned) => tracing::info!("Pruned {} call log entries", pruned),
+                Err(e) => tracing::warn!("Failed to prune call logs: {e:#}"),
+            }
+        }
+    })
+}
@@ -0,0 +1,399 @@
+use crate::proxy::UpstreamRequest;
+use axum::{
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Json, Response},
+};
+use serde_json::{Map, Number, Value};
+use shared::{Api, ApiEndpoint};
+
+/// Why a proxied request was refused before it reached the paygate.
+#[derive(Debug)]
+pub struct EndpointRejection {
+    status: StatusCode,
+    error: String,
+    details: Vec<String>,
+}
+
+impl EndpointRejection {
+    pub fn new(status: StatusCode, error: impl Into<String>) -> Self {
+        Self {
+            status,
+            error: error.into(),
+            details: Vec::new(),
+        }
+    }
+
+    pub fn with_details(mut self, details: Vec<String>) -> Self {
+        self.details = details;
+        self
+    }
+}
+
+impl IntoResponse for EndpointRejection {
+    fn into_response(self) -> Response {
+        let body = serde_json::json!({
+            "error": self.error,
+            "details": self.details,
+        });
+        (self.status, Json(body)).into_response()
+    }
+}
+
+/// Checks a request against the endpoints the provider published and returns the
+/// endpoint it matched. APIs that have not declared any endpoints are proxied
+/// without restriction.
+///
+/// Unknown paths are rejected with 404, known paths with an undeclared method
+/// with 405, and query strings or bodies that fail the endpoint's JSON Schemas
+/// with 400 listing every violation. Endpoints with a `body_schema` only take
+/// JSON, and refuse other content types with 415.
+pub fn validate_request(
+    api: &Api,
+    request: &UpstreamRequest,
+) -> Result<Option<ApiEndpoint>, EndpointRejection> {
+    if has_dot_segment(&request.path) {
+        return Err(EndpointRejection::new(
+            StatusCode::BAD_REQUEST,
+            format!("{} has a . or .. segment", request.path),
+        ));
+    }
+    if api.endpoints.is_empty() {
+        return Ok(None);
+    }
+
+    let candidates: Vec<&ApiEndpoint> = api
+        .endpoints
+        .iter()
+        .filter(|endpoint| path_matches(&endpoint.path, &request.path))
+        .collect();
+    if candidates.is_empty() {
+        return Err(EndpointRejection::new(
+            StatusCode::NOT_FOUND,
+            format!("No endpoint is published for {}", request.path),
+        ));
+    }
+
+    let Some(endpoint) = candidates
+        .iter()
+        .find(|endpoint| endpoint.method.as_str() == request.method.as_str())
+    else {
+        let allowed = candidates
+            .iter()
+            .map(|endpoint| endpoint.method.as_str().to_string())
+            .collect();
+        return Err(EndpointRejection::new(
+            StatusCode::METHOD_NOT_ALLOWED,
+            format!("{} is not published for {}", request.method, request.path),
+        )
+        .with_details(allowed));
+    };
+
+    if endpoint.body_schema.is_some() && !is_json_body(&request.headers) {
+        return Err(EndpointRejection::new(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            format!(
+                "{} {} only accepts JSON bodies",
+                endpoint.method.as_str(),
+                endpoint.path
+            ),
+        ));
+    }
+
+    let mut details = Vec::new();
+    if let Some(schema) = &endpoint.query_params {
+        let instance = query_instance(schema, request.query.as_deref());
+        details.extend(schema_errors(schema, &instance, "query")?);
+    }
+    if let Some(schema) = &endpoint.body_schema {
+        let body = if request.body.is_empty() {
+            Some(Value::Null)
+        } else {
+            match serde_json::from_slice(&request.body) {
+                Ok(body) => Some(body),
+                Err(e) => {
+                    details.push(format!("body: not valid JSON ({})", e));
+                    None
+                }
+            }
+        };
+        if let Some(body) = body {
+            details.extend(schema_errors(schema, &body, "body")?);
+        }
+    }
+
+    if details.is_empty() {
+        Ok(Some((*endpoint).clone()))
+    } else {
+        Err(EndpointRejection::new(
+            StatusCode::BAD_REQUEST,
+            format!(
+                "Request does not match {} {}",
+                endpoint.method.as_str(),
+                endpoint.path
+            ),
+        )
+        .with_details(details))
+    }
+}
+
+/// Returns false if any endpoint carries a `body_schema` or `query_params` that
+/// is not a valid JSON Schema.
+pub fn endpoint_schemas_are_valid(endpoints: &[ApiEndpoint]) -> bool {
+    endpoints
+        .iter()
+        .flat_map(|endpoint| [&endpoint.body_schema, &endpoint.query_params])
+        .flatten()
+        .all(|schema| jsonschema::validator_for(schema).is_ok())
+}
+
+/// Whether `path` has a `.` or `..` segment, also when percent-encoded or
+/// separated by backslashes. URL parsers resolve these before the request is
+/// sent, so a path like `/items/../admin` would match `/items/{id}/...` here
+/// but reach a different upstream path.
+pub fn has_dot_segment(path: &str) -> bool {
+    path.split(['/', '\\']).any(|segment| {
+        let decoded = segment.to_ascii_lowercase().replace("%2e", ".");
+        decoded == "." || decoded == ".."
+    })
+}
+
+/// Matches a request path against an endpoint template. A segment written as
+/// `{name}` or `:name` matches any single segment, and a trailing `*` or
+/// `{*name}` matches the rest of the path. Leading and trailing slashes are
+/// ignored.
+fn path_matches(template: &str, path: &str) -> bool {
+    let mut expected = segments(template);
+    let mut actual = segments(path);
+
+    loop {
+        match (expected.next(), actual.next()) {
+            (Some(pattern), rest) if pattern == "*" || is_wildcard(pattern) => {
+                return rest.is_some();
+            }
+            (Some(pattern), Some(segment)) => {
+                if !is_param(pattern) && pattern != segment {
+                    return false;
+                }
+            }
+            (None, None) => return true,
+            _ => return false,
+        }
+    }
+}
+
+fn segments(path: &str) -> impl Iterator<Item = &str> {
+    path.trim_matches('/').split('/').filter(|s| !s.is_empty())
+}
+
+fn is_param(segment: &str) -> bool {
+    (segment.starts_with('{') && segment.ends_with('}') && segment.len() > 2)
+        || (segment.starts_with(':') && segment.len() > 1)
+}
+
+fn is_wildcard(segment: &str) -> bool {
+    segment.starts_with("{*") && segment.ends_with('}')
+}
+
+/// Bodies without a content type are taken to be JSON.
+fn is_json_body(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_none_or(|value| value.to_ascii_lowercase().contains("json"))
+}
+
+fn schema_errors(
+    schema: &Value,
+    instance: &Value,
+    location: &str,
+) -> Result<Vec<String>, EndpointRejection> {
+    let validator = jsonschema::validator_for(schema).map_err(|e| {
+        tracing::warn!("Invalid {} schema on endpoint: {}", location, e);
+        EndpointRejection::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("The endpoint's {} schema is invalid", location),
+        )
+    })?;
+
+    Ok(validator
+        .iter_errors(instance)
+        .map(|error| format!("{}{}: {}", location, error.instance_path, error))
+        .collect())
+}
+
+/// Turns a query string into a JSON object the `query_params` schema can be
+/// checked against. Query values are always text, so each one is coerced to the
+/// type its property declares; repeated keys become arrays.
+fn query_instance(schema: &Value, query: Option<&str>) -> Value {
+    let mut grouped: Map<String, Value> = Map::new();
+    for (key, value) in url::form_urlencoded::parse(query.unwrap_or_default().as_bytes()) {
+        let property = &schema["properties"][key.as_ref()];
+        let is_array = declared_types(property).contains(&"array");
+        let value = coerce(
+            if is_array { &property["items"] } else { property },
+            &value,
+        );
+
+        match grouped.get_mut(key.as_ref()) {
+            Some(Value::Array(values)) => values.push(value),
+            Some(existing) => {
+                let first = existing.take();
+                *existing = Value::Array(vec![first, value]);
+            }
+            None if is_array => {
+                grouped.insert(key.into_owned(), Value::Array(vec![value]));
+            }
+            None => {
+                grouped.insert(key.into_owned(), value);
+            }
+        }
+    }
+    Value::Object(grouped)
+}
+
+fn declared_types(schema: &Value) -> Vec<&str> {
+    match &schema["type"] {
+        Value::String(ty) => vec![ty.as_str()],
+        Value::Array(types) => types.iter().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn coerce(schema: &Value, raw: &str) -> Value {
+    let types = declared_types(schema);
+    if types.contains(&"integer") {
+        if let Ok(n) = raw.parse::<i64>() {
+            return Value::Number(n.into());
+        }
+    }
+    if types.contains(&"number") {
+        if let Some(n) = raw.parse::<f64>().ok().and_then(Number::from_f64) {
+            return Value::Number(n);
+        }
+    }
+    if types.contains(&"boolean") {
+        match raw {
+            "true" => return Value::Bool(true),
+            "false" => return Value::Bool(false),
+            _ => {}
+        }
+    }
+    Value::String(raw.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Method;
+    use serde_json::json;
+    use shared::ResponseFormat;
+
+    fn api() -> Api {
+        serde_json::from_value(json!({
+            "id": uuid::Uuid::new_v4(),
+            "user_id": uuid::Uuid::new_v4(),
+            "name": "Items",
+            "description": null,
+            "category": "Data",
+            "kind": "Rest",
+            "base_url": "https://a.test",
+            "endpoints": [{
+                "path": "/items",
+                "method": "POST",
+                "body_schema": {
+                    "type": "object",
+                    "required": ["name"],
+                    "properties": { "name": { "type": "string" } },
+                },
+            }],
+            "payment_config": null,
+            "proxy_config": null,
+            "graphql": null,
+            "created_at": chrono::Utc::now(),
+            "updated_at": chrono::Utc::now(),
+        }))
+        .unwrap()
+    }
+
+    fn post(content_type: Option<&str>, body: &str) -> UpstreamRequest {
+        let mut headers = HeaderMap::new();
+        if let Some(content_type) = content_type {
+            headers.insert(header::CONTENT_TYPE, content_type.parse().unwrap());
+        }
+        UpstreamRequest {
+            method: Method::POST,
+            path: "/items".to_string(),
+            query: None,
+            headers,
+            body: body.to_string().into(),
+            response_format: ResponseFormat::Passthrough,
+        }
+    }
+
+    fn status(request: &UpstreamRequest) -> Option<StatusCode> {
+        validate_request(&api(), request).err().map(|e| e.status)
+    }
+
+    #[test]
+    fn checks_json_bodies_against_the_schema() {
+        for content_type in [
+            Some("application/json"),
+            Some("application/json; charset=utf-8"),
+            None,
+        ] {
+            assert_eq!(status(&post(content_type, r#"{"name":"a"}"#)), None);
+        }
+        assert_eq!(
+            status(&post(Some("application/json"), r#"{"name":1}"#)),
+            Some(StatusCode::BAD_REQUEST)
+        );
+        assert_eq!(
+            status(&post(None, "not json")),
+            Some(StatusCode::BAD_REQUEST)
+        );
+    }
+
+    #[test]
+    fn refuses_other_content_types_when_there_is_a_body_schema() {
+        for content_type in ["text/plain", "multipart/form-data; boundary=x"] {
+            assert_eq!(
+                status(&post(Some(content_type), r#"{"name":1}"#)),
+                Some(StatusCode::UNSUPPORTED_MEDIA_TYPE),
+                "{content_type}"
+            );
+        }
+    }
+
+    #[test]
+    fn matches_params_and_wildcards() {
+        assert!(path_matches("/items/{id}/details", "/items/42/details"));
+        assert!(path_matches("/items/:id", "/items/42/"));
+        assert!(path_matches("/files/{*rest}", "/files/a/b/c"));
+        assert!(!path_matches("/files/{*rest}", "/files"));
+        assert!(!path_matches("/items/{id}", "/items/42/details"));
+        assert!(!path_matches("/items/{id}", "/users/42"));
+    }
+
+    #[test]
+    fn finds_dot_segments() {
+        for path in [
+            "/items/../admin/details",
+            "/items/%2e%2e/admin",
+            "/items/.%2E/admin",
+            "/items/..\\admin/details",
+            "/items/./details",
+            "/items/%2E/details",
+            "..",
+        ] {
+            assert!(has_dot_segment(path), "{path}");
+        }
+        for path in [
+            "/items/42/details",
+            "/items/...",
+            "/items/.well-known",
+            "/a..b/c",
+        ] {
+            assert!(!has_dot_segment(path), "{path}");
+        }
+    }
+}
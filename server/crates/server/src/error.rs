@@ -0,0 +1,190 @@
+use axum::{
+    extract::Request,
+    http::{header, HeaderName, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use uuid::Uuid;
+
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// Gives every request an id, taken from its `X-Request-Id` header or made up,
+/// and echoes it in the response. Errors carry it so a report from a user can be
+/// matched with the server's logs.
+pub async fn request_id(mut req: Request, next: Next) -> Response {
+    let id = req
+        .headers()
+        .get(&REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| !value.is_empty() && value.len() <= 128)
+        .map(str::to_string)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+    if let Ok(value) = HeaderValue::try_from(id.as_str()) {
+        req.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+
+    let mut response = REQUEST_ID.scope(id.clone(), next.run(req)).await;
+    if let Ok(value) = HeaderValue::try_from(id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
+
+/// The id `request_id` gave the request being handled.
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(Clone::clone).ok()
+}
+
+/// An error from a management handler. It is returned as an RFC 9457 problem
+/// details document with a machine readable `code` and the request id, while
+/// the underlying cause is only logged.
+#[derive(Debug)]
+pub struct AppError {
+    status: StatusCode,
+    code: &'static str,
+    detail: String,
+    source: Option<anyhow::Error>,
+}
+
+#[derive(Serialize)]
+struct Problem<'a> {
+    r#type: &'static str,
+    title: &'static str,
+    status: u16,
+    detail: &'a str,
+    code: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+}
+
+impl AppError {
+    pub fn new(status: StatusCode, code: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            status,
+            code,
+            detail: detail.into(),
+            source: None,
+        }
+    }
+
+    pub fn bad_request(detail: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, "bad_request", detail)
+    }
+
+    pub fn unauthorized(detail: impl Into<String>) -> Self {
+        Self::new(StatusCode::UNAUTHORIZED, "unauthorized", detail)
+    }
+
+    pub fn forbidden(detail: impl Into<String>) -> Self {
+        Self::new(StatusCode::FORBIDDEN, "forbidden", detail)
+    }
+
+    pub fn not_found(detail: impl Into<String>) -> Self {
+        Self::new(StatusCode::NOT_FOUND, "not_found", detail)
+    }
+
+    pub fn service_unavailable(detail: impl Into<String>) -> Self {
+        Self::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "service_unavailable",
+            detail,
+        )
+    }
+
+    pub fn internal(source: impl Into<anyhow::Error>) -> Self {
+        Self::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "internal_error",
+            "Something went wrong on our side",
+        )
+        .with_source(source)
+    }
+
+    pub fn with_source(mut self, source: impl Into<anyhow::Error>) -> Self {
+        self.source = Some(source.into());
+        self
+    }
+}
+
+/// Repository errors are mapped by their database cause: unique violations are
+/// conflicts, foreign key violations point at a record that doesn't exist, and
+/// anything unexpected is an internal error.
+impl From<anyhow::Error> for AppError {
+    fn from(error: anyhow::Error) -> Self {
+        let mapped = match error.downcast_ref::<sqlx::Error>() {
+            Some(sqlx::Error::RowNotFound) => {
+                Some(AppError::not_found("The requested record does not exist"))
+            }
+            Some(sqlx::Error::Database(db)) => match db.code().as_deref() {
+                Some("23505") => Some(AppError::new(
+                    StatusCode::CONFLICT,
+                    "conflict",
+                    match db.constraint() {
+                        Some(constraint) => {
+                            format!("Conflicts with an existing record ({})", constraint)
+                        }
+                        None => "Conflicts with an existing record".to_string(),
+                    },
+                )),
+                Some("23503") => Some(AppError::new(
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    "invalid_reference",
+                    "Refers to a record that does not exist",
+                )),
+                Some("23502") | Some("23514") | Some("22001") | Some("22P02") => Some(
+                    AppError::bad_request("The request has a value the database can't store"),
+                ),
+                _ => None,
+            },
+            _ => None,
+        };
+        match mapped {
+            Some(mapped) => mapped.with_source(error),
+            None => AppError::internal(error),
+        }
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let request_id = current_request_id();
+        let id = request_id.as_deref().unwrap_or("-");
+        let cause = self
+            .source
+            .as_ref()
+            .map(|source| format!("{source:#}"))
+            .unwrap_or_else(|| self.detail.clone());
+        if self.status.is_server_error() {
+            tracing::error!("Request {} failed with {}: {}", id, self.status, cause);
+        } else {
+            tracing::info!("Request {} failed with {}: {}", id, self.status, cause);
+        }
+
+        let problem = Problem {
+            r#type: "about:blank",
+            title: self.status.canonical_reason().unwrap_or("Error"),
+            status: self.status.as_u16(),
+            detail: &self.detail,
+            code: self.code,
+            request_id,
+        };
+        let body = serde_json::to_vec(&problem).unwrap_or_default();
+        let mut response = (
+            self.status,
+            [(header::CONTENT_TYPE, "application/problem+json")],
+            body,
+        )
+            .into_response();
+        if self.status == StatusCode::UNAUTHORIZED {
+            response
+                .headers_mut()
+                .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+        }
+        response
+    }
+}
@@ -0,0 +1,202 @@
+use crate::error::AppError;
+use axum::extract::rejection::{JsonRejection, PathRejection, QueryRejection};
+use axum::extract::{FromRequest, FromRequestParts, Request};
+use axum::http::{request::Parts, StatusCode};
+use axum::response::{IntoResponse, Response};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// `axum::Json` for management handlers: a body that can't be read is refused
+/// as a problem details document, like every other error they return.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Json<T>(pub T);
+
+/// `axum::extract::Path`, refusing path parameters that don't parse as an
+/// `AppError`.
+#[derive(Clone, Copy, Debug)]
+pub struct Path<T>(pub T);
+
+/// `axum::extract::Query`, refusing query strings that don't parse as an
+/// `AppError`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Query<T>(pub T);
+
+impl<T, S> FromRequest<S> for Json<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, AppError> {
+        match axum::Json::<T>::from_request(req, state).await {
+            Ok(axum::Json(value)) => Ok(Json(value)),
+            Err(rejection) => Err(json_rejection(rejection)),
+        }
+    }
+}
+
+impl<T: Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> Response {
+        axum::Json(self.0).into_response()
+    }
+}
+
+impl<T, S> FromRequestParts<S> for Path<T>
+where
+    T: DeserializeOwned + Send,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, AppError> {
+        match axum::extract::Path::<T>::from_request_parts(parts, state).await {
+            Ok(axum::extract::Path(value)) => Ok(Path(value)),
+            Err(rejection) => Err(path_rejection(rejection)),
+        }
+    }
+}
+
+impl<T, S> FromRequestParts<S> for Query<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, AppError> {
+        match axum::extract::Query::<T>::from_request_parts(parts, state).await {
+            Ok(axum::extract::Query(value)) => Ok(Query(value)),
+            Err(rejection) => Err(query_rejection(rejection)),
+        }
+    }
+}
+
+fn json_rejection(rejection: JsonRejection) -> AppError {
+    let code = match rejection.status() {
+        StatusCode::UNSUPPORTED_MEDIA_TYPE => "unsupported_media_type",
+        StatusCode::PAYLOAD_TOO_LARGE => "payload_too_large",
+        StatusCode::UNPROCESSABLE_ENTITY => "invalid_body",
+        _ => "bad_request",
+    };
+    AppError::new(rejection.status(), code, rejection.body_text())
+}
+
+fn path_rejection(rejection: PathRejection) -> AppError {
+    match rejection.status() {
+        StatusCode::BAD_REQUEST => AppError::bad_request(rejection.body_text()),
+        status => AppError::new(status, "internal_error", "Something went wrong on our side")
+            .with_source(anyhow::anyhow!(rejection.body_text())),
+    }
+}
+
+fn query_rejection(rejection: QueryRejection) -> AppError {
+    AppError::new(rejection.status(), "bad_request", rejection.body_text())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize)]
+    struct Named {
+        name: String,
+    }
+
+    async fn problem(error: AppError) -> (StatusCode, serde_json::Value) {
+        let response = error.into_response();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    fn json_request(content_type: &str, body: &str) -> Request {
+        Request::builder()
+            .method("POST")
+            .uri("/")
+            .header("content-type", content_type)
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn reads_json_bodies() {
+        let Json(named) =
+            Json::<Named>::from_request(json_request("application/json", r#"{"name":"a"}"#), &())
+                .await
+                .unwrap();
+        assert_eq!(named.name, "a");
+    }
+
+    #[tokio::test]
+    async fn refuses_bad_json_as_problems() {
+        for (content_type, body, status, code) in [
+            (
+                "text/plain",
+                r#"{"name":"a"}"#,
+                415,
+                "unsupported_media_type",
+            ),
+            ("application/json", "{", 400, "bad_request"),
+            ("application/json", r#"{"name":1}"#, 422, "invalid_body"),
+        ] {
+            let error = Json::<Named>::from_request(json_request(content_type, body), &())
+                .await
+                .unwrap_err();
+            let (actual, problem) = problem(error).await;
+            assert_eq!(actual.as_u16(), status);
+            assert_eq!(problem["code"], code);
+            assert_eq!(problem["status"], status);
+        }
+    }
+
+    #[tokio::test]
+    async fn refuses_bad_queries_as_problems() {
+        let (mut parts, _) = Request::builder()
+            .uri("/?limit=many")
+            .body(Body::empty())
+            .unwrap()
+            .into_parts();
+        #[derive(Debug, Deserialize)]
+        struct Limit {
+            #[allow(dead_code)]
+            limit: u32,
+        }
+        let error = Query::<Limit>::from_request_parts(&mut parts, &())
+            .await
+            .unwrap_err();
+        let (status, problem) = problem(error).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(problem["code"], "bad_request");
+    }
+
+    #[tokio::test]
+    async fn refuses_bad_path_parameters_as_problems() {
+        use tower::ServiceExt;
+
+        let app = axum::Router::new().route(
+            "/users/{user_id}",
+            axum::routing::get(
+                |Path(user_id): Path<uuid::Uuid>| async move { user_id.to_string() },
+            ),
+        );
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/users/not-a-uuid")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            response.headers()["content-type"],
+            "application/problem+json"
+        );
+    }
+}
@@ -0,0 +1,734 @@
+use crate::endpoints::EndpointRejection;
+use crate::proxy::UpstreamRequest;
+use apollo_compiler::executable::{ExecutableDocument, Selection, SelectionSet};
+use apollo_compiler::response::{JsonMap, JsonValue};
+use apollo_compiler::validation::{DiagnosticList, Valid};
+use apollo_compiler::{ast, Schema};
+use axum::http::StatusCode;
+use reqwest::Method;
+use serde::Deserialize;
+use serde_json::Value;
+use shared::{Api, ApiKind, CreateApiRequest, GraphQLConfig};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::{Arc, RwLock};
+use uuid::Uuid;
+
+/// Arguments that set how many items a list field returns.
+const PAGE_SIZE_ARGUMENTS: &[&str] = &["first", "last", "limit"];
+
+/// Scalars every schema has, which introspection lists but SDL must not declare.
+const BUILT_IN_SCALARS: &[&str] = &["String", "Int", "Float", "Boolean", "ID"];
+
+const BUILT_IN_DIRECTIVES: &[&str] = &["skip", "include", "deprecated", "specifiedBy", "oneOf"];
+
+/// An operation that passed validation, with what it costs to run.
+#[derive(Clone, Debug)]
+pub struct CheckedOperation {
+    pub name: Option<String>,
+    pub complexity: u64,
+    pub depth: u32,
+    pub price: Option<f64>,
+}
+
+/// Parsed schemas of GraphQL APIs, rebuilt whenever the API is updated.
+#[derive(Clone, Debug, Default)]
+pub struct SchemaCache {
+    schemas: Arc<RwLock<HashMap<Uuid, CachedSchema>>>,
+}
+
+#[derive(Debug)]
+struct CachedSchema {
+    updated_at: chrono::DateTime<chrono::Utc>,
+    schema: Arc<Valid<Schema>>,
+}
+
+impl SchemaCache {
+    pub fn get(&self, api: &Api) -> Option<Arc<Valid<Schema>>> {
+        {
+            let schemas = self.schemas.read().expect("schema cache poisoned");
+            if let Some(cached) = schemas.get(&api.id) {
+                if cached.updated_at == api.updated_at {
+                    return Some(cached.schema.clone());
+                }
+            }
+        }
+
+        let sdl = api.graphql.as_ref()?.sdl.as_deref()?;
+        let schema = match Schema::parse_and_validate(sdl, "schema.graphql") {
+            Ok(schema) => Arc::new(schema),
+            Err(e) => {
+                tracing::warn!("Invalid GraphQL schema on API {}: {}", api.id, e.errors);
+                return None;
+            }
+        };
+        let mut schemas = self.schemas.write().expect("schema cache poisoned");
+        schemas.insert(
+            api.id,
+            CachedSchema {
+                updated_at: api.updated_at,
+                schema: schema.clone(),
+            },
+        );
+        Some(schema)
+    }
+}
+
+/// Checks the `graphql` config of a GraphQL API before it is saved, replacing
+/// an introspection result with the equivalent SDL.
+pub fn prepare_api(request: &mut CreateApiRequest) -> Result<(), String> {
+    if request.kind != ApiKind::GraphQL {
+        return Ok(());
+    }
+    let config = request
+        .graphql
+        .as_mut()
+        .ok_or("GraphQL APIs need a graphql config")?;
+
+    if let Some(introspection) = config.introspection.take() {
+        config.sdl = Some(introspection_to_sdl(&introspection)?);
+    }
+    let sdl = config
+        .sdl
+        .as_deref()
+        .ok_or("GraphQL APIs need a schema, as SDL or introspection")?;
+    Schema::parse_and_validate(sdl, "schema.graphql").map_err(|e| e.errors.to_string())?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GraphQLRequest {
+    query: String,
+    operation_name: Option<String>,
+    #[serde(default)]
+    variables: Option<Value>,
+}
+
+/// Validates a GraphQL call against the API's schema and works out its price.
+///
+/// Only the configured `path` is served, over GET and POST; mutations need
+/// POST. Operations that don't validate, or that go over `max_complexity` or
+/// `max_depth`, are refused with 400 listing the problems. `default_price` is
+/// the API's `cost_per_request`, the least a named operation is charged when
+/// there is no complexity price.
+pub fn check_request(
+    config: &GraphQLConfig,
+    schema: &Valid<Schema>,
+    request: &UpstreamRequest,
+    default_price: f64,
+) -> Result<CheckedOperation, EndpointRejection> {
+    if request.path.trim_end_matches('/') != config.path.trim_end_matches('/') {
+        return Err(EndpointRejection::new(
+            StatusCode::NOT_FOUND,
+            format!("No endpoint is published for {}", request.path),
+        ));
+    }
+
+    let graphql_request = match request.method {
+        Method::POST => serde_json::from_slice::<GraphQLRequest>(&request.body)
+            .map_err(|e| invalid_request(format!("body: {}", e)))?,
+        Method::GET => from_query(request.query.as_deref())?,
+        _ => {
+            return Err(EndpointRejection::new(
+                StatusCode::METHOD_NOT_ALLOWED,
+                format!("{} is not published for {}", request.method, request.path),
+            )
+            .with_details(vec!["GET".to_string(), "POST".to_string()]))
+        }
+    };
+
+    let document =
+        ExecutableDocument::parse_and_validate(schema, &graphql_request.query, "query.graphql")
+            .map_err(|e| invalid_operation(&e.errors))?;
+    let operation = document
+        .operations
+        .get(graphql_request.operation_name.as_deref())
+        .map_err(|e| invalid_request(e.message().to_string()))?;
+    if operation.operation_type == ast::OperationType::Mutation && request.method == Method::GET {
+        return Err(EndpointRejection::new(
+            StatusCode::METHOD_NOT_ALLOWED,
+            "Mutations must be sent with POST",
+        )
+        .with_details(vec!["POST".to_string()]));
+    }
+
+    let variables = match graphql_request.variables {
+        None | Some(Value::Null) => JsonMap::new(),
+        Some(variables) => serde_json::from_value(variables)
+            .map_err(|_| invalid_request("variables: must be an object".to_string()))?,
+    };
+    let variables = apollo_compiler::request::coerce_variable_values(schema, operation, &variables)
+        .map_err(|e| invalid_request(format!("variables: {}", e.message())))?;
+
+    let (complexity, depth) = cost(
+        &document,
+        &operation.selection_set,
+        &variables,
+        1,
+        &mut HashMap::new(),
+    );
+    let mut details = Vec::new();
+    if let Some(max) = config.max_complexity.filter(|max| complexity > *max) {
+        details.push(format!(
+            "complexity {} exceeds the limit of {}",
+            complexity, max
+        ));
+    }
+    if let Some(max) = config.max_depth.filter(|max| depth > *max) {
+        details.push(format!("depth {} exceeds the limit of {}", depth, max));
+    }
+    if !details.is_empty() {
+        return Err(EndpointRejection::new(
+            StatusCode::BAD_REQUEST,
+            "GraphQL operation is too expensive",
+        )
+        .with_details(details));
+    }
+
+    let name = operation.name.as_ref().map(|name| name.to_string());
+    let price = price(config, name.as_deref(), complexity, default_price);
+    Ok(CheckedOperation {
+        name,
+        complexity,
+        depth,
+        price,
+    })
+}
+
+/// What an operation costs. The caller picks the operation name, so a named
+/// price never undercuts what the operation would cost without one: it is
+/// raised to the complexity price, or to `default_price` (the API's
+/// `cost_per_request`) when no complexity price is set.
+fn price(
+    config: &GraphQLConfig,
+    name: Option<&str>,
+    complexity: u64,
+    default_price: f64,
+) -> Option<f64> {
+    let complexity_price = config
+        .complexity_price
+        .map(|price| price * complexity as f64);
+    match name.and_then(|name| config.operation_prices.get(name)) {
+        Some(named) => Some(named.max(complexity_price.unwrap_or(default_price))),
+        None => complexity_price,
+    }
+}
+
+fn from_query(query: Option<&str>) -> Result<GraphQLRequest, EndpointRejection> {
+    let mut graphql_query = None;
+    let mut operation_name = None;
+    let mut variables = None;
+    for (key, value) in url::form_urlencoded::parse(query.unwrap_or_default().as_bytes()) {
+        match key.as_ref() {
+            "query" => graphql_query = Some(value.into_owned()),
+            "operationName" => operation_name = Some(value.into_owned()),
+            "variables" => {
+                variables = Some(
+                    serde_json::from_str(&value)
+                        .map_err(|_| invalid_request("variables: not valid JSON".to_string()))?,
+                )
+            }
+            _ => {}
+        }
+    }
+
+    Ok(GraphQLRequest {
+        query: graphql_query.ok_or_else(|| invalid_request("query: missing".to_string()))?,
+        operation_name,
+        variables,
+    })
+}
+
+fn invalid_request(detail: String) -> EndpointRejection {
+    EndpointRejection::new(StatusCode::BAD_REQUEST, "Invalid GraphQL request")
+        .with_details(vec![detail])
+}
+
+fn invalid_operation(errors: &DiagnosticList) -> EndpointRejection {
+    let details = errors
+        .iter()
+        .map(|diagnostic| {
+            let error = diagnostic.to_json();
+            match error.locations.first() {
+                Some(location) => {
+                    format!("{}:{}: {}", location.line, location.column, error.message)
+                }
+                None => error.message,
+            }
+        })
+        .collect();
+    EndpointRejection::new(StatusCode::BAD_REQUEST, "Invalid GraphQL operation")
+        .with_details(details)
+}
+
+/// Complexity and depth of a selection set. Every field counts once, times the
+/// page size requested for list fields; `__typename` is free.
+///
+/// Each fragment is walked once and its cost kept in `fragments`, relative to
+/// where it is spread, so fragments spreading each other many times over can't
+/// make the walk exponential.
+fn cost(
+    document: &ExecutableDocument,
+    selection_set: &SelectionSet,
+    variables: &JsonMap,
+    depth: u32,
+    fragments: &mut HashMap<String, (u64, u32)>,
+) -> (u64, u32) {
+    let mut complexity: u64 = 0;
+    let mut max_depth = 0;
+    for selection in &selection_set.selections {
+        let (selection_complexity, selection_depth) = match selection {
+            Selection::Field(field) if field.name == "__typename" => (0, depth),
+            Selection::Field(field) => {
+                let (children, child_depth) = cost(
+                    document,
+                    &field.selection_set,
+                    variables,
+                    depth + 1,
+                    fragments,
+                );
+                let items = page_size(&field.arguments, variables).unwrap_or(1);
+                (
+                    items.saturating_mul(children.saturating_add(1)),
+                    child_depth.max(depth),
+                )
+            }
+            Selection::FragmentSpread(spread) => {
+                let name = spread.fragment_name.as_str();
+                let (fragment_complexity, fragment_depth) = match fragments.get(name) {
+                    Some(cached) => *cached,
+                    None => {
+                        let computed = match document.fragments.get(&spread.fragment_name) {
+                            Some(fragment) => {
+                                cost(document, &fragment.selection_set, variables, 0, fragments)
+                            }
+                            None => (0, 0),
+                        };
+                        fragments.insert(name.to_string(), computed);
+                        computed
+                    }
+                };
+                (fragment_complexity, depth.saturating_add(fragment_depth))
+            }
+            Selection::InlineFragment(fragment) => cost(
+                document,
+                &fragment.selection_set,
+                variables,
+                depth,
+                fragments,
+            ),
+        };
+        complexity = complexity.saturating_add(selection_complexity);
+        max_depth = max_depth.max(selection_depth);
+    }
+    (complexity, max_depth)
+}
+
+fn page_size(
+    arguments: &[apollo_compiler::Node<ast::Argument>],
+    variables: &JsonMap,
+) -> Option<u64> {
+    arguments
+        .iter()
+        .filter(|argument| PAGE_SIZE_ARGUMENTS.contains(&argument.name.as_str()))
+        .find_map(|argument| match argument.value.as_ref() {
+            ast::Value::Int(value) => value.try_to_i32().ok().map(i64::from),
+            ast::Value::Variable(name) => variables.get(name.as_str()).and_then(JsonValue::as_i64),
+            _ => None,
+        })
+        .and_then(|size| u64::try_from(size).ok())
+}
+
+/// Prints the schema described by an introspection query result as SDL.
+/// Accepts the full response (`{"data": {"__schema": ...}}`) or just `__schema`.
+pub fn introspection_to_sdl(introspection: &Value) -> Result<String, String> {
+    let schema = introspection
+        .pointer("/data/__schema")
+        .or_else(|| introspection.get("__schema"))
+        .ok_or("introspection result has no __schema")?;
+
+    let mut sdl = String::new();
+    let root = |key: &str| schema[key]["name"].as_str();
+    let _ = writeln!(sdl, "schema {{");
+    for (operation, key) in [
+        ("query", "queryType"),
+        ("mutation", "mutationType"),
+        ("subscription", "subscriptionType"),
+    ] {
+        if let Some(name) = root(key) {
+            let _ = writeln!(sdl, "  {}: {}", operation, name);
+        }
+    }
+    let _ = writeln!(sdl, "}}");
+
+    for directive in schema["directives"].as_array().into_iter().flatten() {
+        let name = directive["name"]
+            .as_str()
+            .ok_or("directive without a name")?;
+        if BUILT_IN_DIRECTIVES.contains(&name) {
+            continue;
+        }
+        let locations: Vec<&str> = directive["locations"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+            .collect();
+        sdl.push('\n');
+        write_description(&mut sdl, &directive["description"], "");
+        let _ = writeln!(
+            sdl,
+            "directive @{}{}{} on {}",
+            name,
+            arguments(&directive["args"])?,
+            if directive["isRepeatable"].as_bool() == Some(true) {
+                " repeatable"
+            } else {
+                ""
+            },
+            locations.join(" | ")
+        );
+    }
+
+    for ty in schema["types"]
+        .as_array()
+        .ok_or("introspection has no types")?
+    {
+        let name = ty["name"].as_str().ok_or("type without a name")?;
+        if name.starts_with("__") || BUILT_IN_SCALARS.contains(&name) {
+            continue;
+        }
+        sdl.push('\n');
+        write_description(&mut sdl, &ty["description"], "");
+        let interfaces: Vec<&str> = ty["interfaces"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|interface| interface["name"].as_str())
+            .collect();
+        let implements = if interfaces.is_empty() {
+            String::new()
+        } else {
+            format!(" implements {}", interfaces.join(" & "))
+        };
+
+        match ty["kind"].as_str() {
+            Some("SCALAR") => {
+                let _ = writeln!(sdl, "scalar {}", name);
+            }
+            Some(kind @ ("OBJECT" | "INTERFACE")) => {
+                let keyword = if kind == "OBJECT" {
+                    "type"
+                } else {
+                    "interface"
+                };
+                let _ = writeln!(sdl, "{} {}{} {{", keyword, name, implements);
+                for field in ty["fields"].as_array().into_iter().flatten() {
+                    write_description(&mut sdl, &field["description"], "  ");
+                    let _ = writeln!(
+                        sdl,
+                        "  {}{}: {}{}",
+                        field["name"].as_str().ok_or("field without a name")?,
+                        arguments(&field["args"])?,
+                        type_ref(&field["type"])?,
+                        deprecation(field)
+                    );
+                }
+                let _ = writeln!(sdl, "}}");
+            }
+            Some("UNION") => {
+                let members: Vec<&str> = ty["possibleTypes"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|member| member["name"].as_str())
+                    .collect();
+                let _ = writeln!(sdl, "union {} = {}", name, members.join(" | "));
+            }
+            Some("ENUM") => {
+                let _ = writeln!(sdl, "enum {} {{", name);
+                for value in ty["enumValues"].as_array().into_iter().flatten() {
+                    write_description(&mut sdl, &value["description"], "  ");
+                    let _ = writeln!(
+                        sdl,
+                        "  {}{}",
+                        value["name"].as_str().ok_or("enum value without a name")?,
+                        deprecation(value)
+                    );
+                }
+                let _ = writeln!(sdl, "}}");
+            }
+            Some("INPUT_OBJECT") => {
+                let _ = writeln!(sdl, "input {} {{", name);
+                for field in ty["inputFields"].as_array().into_iter().flatten() {
+                    write_description(&mut sdl, &field["description"], "  ");
+                    let _ = writeln!(sdl, "  {}", input_value(field)?);
+                }
+                let _ = writeln!(sdl, "}}");
+            }
+            _ => return Err(format!("type {} has an unknown kind", name)),
+        }
+    }
+
+    Ok(sdl)
+}
+
+fn type_ref(ty: &Value) -> Result<String, String> {
+    match ty["kind"].as_str() {
+        Some("NON_NULL") => Ok(format!("{}!", type_ref(&ty["ofType"])?)),
+        Some("LIST") => Ok(format!("[{}]", type_ref(&ty["ofType"])?)),
+        _ => ty["name"]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| "type reference without a name".to_string()),
+    }
+}
+
+fn input_value(value: &Value) -> Result<String, String> {
+    let mut printed = format!(
+        "{}: {}",
+        value["name"].as_str().ok_or("argument without a name")?,
+        type_ref(&value["type"])?
+    );
+    if let Some(default) = value["defaultValue"].as_str() {
+        let _ = write!(printed, " = {}", default);
+    }
+    printed.push_str(&deprecation(value));
+    Ok(printed)
+}
+
+fn arguments(args: &Value) -> Result<String, String> {
+    let args = args
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(input_value)
+        .collect::<Result<Vec<_>, _>>()?;
+    if args.is_empty() {
+        Ok(String::new())
+    } else {
+        Ok(format!("({})", args.join(", ")))
+    }
+}
+
+fn deprecation(item: &Value) -> String {
+    if item["isDeprecated"].as_bool() != Some(true) {
+        return String::new();
+    }
+    match item["deprecationReason"].as_str() {
+        Some(reason) => format!(
+            " @deprecated(reason: {})",
+            serde_json::to_string(reason).unwrap_or_default()
+        ),
+        None => " @deprecated".to_string(),
+    }
+}
+
+/// Descriptions are printed as regular strings; JSON string escapes are valid
+/// GraphQL string escapes.
+fn write_description(sdl: &mut String, description: &Value, indent: &str) {
+    if let Some(description) = description.as_str().filter(|d| !d.is_empty()) {
+        let _ = writeln!(
+            sdl,
+            "{}{}",
+            indent,
+            serde_json::to_string(description).unwrap_or_default()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::ResponseFormat;
+
+    const SDL: &str = "
+        type Query { user(id: ID!): User, users(first: Int): [User] }
+        type User { id: ID, name: String, friends(first: Int): [User] }
+    ";
+
+    fn check(
+        config: &GraphQLConfig,
+        query: &str,
+        variables: Value,
+    ) -> Result<CheckedOperation, EndpointRejection> {
+        let schema = Schema::parse_and_validate(SDL, "schema.graphql").unwrap();
+        let request = UpstreamRequest {
+            method: Method::POST,
+            path: "/graphql".to_string(),
+            query: None,
+            headers: Default::default(),
+            body: serde_json::json!({ "query": query, "variables": variables })
+                .to_string()
+                .into(),
+            response_format: ResponseFormat::Passthrough,
+        };
+        check_request(config, &schema, &request, 0.5)
+    }
+
+    fn config() -> GraphQLConfig {
+        GraphQLConfig {
+            path: "/graphql".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn counts_fields_times_page_size() {
+        let operation = check(
+            &config(),
+            "query($n: Int) { users(first: $n) { id __typename friends(first: 3) { name } } }",
+            serde_json::json!({ "n": 10 }),
+        )
+        .unwrap();
+        // users: 10 * (id + friends: 3 * name)
+        assert_eq!(operation.complexity, 10 * (1 + 3 * (1 + 1) + 1));
+        assert_eq!(operation.depth, 3);
+    }
+
+    #[test]
+    fn fragments_count_where_they_are_spread() {
+        let operation = check(
+            &config(),
+            "{ user(id: 1) { ...Person friends(first: 2) { ...Person } } }
+             fragment Person on User { id name }",
+            Value::Null,
+        )
+        .unwrap();
+        assert_eq!(operation.complexity, 1 + 2 + 2 * (1 + 2));
+        assert_eq!(operation.depth, 3);
+    }
+
+    #[test]
+    fn nested_fragment_spreads_are_walked_once() {
+        // Each fragment spreads the next one twice, 2^40 times in all.
+        let mut query = "{ user(id: 1) { ...F0 } }".to_string();
+        for i in 0..40 {
+            query.push_str(&format!(
+                " fragment F{} on User {{ ...F{} friends {{ ...F{} }} }}",
+                i,
+                i + 1,
+                i + 1
+            ));
+        }
+        query.push_str(" fragment F40 on User { id }");
+
+        let mut config = config();
+        config.max_depth = Some(100);
+        let operation = check(&config, &query, Value::Null).unwrap();
+        assert_eq!(operation.complexity, 1 << 41);
+        assert_eq!(operation.depth, 42);
+    }
+
+    #[test]
+    fn rejects_operations_over_the_limits() {
+        let mut config = config();
+        config.max_complexity = Some(5);
+        config.max_depth = Some(2);
+        let rejection = check(
+            &config,
+            "{ users(first: 10) { friends { id } } }",
+            Value::Null,
+        )
+        .unwrap_err();
+        assert_eq!(
+            format!("{:?}", rejection),
+            format!(
+                "{:?}",
+                EndpointRejection::new(
+                    StatusCode::BAD_REQUEST,
+                    "GraphQL operation is too expensive"
+                )
+                .with_details(vec![
+                    "complexity 30 exceeds the limit of 5".to_string(),
+                    "depth 3 exceeds the limit of 2".to_string(),
+                ])
+            )
+        );
+    }
+
+    #[test]
+    fn named_prices_never_undercut_the_complexity_price() {
+        let mut config = config();
+        config.operation_prices.insert("Cheap".to_string(), 0.01);
+        config.complexity_price = Some(0.001);
+
+        let cheap = check(&config, "query Cheap { user(id: 1) { id } }", Value::Null).unwrap();
+        assert_eq!(cheap.price, Some(0.01));
+        let costly = check(
+            &config,
+            "query Cheap { users(first: 100) { id name } }",
+            Value::Null,
+        )
+        .unwrap();
+        assert_eq!(costly.price, Some(0.001 * 300.0));
+        let other = check(&config, "query Other { user(id: 1) { id } }", Value::Null).unwrap();
+        assert_eq!(other.price, Some(0.001 * 2.0));
+    }
+
+    #[test]
+    fn named_prices_are_at_least_the_default_price() {
+        let mut config = config();
+        config.operation_prices.insert("Cheap".to_string(), 0.01);
+        config.operation_prices.insert("Dear".to_string(), 2.0);
+
+        let cheap = check(&config, "query Cheap { user(id: 1) { id } }", Value::Null).unwrap();
+        assert_eq!(cheap.price, Some(0.5));
+        let dear = check(&config, "query Dear { user(id: 1) { id } }", Value::Null).unwrap();
+        assert_eq!(dear.price, Some(2.0));
+        let other = check(&config, "{ user(id: 1) { id } }", Value::Null).unwrap();
+        assert_eq!(other.price, None);
+    }
+
+    #[test]
+    fn converts_introspection_to_sdl() {
+        let introspection = serde_json::json!({
+            "data": { "__schema": {
+                "queryType": { "name": "Query" },
+                "mutationType": null,
+                "subscriptionType": null,
+                "directives": [
+                    { "name": "include", "locations": ["FIELD"], "args": [] },
+                    { "name": "cost", "locations": ["FIELD_DEFINITION"], "args": [
+                        { "name": "weight", "type": { "kind": "NON_NULL", "name": null,
+                          "ofType": { "kind": "SCALAR", "name": "Int" } } }
+                    ] }
+                ],
+                "types": [
+                    { "kind": "SCALAR", "name": "String" },
+                    { "kind": "OBJECT", "name": "__Schema", "fields": [] },
+                    { "kind": "OBJECT", "name": "Query", "description": "Entry \"points\"",
+                      "interfaces": [], "fields": [
+                        { "name": "tags", "args": [
+                            { "name": "first", "type": { "kind": "SCALAR", "name": "Int" },
+                              "defaultValue": "10" }
+                          ],
+                          "type": { "kind": "LIST", "name": null,
+                            "ofType": { "kind": "ENUM", "name": "Tag" } },
+                          "isDeprecated": true, "deprecationReason": "Use labels" }
+                    ] },
+                    { "kind": "ENUM", "name": "Tag", "enumValues": [
+                        { "name": "NEW" }, { "name": "OLD" }
+                    ] }
+                ]
+            } }
+        });
+
+        let sdl = introspection_to_sdl(&introspection).unwrap();
+        assert!(sdl.contains("schema {\n  query: Query\n}"));
+        assert!(sdl.contains("directive @cost(weight: Int!) on FIELD_DEFINITION"));
+        assert!(!sdl.contains("@include"));
+        assert!(!sdl.contains("scalar String"));
+        assert!(!sdl.contains("__Schema"));
+        assert!(sdl.contains("\"Entry \\\"points\\\"\"\ntype Query {"));
+        assert!(sdl.contains("  tags(first: Int = 10): [Tag] @deprecated(reason: \"Use labels\")"));
+        assert!(sdl.contains("enum Tag {\n  NEW\n  OLD\n}"));
+        Schema::parse_and_validate(&sdl, "schema.graphql").unwrap();
+    }
+
+    #[test]
+    fn introspection_needs_a_schema() {
+        assert!(introspection_to_sdl(&serde_json::json!({ "data": {} })).is_err());
+    }
+}
@@ -1,192 +1,709 @@
 use crate::app::AppState;
+use crate::auth::{api_key_prefix, generate_api_key, hash_api_key, AuthUser};
+use crate::balancer;
+use crate::breaker;
+use crate::cache;
+use crate::call_log;
+use crate::endpoints::endpoint_schemas_are_valid;
+use crate::error::AppError;
+use crate::extract::{Json, Path, Query};
+use crate::graphql;
+use crate::rate_limit;
+use crate::tls;
+use crate::transform;
+use crate::webhooks::generate_secret;
 use anyhow::Result;
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
-    response::Json,
+    extract::State,
+    http::{HeaderName, HeaderValue, StatusCode},
 };
+use database::user_id_for_clerk_id;
 use serde::{Deserialize, Serialize};
-use shared::{Api, CreateApiRequest, CreateUserRequest, User};
+use shared::{
+    Api, ApiKey, ApiKeyScope, ApiMock, ApiSecret, ApiStatus, ApiTlsConfig, CallLog, CallLogFilter,
+    CreateApiKeyRequest, CreateApiMockRequest, CreateApiRequest, CreateApiSecretRequest,
+    CreateWebhookRequest, LatencyPercentiles, PutApiTlsRequest, SecretLocation,
+    User, Webhook, WebhookDelivery,
+};
+use std::collections::HashMap;
 use uuid::Uuid;
 
+/// The email address is taken from the session token, not the request.
 #[derive(Debug, Deserialize, Serialize)]
 pub struct CreateUserWithClerkIdRequest {
     pub clerk_id: String,
     pub name: String,
-    pub email: String,
-}
-
-pub async fn create_user(
-    State(state): State<AppState>,
-    Json(request): Json<CreateUserRequest>,
-) -> Result<Json<User>, StatusCode> {
-    match state.user_repo.create_user(request).await {
-        Ok(user) => Ok(Json(user)),
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
-    }
 }
 
 pub async fn create_user_with_clerk_id(
     State(state): State<AppState>,
+    auth: AuthUser,
     Json(request): Json<CreateUserWithClerkIdRequest>,
-) -> Result<Json<User>, StatusCode> {
-    match state.user_repo.create_user_with_clerk_id(&request.clerk_id, request.name, request.email).await {
-        Ok(user) => Ok(Json(user)),
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
-    }
+) -> Result<Json<User>, AppError> {
+    auth.require_clerk_user(&request.clerk_id)?;
+    let email = auth.session_email()?.to_string();
+    let user = state
+        .user_repo
+        .create_user_with_clerk_id(&request.clerk_id, request.name, email)
+        .await?
+        .ok_or_else(|| {
+            AppError::new(
+                StatusCode::CONFLICT,
+                "conflict",
+                "The email belongs to another user",
+            )
+        })?;
+    Ok(Json(user))
 }
 
 pub async fn get_user(
     State(state): State<AppState>,
     Path(user_id): Path<Uuid>,
-) -> Result<Json<User>, StatusCode> {
-    match state.user_repo.get_user_by_id(user_id).await {
-        Ok(Some(user)) => Ok(Json(user)),
-        Ok(None) => Err(StatusCode::NOT_FOUND),
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
-    }
+    auth: AuthUser,
+) -> Result<Json<User>, AppError> {
+    auth.require_owner(user_id)?;
+    Ok(Json(find_user(&state, user_id).await?))
 }
 
 pub async fn get_user_by_clerk_id(
     State(state): State<AppState>,
+    auth: AuthUser,
     Path(clerk_id): Path<String>,
-) -> Result<Json<User>, StatusCode> {
-    // Convert Clerk ID to UUID using the same logic as create_user_with_clerk_id
-    let user_uuid = Uuid::parse_str(&clerk_id).unwrap_or_else(|_| {
-        Uuid::new_v5(&Uuid::NAMESPACE_OID, clerk_id.as_bytes())
-    });
+) -> Result<Json<User>, AppError> {
+    auth.require_clerk_user(&clerk_id)?;
+    Ok(Json(find_user(&state, user_id_for_clerk_id(&clerk_id)).await?))
+}
+
+async fn find_user(state: &AppState, user_id: Uuid) -> Result<User, AppError> {
+    state
+        .user_repo
+        .get_user_by_id(user_id)
+        .await?
+        .ok_or_else(|| AppError::not_found("User not found"))
+}
 
-    match state.user_repo.get_user_by_id(user_uuid).await {
-        Ok(Some(user)) => Ok(Json(user)),
-        Ok(None) => Err(StatusCode::NOT_FOUND),
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+/// The API `api_id` if it belongs to `user_id`. Other users' APIs are reported
+/// as missing so their ids can't be probed.
+async fn find_api(state: &AppState, user_id: Uuid, api_id: Uuid) -> Result<Api, AppError> {
+    state
+        .api_repo
+        .get_api_by_id(api_id)
+        .await?
+        .filter(|api| api.user_id == user_id)
+        .ok_or_else(|| AppError::not_found("API not found"))
+}
+
+/// The webhook `webhook_id` if it belongs to `user_id`.
+async fn find_webhook(
+    state: &AppState,
+    user_id: Uuid,
+    webhook_id: Uuid,
+) -> Result<Webhook, AppError> {
+    state
+        .webhook_repo
+        .get_webhook_by_id(webhook_id)
+        .await?
+        .filter(|webhook| webhook.user_id == user_id)
+        .ok_or_else(|| AppError::not_found("Webhook not found"))
+}
+
+/// Fills in the request and response size limits callers of the API are held to.
+fn with_limits(state: &AppState, mut api: Api) -> Api {
+    let settings = state.upstream.settings(api.proxy_config.as_ref());
+    api.limits = Some(settings.limits());
+    api
+}
+
+/// Fills in each API's state from its latest health check.
+async fn with_health(state: &AppState, mut apis: Vec<Api>) -> Result<Vec<Api>, AppError> {
+    let latest: HashMap<Uuid, _> = state
+        .health_repo
+        .get_latest_checks()
+        .await?
+        .into_iter()
+        .map(|check| (check.api_id, check))
+        .collect();
+    for api in &mut apis {
+        api.health = Some(state.health.state(api, latest.get(&api.id)));
     }
+    Ok(apis)
+}
+
+/// Checks an API before it is created or updated, and converts GraphQL
+/// introspection results to SDL.
+async fn validate_api(state: &AppState, request: &mut CreateApiRequest) -> Result<(), AppError> {
+    if !endpoint_schemas_are_valid(&request.endpoints) {
+        return Err(AppError::bad_request(
+            "An endpoint has an invalid JSON Schema",
+        ));
+    }
+
+    graphql::prepare_api(request)
+        .map_err(|e| AppError::bad_request(format!("Invalid GraphQL config: {}", e)))?;
+
+    transform::validate(request.proxy_config.as_ref())
+        .map_err(|e| AppError::bad_request(format!("Invalid transforms: {}", e)))?;
+
+    call_log::validate(request.proxy_config.as_ref())
+        .map_err(|e| AppError::bad_request(format!("Invalid call log settings: {}", e)))?;
+
+    rate_limit::validate(request.proxy_config.as_ref(), &request.endpoints)
+        .map_err(|e| AppError::bad_request(format!("Invalid rate limits: {}", e)))?;
+
+    cache::validate(&request.endpoints)
+        .map_err(|e| AppError::bad_request(format!("Invalid cache settings: {}", e)))?;
+
+    breaker::validate(request.proxy_config.as_ref())
+        .map_err(|e| AppError::bad_request(format!("Invalid circuit breaker: {}", e)))?;
+
+    if let Err(e) = state
+        .upstream
+        .policy()
+        .check_base_url(&request.base_url)
+        .await
+    {
+        return Err(AppError::bad_request(format!(
+            "base_url {} is not allowed: {}",
+            request.base_url, e
+        )));
+    }
+
+    balancer::validate(request.proxy_config.as_ref())
+        .map_err(|e| AppError::bad_request(format!("Invalid load balancing: {}", e)))?;
+    if let Some(balancing) = balancer::config(request.proxy_config.as_ref()) {
+        for target in &balancing.targets {
+            if let Err(e) = state.upstream.policy().check_base_url(&target.url).await {
+                return Err(AppError::bad_request(format!(
+                    "Upstream target {} is not allowed: {}",
+                    target.url, e
+                )));
+            }
+        }
+    }
+
+    Ok(())
 }
 
 pub async fn create_api(
     State(state): State<AppState>,
     Path(user_id): Path<Uuid>,
-    Json(request): Json<CreateApiRequest>,
-) -> Result<Json<Api>, StatusCode> {
-    match state.user_repo.get_user_by_id(user_id).await {
-        Ok(Some(_)) => {}
-        Ok(None) => return Err(StatusCode::NOT_FOUND),
-        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+    auth: AuthUser,
+    Json(mut request): Json<CreateApiRequest>,
+) -> Result<Json<Api>, AppError> {
+    auth.authorize(user_id, ApiKeyScope::ApisWrite)?;
+    find_user(&state, user_id).await?;
+    validate_api(&state, &mut request).await?;
+
+    let api = state.api_repo.create_api(user_id, request).await?;
+    Ok(Json(with_limits(&state, api)))
+}
+
+pub async fn list_user_apis(
+    State(state): State<AppState>,
+    Path(user_id): Path<Uuid>,
+) -> Result<Json<Vec<Api>>, AppError> {
+    find_user(&state, user_id).await?;
+
+    let apis = state
+        .api_repo
+        .get_apis_by_user_id(user_id)
+        .await?
+        .into_iter()
+        .map(|api| with_limits(&state, api))
+        .collect();
+    Ok(Json(with_health(&state, apis).await?))
+}
+
+pub async fn get_api(
+    State(state): State<AppState>,
+    Path((user_id, api_id)): Path<(Uuid, Uuid)>,
+) -> Result<Json<Api>, AppError> {
+    find_user(&state, user_id).await?;
+
+    let api = find_api(&state, user_id, api_id).await?;
+    Ok(Json(with_limits(&state, api)))
+}
+
+pub async fn update_api(
+    State(state): State<AppState>,
+    Path((user_id, api_id)): Path<(Uuid, Uuid)>,
+    auth: AuthUser,
+    Json(mut request): Json<CreateApiRequest>,
+) -> Result<Json<Api>, AppError> {
+    auth.authorize(user_id, ApiKeyScope::ApisWrite)?;
+    find_user(&state, user_id).await?;
+    find_api(&state, user_id, api_id).await?;
+    validate_api(&state, &mut request).await?;
+
+    match state.api_repo.update_api(api_id, request).await? {
+        Some(api) => Ok(Json(with_limits(&state, api))),
+        None => Err(AppError::not_found("API not found")),
     }
+}
 
-    match state.api_repo.create_api(user_id, request).await {
-        Ok(api) => Ok(Json(api)),
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+pub async fn list_all_apis(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<Api>>, AppError> {
+    let apis = state.api_repo.get_all_apis().await?;
+    if apis.is_empty() {
+        return Err(AppError::not_found("No APIs are published yet"));
     }
+
+    let apis = apis
+        .into_iter()
+        .map(|api| with_limits(&state, api))
+        .collect();
+    Ok(Json(with_health(&state, apis).await?))
 }
 
-pub async fn list_user_apis(
+#[derive(Debug, Deserialize)]
+pub struct StatusQuery {
+    pub window_hours: Option<u32>,
+}
+
+/// Public health report for an API: its current state, plus uptime and probe
+/// latency over the last `window_hours` (24 by default).
+pub async fn get_api_status(
+    State(state): State<AppState>,
+    Path(api_id): Path<Uuid>,
+    Query(query): Query<StatusQuery>,
+) -> Result<Json<ApiStatus>, AppError> {
+    let api = state
+        .api_repo
+        .get_api_by_id(api_id)
+        .await?
+        .ok_or_else(|| AppError::not_found("API not found"))?;
+
+    let window_hours = query
+        .window_hours
+        .unwrap_or(24)
+        .clamp(1, state.health.max_window_hours());
+    let since = chrono::Utc::now() - chrono::Duration::hours(window_hours.into());
+
+    let latest = state.health_repo.get_latest_check(api_id).await?;
+    let summary = state.health_repo.summarize(api_id, since).await?;
+
+    Ok(Json(ApiStatus {
+        api_id,
+        state: state.health.state(&api, latest.as_ref()),
+        last_checked_at: latest.as_ref().map(|check| check.checked_at),
+        last_status_code: latest.as_ref().and_then(|check| check.status_code),
+        last_error: latest.and_then(|check| check.error),
+        window_hours,
+        checks: summary.checks,
+        uptime_percent: (summary.checks > 0)
+            .then(|| summary.healthy_checks as f64 * 100.0 / summary.checks as f64),
+        latency_ms: LatencyPercentiles {
+            p50: summary.p50,
+            p95: summary.p95,
+            p99: summary.p99,
+        },
+    }))
+}
+
+pub async fn delete_api(
+    State(state): State<AppState>,
+    Path((user_id, api_id)): Path<(Uuid, Uuid)>,
+    auth: AuthUser,
+) -> Result<StatusCode, AppError> {
+    auth.authorize(user_id, ApiKeyScope::ApisWrite)?;
+    find_user(&state, user_id).await?;
+    find_api(&state, user_id, api_id).await?;
+
+    if state.api_repo.delete_api(api_id).await? {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(AppError::not_found("API not found"))
+    }
+}
+
+pub async fn create_webhook(
     State(state): State<AppState>,
     Path(user_id): Path<Uuid>,
-) -> Result<Json<Vec<Api>>, StatusCode> {
-    match state.user_repo.get_user_by_id(user_id).await {
-        Ok(Some(_)) => {}
-        Ok(None) => return Err(StatusCode::NOT_FOUND),
-        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+    auth: AuthUser,
+    Json(request): Json<CreateWebhookRequest>,
+) -> Result<Json<Webhook>, AppError> {
+    auth.authorize(user_id, ApiKeyScope::WebhooksWrite)?;
+    find_user(&state, user_id).await?;
+
+    if let Err(e) = state.upstream.policy().check_base_url(&request.url).await {
+        return Err(AppError::bad_request(format!(
+            "url {} is not allowed: {}",
+            request.url, e
+        )));
+    }
+    if request.events.is_empty() {
+        return Err(AppError::bad_request("At least one event is required"));
     }
 
-    match state.api_repo.get_apis_by_user_id(user_id).await {
-        Ok(apis) => Ok(Json(apis)),
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    if let Some(api_id) = request.api_id {
+        find_api(&state, user_id, api_id).await?;
     }
+
+    let secret = generate_secret();
+    let mut webhook = state
+        .webhook_repo
+        .create_webhook(user_id, &secret, request)
+        .await?;
+    webhook.secret = Some(secret);
+    Ok(Json(webhook))
 }
 
-pub async fn get_api(
+pub async fn list_webhooks(
+    State(state): State<AppState>,
+    Path(user_id): Path<Uuid>,
+    auth: AuthUser,
+) -> Result<Json<Vec<Webhook>>, AppError> {
+    auth.authorize(user_id, ApiKeyScope::WebhooksRead)?;
+    find_user(&state, user_id).await?;
+
+    let webhooks = state.webhook_repo.get_webhooks_by_user_id(user_id).await?;
+    Ok(Json(webhooks))
+}
+
+pub async fn delete_webhook(
+    State(state): State<AppState>,
+    Path((user_id, webhook_id)): Path<(Uuid, Uuid)>,
+    auth: AuthUser,
+) -> Result<StatusCode, AppError> {
+    auth.authorize(user_id, ApiKeyScope::WebhooksWrite)?;
+    find_webhook(&state, user_id, webhook_id).await?;
+
+    if state.webhook_repo.delete_webhook(webhook_id).await? {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(AppError::not_found("Webhook not found"))
+    }
+}
+
+pub async fn list_webhook_deliveries(
+    State(state): State<AppState>,
+    Path((user_id, webhook_id)): Path<(Uuid, Uuid)>,
+    auth: AuthUser,
+) -> Result<Json<Vec<WebhookDelivery>>, AppError> {
+    auth.authorize(user_id, ApiKeyScope::WebhooksRead)?;
+    find_webhook(&state, user_id, webhook_id).await?;
+
+    let deliveries = state
+        .webhook_repo
+        .get_deliveries_by_webhook_id(webhook_id, 100)
+        .await?;
+    Ok(Json(deliveries))
+}
+
+pub async fn put_api_secret(
     State(state): State<AppState>,
     Path((user_id, api_id)): Path<(Uuid, Uuid)>,
-) -> Result<Json<Api>, StatusCode> {
-    match state.user_repo.get_user_by_id(user_id).await {
-        Ok(Some(_)) => {}
-        Ok(None) => return Err(StatusCode::NOT_FOUND),
-        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
-    }
-
-    match state.api_repo.get_api_by_id(api_id).await {
-        Ok(Some(api)) => {
-            if api.user_id == user_id {
-                Ok(Json(api))
-            } else {
-                Err(StatusCode::NOT_FOUND)
-            }
+    auth: AuthUser,
+    Json(request): Json<CreateApiSecretRequest>,
+) -> Result<Json<ApiSecret>, AppError> {
+    auth.authorize(user_id, ApiKeyScope::ApisWrite)?;
+    find_api(&state, user_id, api_id).await?;
+
+    let Some(keyring) = state.secrets.as_ref() else {
+        return Err(AppError::service_unavailable(
+            "Secrets are not configured on this server",
+        ));
+    };
+
+    let name = match request.location {
+        SecretLocation::Bearer => "Authorization".to_string(),
+        SecretLocation::Header | SecretLocation::Query => match request.name {
+            Some(name) if !name.trim().is_empty() => name.trim().to_string(),
+            _ => return Err(AppError::bad_request("name is required for this location")),
+        },
+    };
+    let header_safe = match request.location {
+        SecretLocation::Header => {
+            HeaderName::try_from(name.as_str()).is_ok()
+                && HeaderValue::try_from(request.value.as_str()).is_ok()
+        }
+        SecretLocation::Bearer => {
+            HeaderValue::try_from(format!("Bearer {}", request.value)).is_ok()
         }
-        Ok(None) => Err(StatusCode::NOT_FOUND),
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+        SecretLocation::Query => true,
+    };
+    if request.value.is_empty() || !header_safe {
+        return Err(AppError::bad_request(
+            "value must be non-empty and valid in an HTTP header",
+        ));
     }
+
+    let encrypted = keyring
+        .encrypt(api_id, request.value.as_bytes())
+        .map_err(AppError::internal)?;
+
+    let secret = state
+        .secret_repo
+        .upsert_secret(
+            api_id,
+            request.location,
+            &name,
+            &encrypted.ciphertext,
+            &encrypted.nonce,
+            &encrypted.key_id,
+        )
+        .await?;
+    Ok(Json(secret))
 }
 
-pub async fn update_api(
+pub async fn list_api_secrets(
     State(state): State<AppState>,
     Path((user_id, api_id)): Path<(Uuid, Uuid)>,
-    Json(request): Json<CreateApiRequest>,
-) -> Result<Json<Api>, StatusCode> {
-    match state.user_repo.get_user_by_id(user_id).await {
-        Ok(Some(_)) => {}
-        Ok(None) => return Err(StatusCode::NOT_FOUND),
-        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
-    }
-
-    match state.api_repo.get_api_by_id(api_id).await {
-        Ok(Some(api)) => {
-            if api.user_id != user_id {
-                return Err(StatusCode::NOT_FOUND);
-            }
-        }
-        Ok(None) => return Err(StatusCode::NOT_FOUND),
-        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+    auth: AuthUser,
+) -> Result<Json<Vec<ApiSecret>>, AppError> {
+    auth.authorize(user_id, ApiKeyScope::ApisRead)?;
+    find_api(&state, user_id, api_id).await?;
+
+    let secrets = state.secret_repo.get_secrets_by_api_id(api_id).await?;
+    Ok(Json(secrets))
+}
+
+pub async fn delete_api_secret(
+    State(state): State<AppState>,
+    Path((user_id, api_id, secret_id)): Path<(Uuid, Uuid, Uuid)>,
+    auth: AuthUser,
+) -> Result<StatusCode, AppError> {
+    auth.authorize(user_id, ApiKeyScope::ApisWrite)?;
+    find_api(&state, user_id, api_id).await?;
+
+    if state.secret_repo.delete_secret(api_id, secret_id).await? {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(AppError::not_found("Secret not found"))
     }
+}
+
+pub async fn put_api_tls(
+    State(state): State<AppState>,
+    Path((user_id, api_id)): Path<(Uuid, Uuid)>,
+    auth: AuthUser,
+    Json(request): Json<PutApiTlsRequest>,
+) -> Result<Json<ApiTlsConfig>, AppError> {
+    auth.authorize(user_id, ApiKeyScope::ApisWrite)?;
+    find_api(&state, user_id, api_id).await?;
+
+    let Some(keyring) = state.secrets.as_ref() else {
+        return Err(AppError::service_unavailable(
+            "Secrets are not configured on this server",
+        ));
+    };
+
+    tls::validate(&request)
+        .map_err(|e| AppError::bad_request(format!("Invalid TLS settings: {}", e)))?;
+
+    let encrypted = serde_json::to_vec(&request)
+        .map_err(anyhow::Error::from)
+        .and_then(|plaintext| keyring.encrypt(api_id, &plaintext))
+        .map_err(AppError::internal)?;
 
-    match state.api_repo.update_api(api_id, request).await {
-        Ok(Some(api)) => Ok(Json(api)),
-        Ok(None) => Err(StatusCode::NOT_FOUND),
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    let config = state
+        .tls_repo
+        .upsert_tls_config(
+            api_id,
+            request.ca_bundle.is_some(),
+            request.client_certificate.is_some(),
+            &encrypted.ciphertext,
+            &encrypted.nonce,
+            &encrypted.key_id,
+        )
+        .await?;
+    Ok(Json(config))
+}
+
+pub async fn get_api_tls(
+    State(state): State<AppState>,
+    Path((user_id, api_id)): Path<(Uuid, Uuid)>,
+    auth: AuthUser,
+) -> Result<Json<ApiTlsConfig>, AppError> {
+    auth.authorize(user_id, ApiKeyScope::ApisRead)?;
+    find_api(&state, user_id, api_id).await?;
+
+    match state.tls_repo.get_tls_config(api_id).await? {
+        Some(config) => Ok(Json(config)),
+        None => Err(AppError::not_found("This API has no TLS settings")),
     }
 }
 
-pub async fn list_all_apis(
+pub async fn delete_api_tls(
     State(state): State<AppState>,
-) -> Result<Json<Vec<Api>>, StatusCode> {
-    match state.api_repo.get_all_apis().await {
-        Ok(apis) => {
-            if apis.is_empty() {
-                return Err(StatusCode::NOT_FOUND);
-            }
-            
-            Ok(Json(apis))
-        },
-        Err(_) => {
-            Err(StatusCode::INTERNAL_SERVER_ERROR)
-        },
+    Path((user_id, api_id)): Path<(Uuid, Uuid)>,
+    auth: AuthUser,
+) -> Result<StatusCode, AppError> {
+    auth.authorize(user_id, ApiKeyScope::ApisWrite)?;
+    find_api(&state, user_id, api_id).await?;
+
+    if state.tls_repo.delete_tls_config(api_id).await? {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(AppError::not_found("This API has no TLS settings"))
     }
 }
 
-pub async fn delete_api(
+pub async fn put_api_mock(
     State(state): State<AppState>,
     Path((user_id, api_id)): Path<(Uuid, Uuid)>,
-) -> Result<StatusCode, StatusCode> {
-    match state.user_repo.get_user_by_id(user_id).await {
-        Ok(Some(_)) => {}
-        Ok(None) => return Err(StatusCode::NOT_FOUND),
-        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+    auth: AuthUser,
+    Json(request): Json<CreateApiMockRequest>,
+) -> Result<Json<ApiMock>, AppError> {
+    auth.authorize(user_id, ApiKeyScope::ApisWrite)?;
+    let api = find_api(&state, user_id, api_id).await?;
+
+    let status = request.status.unwrap_or(200);
+    let headers_valid = request.headers.iter().all(|(name, value)| {
+        HeaderName::try_from(name.as_str()).is_ok() && HeaderValue::try_from(value.as_str()).is_ok()
+    });
+    if !request.path.starts_with('/') {
+        return Err(AppError::bad_request("path must start with /"));
+    }
+    if !(100..=599).contains(&status) {
+        return Err(AppError::bad_request("status must be between 100 and 599"));
+    }
+    if !headers_valid {
+        return Err(AppError::bad_request("headers must be valid HTTP headers"));
+    }
+    // APIs with endpoints are mocked per endpoint, keyed by its path template.
+    let published = api.endpoints.is_empty()
+        || api.endpoints.iter().any(|endpoint| {
+            endpoint.path == request.path && endpoint.method.as_str() == request.method.as_str()
+        });
+    if !published {
+        return Err(AppError::bad_request(format!(
+            "{} {} is not a published endpoint",
+            request.method.as_str(),
+            request.path
+        )));
     }
 
-    match state.api_repo.get_api_by_id(api_id).await {
-        Ok(Some(api)) => {
-            if api.user_id != user_id {
-                return Err(StatusCode::NOT_FOUND);
+    let mut headers = request.headers;
+    let body = match request.body {
+        serde_json::Value::String(body) => body,
+        body => {
+            if !headers
+                .keys()
+                .any(|name| name.eq_ignore_ascii_case("content-type"))
+            {
+                headers.insert("content-type".to_string(), "application/json".to_string());
             }
+            body.to_string()
+        }
+    };
+
+    let mock = state
+        .mock_repo
+        .upsert_mock(
+            api_id,
+            &request.method,
+            &request.path,
+            status,
+            &headers,
+            &body,
+        )
+        .await?;
+    Ok(Json(mock))
+}
+
+pub async fn list_api_mocks(
+    State(state): State<AppState>,
+    Path((user_id, api_id)): Path<(Uuid, Uuid)>,
+    auth: AuthUser,
+) -> Result<Json<Vec<ApiMock>>, AppError> {
+    auth.authorize(user_id, ApiKeyScope::ApisRead)?;
+    find_api(&state, user_id, api_id).await?;
+
+    let mocks = state.mock_repo.get_mocks_by_api_id(api_id).await?;
+    Ok(Json(mocks))
+}
+
+pub async fn delete_api_mock(
+    State(state): State<AppState>,
+    Path((user_id, api_id, mock_id)): Path<(Uuid, Uuid, Uuid)>,
+    auth: AuthUser,
+) -> Result<StatusCode, AppError> {
+    auth.authorize(user_id, ApiKeyScope::ApisWrite)?;
+    find_api(&state, user_id, api_id).await?;
+
+    if state.mock_repo.delete_mock(api_id, mock_id).await? {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(AppError::not_found("Mock not found"))
+    }
+}
+
+/// An API's call log, newest first. At most `limit` (100 by default, up to
+/// 1000) entries are returned.
+pub async fn list_call_logs(
+    State(state): State<AppState>,
+    Path((user_id, api_id)): Path<(Uuid, Uuid)>,
+    auth: AuthUser,
+    Query(filter): Query<CallLogFilter>,
+) -> Result<Json<Vec<CallLog>>, AppError> {
+    auth.authorize(user_id, ApiKeyScope::AnalyticsRead)?;
+    find_api(&state, user_id, api_id).await?;
+
+    let limit = filter.limit.unwrap_or(100).clamp(1, 1000);
+    let logs = state
+        .call_log_repo
+        .get_call_logs(api_id, &filter, limit.into())
+        .await?;
+    Ok(Json(logs))
+}
+
+/// Creates a provider API key. The key itself is only returned here; just its
+/// hash is stored.
+pub async fn create_api_key(
+    State(state): State<AppState>,
+    Path(user_id): Path<Uuid>,
+    auth: AuthUser,
+    Json(mut request): Json<CreateApiKeyRequest>,
+) -> Result<Json<ApiKey>, AppError> {
+    auth.require_session(user_id)?;
+    find_user(&state, user_id).await?;
+
+    if request.name.trim().is_empty() {
+        return Err(AppError::bad_request("name is required"));
+    }
+    let mut scopes = Vec::new();
+    for scope in request.scopes {
+        if !scopes.contains(&scope) {
+            scopes.push(scope);
         }
-        Ok(None) => return Err(StatusCode::NOT_FOUND),
-        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
     }
+    request.scopes = scopes;
+    if request.scopes.is_empty() {
+        return Err(AppError::bad_request("At least one scope is required"));
+    }
+
+    let key = generate_api_key();
+    let mut api_key = state
+        .api_key_repo
+        .create_api_key(user_id, &hash_api_key(&key), api_key_prefix(&key), request)
+        .await?;
+    api_key.key = Some(key);
+    Ok(Json(api_key))
+}
+
+pub async fn list_api_keys(
+    State(state): State<AppState>,
+    Path(user_id): Path<Uuid>,
+    auth: AuthUser,
+) -> Result<Json<Vec<ApiKey>>, AppError> {
+    auth.require_session(user_id)?;
+
+    let keys = state.api_key_repo.get_api_keys_by_user_id(user_id).await?;
+    Ok(Json(keys))
+}
+
+pub async fn revoke_api_key(
+    State(state): State<AppState>,
+    Path((user_id, key_id)): Path<(Uuid, Uuid)>,
+    auth: AuthUser,
+) -> Result<StatusCode, AppError> {
+    auth.require_session(user_id)?;
 
-    match state.api_repo.delete_api(api_id).await {
-        Ok(true) => Ok(StatusCode::NO_CONTENT),
-        Ok(false) => Err(StatusCode::NOT_FOUND),
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    if state.api_key_repo.revoke_api_key(user_id, key_id).await? {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(AppError::not_found("API key not found"))
     }
 }
@@ -0,0 +1,298 @@
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue};
+use shared::HeaderRules;
+use std::net::IpAddr;
+
+/// Connection-level headers (RFC 7230, section 6.1) that describe a single hop
+/// and are never forwarded in either direction.
+const HOP_BY_HOP_HEADERS: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Client headers that only make sense between the client and Enigma. The
+/// forwarding headers are replaced by the ones Enigma sets itself.
+const CLIENT_ONLY_HEADERS: &[&str] = &[
+    "host",
+    "content-length",
+    "x-payment",
+    "forwarded",
+    "x-forwarded-host",
+    "x-forwarded-proto",
+];
+
+/// Upstream headers that would be confused with the paygate's own.
+const UPSTREAM_ONLY_HEADERS: &[&str] = &["x-payment-response"];
+
+/// Upstream headers dropped from responses unless the API's policy says otherwise.
+const DEFAULT_DENIED_RESPONSE_HEADERS: &[&str] = &["set-cookie", "server", "x-powered-by"];
+
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+const X_FORWARDED_HOST: HeaderName = HeaderName::from_static("x-forwarded-host");
+const X_FORWARDED_PROTO: HeaderName = HeaderName::from_static("x-forwarded-proto");
+
+/// What the upstream is told about the original request through `X-Forwarded-*`.
+#[derive(Clone, Debug)]
+pub struct ForwardedInfo {
+    pub client_ip: Option<IpAddr>,
+    pub host: Option<HeaderValue>,
+    pub proto: String,
+}
+
+/// Removes hop-by-hop headers, including any listed in the `Connection` header.
+pub fn strip_hop_by_hop(headers: &mut HeaderMap) {
+    let listed: Vec<HeaderName> = headers
+        .get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|name| HeaderName::try_from(name.trim()).ok())
+        .collect();
+    for name in listed {
+        headers.remove(name);
+    }
+    for name in HOP_BY_HOP_HEADERS {
+        headers.remove(*name);
+    }
+}
+
+/// Headers to send upstream: the client's headers filtered by the fixed rules
+/// and the API's policy, plus `X-Forwarded-For`, `-Host` and `-Proto`. The
+/// forwarding headers are only subject to the policy's `deny` list.
+pub fn request_headers(
+    mut headers: HeaderMap,
+    rules: &HeaderRules,
+    forwarded: &ForwardedInfo,
+) -> HeaderMap {
+    strip_hop_by_hop(&mut headers);
+    for name in CLIENT_ONLY_HEADERS {
+        headers.remove(*name);
+    }
+    apply_rules(&mut headers, rules, &[]);
+
+    if let Some(ip) = forwarded.client_ip {
+        let chain = match headers
+            .get(&X_FORWARDED_FOR)
+            .and_then(|value| value.to_str().ok())
+        {
+            Some(previous) => format!("{}, {}", previous, ip),
+            None => ip.to_string(),
+        };
+        if let Ok(value) = HeaderValue::try_from(chain) {
+            headers.insert(X_FORWARDED_FOR, value);
+        }
+    }
+    if let Some(host) = &forwarded.host {
+        headers.insert(X_FORWARDED_HOST, host.clone());
+    }
+    if let Ok(proto) = HeaderValue::try_from(forwarded.proto.as_str()) {
+        headers.insert(X_FORWARDED_PROTO, proto);
+    }
+    for name in [X_FORWARDED_FOR, X_FORWARDED_HOST, X_FORWARDED_PROTO] {
+        if is_listed(rules.deny.as_deref(), &name) {
+            headers.remove(name);
+        }
+    }
+
+    headers
+}
+
+/// Headers to return to the client from an upstream response.
+pub fn response_headers(headers: &HeaderMap, rules: &HeaderRules) -> HeaderMap {
+    let mut headers = headers.clone();
+    strip_hop_by_hop(&mut headers);
+    for name in UPSTREAM_ONLY_HEADERS {
+        headers.remove(*name);
+    }
+    apply_rules(&mut headers, rules, DEFAULT_DENIED_RESPONSE_HEADERS);
+    headers
+}
+
+/// Drops headers missing from `allow` (when set) or listed in `deny`. Without a
+/// `deny` list, `denied_by_default` applies to everything `allow` doesn't name.
+fn apply_rules(headers: &mut HeaderMap, rules: &HeaderRules, denied_by_default: &[&str]) {
+    let names: Vec<HeaderName> = headers.keys().cloned().collect();
+    for name in names {
+        let allowed = is_listed(rules.allow.as_deref(), &name);
+        let denied = match &rules.deny {
+            Some(deny) => is_listed(Some(deny), &name),
+            None => !allowed && denied_by_default.contains(&name.as_str()),
+        };
+        if denied || (rules.allow.is_some() && !allowed) {
+            headers.remove(name);
+        }
+    }
+}
+
+fn is_listed(list: Option<&[String]>, name: &HeaderName) -> bool {
+    list.is_some_and(|list| {
+        list.iter()
+            .any(|listed| listed.eq_ignore_ascii_case(name.as_str()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(
+                HeaderName::try_from(*name).unwrap(),
+                HeaderValue::try_from(*value).unwrap(),
+            );
+        }
+        headers
+    }
+
+    fn rules(allow: Option<&[&str]>, deny: Option<&[&str]>) -> HeaderRules {
+        let list = |names: &[&str]| names.iter().map(|name| name.to_string()).collect();
+        HeaderRules {
+            allow: allow.map(list),
+            deny: deny.map(list),
+        }
+    }
+
+    fn forwarded() -> ForwardedInfo {
+        ForwardedInfo {
+            client_ip: Some(IpAddr::from([203, 0, 113, 7])),
+            host: Some(HeaderValue::from_static("enigma.test")),
+            proto: "https".to_string(),
+        }
+    }
+
+    fn names(headers: &HeaderMap) -> Vec<&str> {
+        let mut names: Vec<&str> = headers.keys().map(HeaderName::as_str).collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn strips_hop_by_hop_headers() {
+        let mut headers = headers(&[
+            ("connection", "keep-alive"),
+            ("keep-alive", "timeout=5"),
+            ("proxy-authorization", "Basic abc"),
+            ("te", "trailers"),
+            ("transfer-encoding", "chunked"),
+            ("upgrade", "h2c"),
+            ("accept", "application/json"),
+        ]);
+        strip_hop_by_hop(&mut headers);
+        assert_eq!(names(&headers), ["accept"]);
+    }
+
+    #[test]
+    fn strips_headers_named_in_connection() {
+        let mut headers = headers(&[
+            ("connection", "X-Internal-Token, x-trace"),
+            ("connection", "close"),
+            ("x-internal-token", "secret"),
+            ("x-trace", "1"),
+            ("x-kept", "1"),
+        ]);
+        strip_hop_by_hop(&mut headers);
+        assert_eq!(names(&headers), ["x-kept"]);
+    }
+
+    #[test]
+    fn forwards_client_headers_with_forwarding_info() {
+        let headers = request_headers(
+            headers(&[
+                ("host", "enigma.test"),
+                ("x-payment", "payload"),
+                ("x-forwarded-for", "198.51.100.1"),
+                ("x-forwarded-host", "spoofed.test"),
+                ("authorization", "Bearer upstream"),
+            ]),
+            &HeaderRules::default(),
+            &forwarded(),
+        );
+        assert_eq!(
+            names(&headers),
+            [
+                "authorization",
+                "x-forwarded-for",
+                "x-forwarded-host",
+                "x-forwarded-proto"
+            ]
+        );
+        assert_eq!(headers["x-forwarded-for"], "198.51.100.1, 203.0.113.7");
+        assert_eq!(headers["x-forwarded-host"], "enigma.test");
+        assert_eq!(headers["x-forwarded-proto"], "https");
+    }
+
+    #[test]
+    fn applies_request_allow_and_deny_lists() {
+        let client = headers(&[
+            ("accept", "application/json"),
+            ("authorization", "Bearer upstream"),
+            ("cookie", "session=1"),
+        ]);
+
+        let allowed = request_headers(
+            client.clone(),
+            &rules(Some(&["Accept"]), None),
+            &forwarded(),
+        );
+        assert_eq!(
+            names(&allowed),
+            [
+                "accept",
+                "x-forwarded-for",
+                "x-forwarded-host",
+                "x-forwarded-proto"
+            ]
+        );
+
+        let denied = request_headers(
+            client,
+            &rules(None, Some(&["cookie", "X-Forwarded-For"])),
+            &forwarded(),
+        );
+        assert_eq!(
+            names(&denied),
+            [
+                "accept",
+                "authorization",
+                "x-forwarded-host",
+                "x-forwarded-proto"
+            ]
+        );
+    }
+
+    #[test]
+    fn filters_response_headers() {
+        let upstream = headers(&[
+            ("content-type", "application/json"),
+            ("set-cookie", "id=1"),
+            ("server", "nginx"),
+            ("x-powered-by", "php"),
+            ("x-payment-response", "forged"),
+            ("transfer-encoding", "chunked"),
+            ("x-request-id", "abc"),
+        ]);
+
+        let default = response_headers(&upstream, &HeaderRules::default());
+        assert_eq!(names(&default), ["content-type", "x-request-id"]);
+
+        let allowed = response_headers(&upstream, &rules(Some(&["Content-Type", "Server"]), None));
+        assert_eq!(names(&allowed), ["content-type", "server"]);
+
+        let denied = response_headers(&upstream, &rules(None, Some(&["x-request-id"])));
+        assert_eq!(
+            names(&denied),
+            ["content-type", "server", "set-cookie", "x-powered-by"]
+        );
+
+        let forged = response_headers(&upstream, &rules(Some(&["x-payment-response"]), None));
+        assert!(forged.is_empty());
+    }
+}
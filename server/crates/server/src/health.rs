@@ -0,0 +1,237 @@
+use crate::balancer;
+use crate::proxy::build_upstream_url;
+use crate::tls::UpstreamTls;
+use crate::upstream::UpstreamClients;
+use database::{ApiRepository, HealthCheckRepository, HealthCheckRow};
+use futures_util::StreamExt;
+use reqwest::Client;
+use shared::{Api, HealthState};
+use std::env;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use url::Url;
+
+/// How many APIs are probed at the same time.
+const CONCURRENCY: usize = 16;
+
+/// Checks older than this many intervals no longer say anything about an API.
+const STALE_AFTER_INTERVALS: u32 = 3;
+
+#[derive(Clone, Debug)]
+pub struct HealthCheckerConfig {
+    pub interval: Duration,
+    pub timeout: Duration,
+    pub retention_days: u32,
+}
+
+impl HealthCheckerConfig {
+    pub fn from_env() -> Self {
+        let number = |name: &str, default: u64| {
+            env::var(name)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default)
+        };
+        Self {
+            interval: Duration::from_secs(number("HEALTH_CHECK_INTERVAL_SECS", 60).max(1)),
+            timeout: Duration::from_secs(number("HEALTH_CHECK_TIMEOUT_SECS", 10)),
+            retention_days: number("HEALTH_CHECK_RETENTION_DAYS", 30).max(1) as u32,
+        }
+    }
+
+    /// How far back status reports can look.
+    pub fn max_window_hours(&self) -> u32 {
+        self.retention_days * 24
+    }
+
+    /// How long a check says anything about an API or target.
+    pub fn stale_after(&self) -> Duration {
+        self.interval * STALE_AFTER_INTERVALS
+    }
+
+    /// The state shown for `api` given its most recent check.
+    pub fn state(&self, api: &Api, latest: Option<&HealthCheckRow>) -> HealthState {
+        let stale_before = chrono::Utc::now()
+            - chrono::Duration::from_std(self.stale_after()).unwrap_or(chrono::Duration::MAX);
+        match latest {
+            _ if !is_enabled(api) => HealthState::Unknown,
+            Some(check) if check.checked_at < stale_before => HealthState::Unknown,
+            Some(check) if check.healthy => HealthState::Healthy,
+            Some(_) => HealthState::Degraded,
+            None => HealthState::Unknown,
+        }
+    }
+}
+
+fn is_enabled(api: &Api) -> bool {
+    !api.proxy_config
+        .as_ref()
+        .and_then(|config| config.health_check.as_ref())
+        .is_some_and(|check| check.disabled)
+}
+
+/// Probes every API once per interval and prunes checks past the retention
+/// period. Probes go through the same `UpstreamPolicy` and TLS settings as
+/// proxied calls.
+pub fn spawn_health_checker(
+    api_repo: ApiRepository,
+    health_repo: HealthCheckRepository,
+    upstream: UpstreamClients,
+    tls: UpstreamTls,
+    config: HealthCheckerConfig,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(config.interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+            let apis = match api_repo.get_all_apis().await {
+                Ok(apis) => apis,
+                Err(e) => {
+                    tracing::warn!("Failed to load APIs for health checks: {e:#}");
+                    continue;
+                }
+            };
+            futures_util::stream::iter(apis.into_iter().filter(is_enabled))
+                .for_each_concurrent(CONCURRENCY, |api| {
+                    check(&upstream, &tls, &health_repo, &config, api)
+                })
+                .await;
+
+            let cutoff = chrono::Utc::now() - chrono::Duration::days(config.retention_days.into());
+            if let Err(e) = health_repo.prune_checks(cutoff).await {
+                tracing::warn!("Failed to prune health checks: {e:#}");
+            }
+        }
+    })
+}
+
+/// Probes the API's `base_url`, or each of its load balancing targets. For
+/// balanced APIs a check per target is recorded, and the API counts as healthy
+/// while any target is.
+async fn check(
+    upstream: &UpstreamClients,
+    tls: &UpstreamTls,
+    health_repo: &HealthCheckRepository,
+    config: &HealthCheckerConfig,
+    api: Api,
+) {
+    let path = api
+        .proxy_config
+        .as_ref()
+        .and_then(|config| config.health_check.as_ref())
+        .and_then(|check| check.path.as_deref());
+    let settings = upstream.settings(api.proxy_config.as_ref());
+    let client = match tls.client(upstream, &api, &settings).await {
+        Ok(client) => client,
+        Err(_) => {
+            let outcome = Outcome::failed("TLS settings could not be loaded");
+            record(health_repo, &api, None, &outcome).await;
+            return;
+        }
+    };
+
+    let outcome = match balancer::config(api.proxy_config.as_ref()) {
+        Some(balancing) => {
+            let probes = balancing
+                .targets
+                .iter()
+                .map(|target| probe_base(upstream, &client, config, &target.url, path));
+            let outcomes = futures_util::future::join_all(probes).await;
+            for (target, outcome) in balancing.targets.iter().zip(&outcomes) {
+                record(health_repo, &api, Some(&target.url), outcome).await;
+            }
+            outcomes
+                .into_iter()
+                .filter(|outcome| outcome.healthy)
+                .min_by_key(|outcome| outcome.latency_ms)
+                .unwrap_or_else(|| Outcome::failed("Every target failed"))
+        }
+        None => probe_base(upstream, &client, config, &api.base_url, path).await,
+    };
+    record(health_repo, &api, None, &outcome).await;
+}
+
+async fn record(
+    health_repo: &HealthCheckRepository,
+    api: &Api,
+    target: Option<&str>,
+    outcome: &Outcome,
+) {
+    if let Err(e) = health_repo
+        .record_check(
+            api.id,
+            target,
+            outcome.healthy,
+            outcome.status_code,
+            outcome.latency_ms,
+            outcome.error.as_deref(),
+        )
+        .await
+    {
+        tracing::warn!("Failed to record health check for API {}: {e:#}", api.id);
+    }
+}
+
+async fn probe_base(
+    upstream: &UpstreamClients,
+    client: &Client,
+    config: &HealthCheckerConfig,
+    base_url: &str,
+    path: Option<&str>,
+) -> Outcome {
+    let url = match path {
+        Some(path) => build_upstream_url(base_url, path, None).ok(),
+        None => Url::parse(base_url).ok(),
+    };
+    match url {
+        Some(url) if upstream.policy().check_url(&url).is_ok() => probe(client, config, url).await,
+        Some(_) => Outcome::failed("Refused by the upstream policy"),
+        None => Outcome::failed("Invalid health check URL"),
+    }
+}
+
+struct Outcome {
+    healthy: bool,
+    status_code: Option<i32>,
+    latency_ms: Option<i32>,
+    error: Option<String>,
+}
+
+impl Outcome {
+    fn failed(error: &str) -> Self {
+        Self {
+            healthy: false,
+            status_code: None,
+            latency_ms: None,
+            error: Some(error.to_string()),
+        }
+    }
+}
+
+/// Sends a GET to `url`. Any response below 500 counts as healthy. Errors are
+/// kept generic since they are shown on the public status page.
+async fn probe(client: &Client, config: &HealthCheckerConfig, url: Url) -> Outcome {
+    let started = Instant::now();
+    let result = tokio::time::timeout(config.timeout, client.get(url).send()).await;
+    let latency_ms = i32::try_from(started.elapsed().as_millis()).unwrap_or(i32::MAX);
+
+    match result {
+        Ok(Ok(response)) => {
+            let status = response.status();
+            Outcome {
+                healthy: !status.is_server_error(),
+                status_code: Some(status.as_u16() as i32),
+                latency_ms: Some(latency_ms),
+                error: status
+                    .is_server_error()
+                    .then(|| format!("Responded with {}", status.as_u16())),
+            }
+        }
+        Ok(Err(e)) if e.is_connect() => Outcome::failed("Connection failed"),
+        Ok(Err(e)) if e.is_timeout() => Outcome::failed("Timed out"),
+        Ok(Err(_)) => Outcome::failed("Request failed"),
+        Err(_) => Outcome::failed("Timed out"),
+    }
+}
@@ -1,5 +1,26 @@
+pub mod auth;
+pub mod balancer;
+pub mod body;
+pub mod breaker;
+pub mod cache;
+pub mod call_log;
+pub mod endpoints;
+pub mod error;
+pub mod extract;
+pub mod graphql;
 pub mod handlers;
+pub mod health;
+pub mod headers;
 pub mod proxy;
+pub mod rate_limit;
+pub mod sandbox;
+pub mod secrets;
+pub mod ssrf;
+pub mod tls;
+pub mod transform;
+pub mod upstream;
+pub mod webhooks;
+pub mod websocket;
 pub mod app;
 
 pub use app::*;
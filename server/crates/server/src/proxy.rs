@@ -1,61 +1,118 @@
 use crate::app::AppState;
+use crate::body;
+use crate::cache::{
+    cacheable_ttl, CachePlan, CacheStatus, CachedResponse, ResponseCache, CACHE_STATUS_HEADER,
+    MAX_TTL_SECONDS,
+};
+use crate::call_log::CallRecord;
+use crate::endpoints::{self, EndpointRejection};
+use crate::graphql;
+use crate::headers::{self, ForwardedInfo};
+use crate::rate_limit::Caller;
+use crate::sandbox::{self, Recording};
+use crate::transform::{self, TemplateContext};
+use crate::webhooks;
+use crate::websocket;
 use anyhow::Result;
 use axum::{
-    body::Bytes,
-    extract::{Path, State},
-    http::StatusCode,
-    response::Json,
+    body::{to_bytes, Body, Bytes},
+    extract::{ws::WebSocketUpgrade, ConnectInfo, FromRequestParts, Path, Request, State},
+    http::{header, request::Parts, HeaderMap, HeaderName, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Json, Response},
+    Extension,
 };
-use reqwest::{Client, Method};
+use futures_util::StreamExt;
+use middleware::PriceOverride;
+use reqwest::Method;
+use serde::Deserialize;
 use serde_json::Value;
-use shared::ProxyRequest;
+use shared::{
+    Api, ApiKind, BodyEncoding, HeaderPolicy, ProxyRequest, ResponseFormat, SecretLocation,
+};
 use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::time::{timeout_at, Instant};
+use url::Url;
 use uuid::Uuid;
 
-pub async fn proxy_request(
+/// A request to be sent to an API's upstream, independent of whether it arrived
+/// as a `ProxyRequest` document or through the transparent proxy route.
+#[derive(Clone)]
+pub struct UpstreamRequest {
+    pub method: Method,
+    pub path: String,
+    pub query: Option<String>,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+    pub response_format: ResponseFormat,
+}
+
+/// The API and normalized upstream request for a proxy call. Built and checked
+/// against the published endpoints by the `prepare_*` middleware, which runs
+/// before the paygate so invalid requests are refused without taking payment.
+#[derive(Clone)]
+pub struct PreparedRequest {
+    pub api: Api,
+    pub upstream: UpstreamRequest,
+    pub cache: Option<CachePlan>,
+    /// Length of the paid window when the request is a WebSocket upgrade.
+    pub session: Option<Duration>,
+    /// The caller's request, for the API's response transformation templates.
+    pub context: TemplateContext,
+    /// Set while the API's sandbox records live responses as mocks.
+    pub recording: Option<Recording>,
+    /// Set when the route keeps a call log.
+    pub call: Option<CallRecord>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TransparentPath {
+    pub api_id: Uuid,
+}
+
+/// Prepares `POST /users/{user_id}/apis/{api_id}`, whose body is a `ProxyRequest`
+/// document describing the upstream call.
+pub async fn prepare_proxy_request(
     State(state): State<AppState>,
     Path((user_id, api_id)): Path<(Uuid, Uuid)>,
-    body: Bytes,
-) -> Result<Json<Value>, StatusCode> {
-    let proxy_request: ProxyRequest = match serde_json::from_slice(&body) {
-        Ok(req) => req,
-        Err(_) => return Err(StatusCode::BAD_REQUEST),
-    };
-
+    req: Request,
+    next: Next,
+) -> Response {
+    let (parts, body) = req.into_parts();
     let api = match state.api_repo.get_api_by_id(api_id).await {
         Ok(Some(api)) => {
             if api.user_id != user_id {
-                return Err(StatusCode::NOT_FOUND);
+                return StatusCode::NOT_FOUND.into_response();
             }
             api
         }
-        Ok(None) => return Err(StatusCode::NOT_FOUND),
-        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
     };
 
-    let client = Client::new();
-    let path = proxy_request.path.unwrap_or_else(|| "/".to_string());
-    let target_url = format!("{}{}", api.base_url, path);
-
-    let query_string = if let Some(query_params) = &proxy_request.query_params {
-        if let Some(params_obj) = query_params.as_object() {
-            let pairs: Vec<String> = params_obj
-                .iter()
-                .map(|(k, v)| format!("{}={}", k, v))
-                .collect();
-            if !pairs.is_empty() {
-                format!("?{}", pairs.join("&"))
-            } else {
-                String::new()
-            }
-        } else {
-            String::new()
-        }
-    } else {
-        String::new()
+    let body = match read_request_body(&state, &api, &parts, body).await {
+        Ok(body) => body,
+        Err(response) => return response,
+    };
+    let proxy_request: ProxyRequest = match serde_json::from_slice(&body) {
+        Ok(req) => req,
+        Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+    };
+
+    // A query string embedded in `path` is kept and the encoded `query_params` are appended to it.
+    let raw_path = proxy_request.path.unwrap_or_else(|| "/".to_string());
+    let (path, path_query) = match raw_path.split_once('?') {
+        Some((path, query)) => (path.to_string(), Some(query.to_string())),
+        None => (raw_path, None),
+    };
+    let params_query = proxy_request.query_params.as_ref().and_then(encode_query_params);
+    let query_string = match (path_query.filter(|q| !q.is_empty()), params_query) {
+        (Some(a), Some(b)) => Some(format!("{}&{}", a, b)),
+        (a, b) => a.or(b),
     };
 
-    let full_url = format!("{}{}", target_url, query_string);
     let mut request_headers = reqwest::header::HeaderMap::new();
 
     if let Some(headers_obj) = &proxy_request.headers {
@@ -83,35 +140,547 @@ pub async fn proxy_request(
         shared::HttpMethod::OPTIONS => Method::OPTIONS,
     };
 
-    let request_body = if let Some(body_data) = &proxy_request.body {
-        serde_json::to_vec(body_data).unwrap_or_default()
-    } else {
-        Vec::new()
+    let encoding = proxy_request.body_encoding;
+    let request_body = match body::encode(proxy_request.body.as_ref(), encoding) {
+        Ok(body) => body,
+        Err(e) => return json_error(StatusCode::BAD_REQUEST, e),
+    };
+    // The caller's content type, from `content_type` or else `headers`, is kept
+    // over the encoding's, except for multipart bodies whose boundary only the
+    // proxy knows.
+    let multipart = encoding == BodyEncoding::Multipart;
+    let content_type = match proxy_request.content_type {
+        Some(content_type) if !multipart => match HeaderValue::try_from(content_type) {
+            Ok(content_type) => Some(content_type),
+            Err(_) => {
+                return json_error(StatusCode::BAD_REQUEST, "Invalid content_type".to_string())
+            }
+        },
+        None if !multipart && request_headers.contains_key(header::CONTENT_TYPE) => None,
+        _ => request_body.content_type,
+    };
+    if let Some(content_type) = content_type {
+        request_headers.insert(header::CONTENT_TYPE, content_type);
+    }
+
+    let upstream_request = UpstreamRequest {
+        method,
+        path,
+        query: query_string,
+        headers: request_headers,
+        body: request_body.bytes,
+        response_format: proxy_request.response_format.unwrap_or_default(),
     };
 
-    let response = match client
-        .request(method, &full_url)
-        .headers(request_headers)
-        .body(request_body)
-        .send()
+    run_prepared(&state, parts, api, upstream_request, next).await
+}
+
+/// Prepares `ANY /proxy/{api_id}/{*path}`, which forwards the caller's real
+/// method, path, query, headers and body so existing HTTP clients can treat
+/// Enigma as the upstream.
+pub async fn prepare_transparent_request(
+    State(state): State<AppState>,
+    Path(params): Path<TransparentPath>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let (parts, body) = req.into_parts();
+    let api = match state.api_repo.get_api_by_id(params.api_id).await {
+        Ok(Some(api)) => api,
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    let body = match read_request_body(&state, &api, &parts, body).await {
+        Ok(body) => body,
+        Err(response) => return response,
+    };
+
+    let upstream_request = UpstreamRequest {
+        method: parts.method.clone(),
+        path: transparent_path(&parts),
+        query: parts.uri.query().map(|q| q.to_string()),
+        headers: parts.headers.clone(),
+        body,
+        response_format: ResponseFormat::Passthrough,
+    };
+
+    run_prepared(&state, parts, api, upstream_request, next).await
+}
+
+/// The part of a `/{route}/{api_id}/{*path}` URI after the API id. Taken from
+/// the raw URI rather than the decoded `{*path}` capture so that escaped
+/// characters like `%2F` reach the upstream untouched.
+pub fn transparent_path(parts: &Parts) -> String {
+    parts
+        .uri
+        .path()
+        .splitn(4, '/')
+        .nth(3)
+        .map(|rest| format!("/{}", rest))
+        .unwrap_or_else(|| "/".to_string())
+}
+
+/// Buffers a proxy call's body, refusing anything over the API's
+/// `max_request_bytes` with 413. A declared `Content-Length` is checked before
+/// any of the body is read.
+pub async fn read_request_body(
+    state: &AppState,
+    api: &Api,
+    parts: &Parts,
+    body: Body,
+) -> Result<Bytes, Response> {
+    let limit = state
+        .upstream
+        .settings(api.proxy_config.as_ref())
+        .max_request_bytes;
+    let too_large = || {
+        json_error(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("Request body exceeds the limit of {} bytes", limit),
+        )
+    };
+
+    let declared = parts
+        .headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    if declared.is_some_and(|length| length > limit) {
+        return Err(too_large());
+    }
+    to_bytes(body, usize::try_from(limit).unwrap_or(usize::MAX))
+        .await
+        .map_err(|_| too_large())
+}
+
+pub fn json_error(status: StatusCode, error: String) -> Response {
+    (status, Json(serde_json::json!({ "error": error }))).into_response()
+}
+
+/// Rejects requests that don't match a published endpoint, that are over one of
+/// the API's rate limits, or whose upstream is an address the `UpstreamPolicy`
+/// forbids, and fails fast with 503 while the upstream's circuit breaker is open.
+/// Otherwise applies the API's header policy and hands the prepared request on
+/// to the paygate and `proxy_request`.
+///
+/// WebSocket upgrades are only accepted for APIs with a `websocket` config and
+/// are charged its session price. GraphQL operations are checked against the
+/// API's schema and charged by operation name or complexity.
+async fn run_prepared(
+    state: &AppState,
+    mut parts: Parts,
+    mut api: Api,
+    mut upstream: UpstreamRequest,
+    next: Next,
+) -> Response {
+    let call = parts.extensions.get::<CallRecord>().cloned();
+    if let Some(call) = &call {
+        call.request(&api, &upstream);
+    }
+    let endpoint = match endpoints::validate_request(&api, &upstream) {
+        Ok(endpoint) => endpoint,
+        Err(rejection) => return rejection.into_response(),
+    };
+    if let (Some(call), Some(endpoint)) = (&call, &endpoint) {
+        call.endpoint(&endpoint.path);
+    }
+    let caller = Caller::from_parts(&parts);
+    if let Err(limited) = state
+        .rate_limiter
+        .check(&api, endpoint.as_ref(), &caller)
         .await
     {
-        Ok(response) => response,
-        Err(_) => return Err(StatusCode::BAD_GATEWAY),
+        return limited.into_response();
+    }
+    let session = if websocket::is_upgrade_request(&parts.headers) {
+        let Some(config) = api.proxy_config.as_ref().and_then(|c| c.websocket.as_ref()) else {
+            return json_error(
+                StatusCode::BAD_REQUEST,
+                "This API does not accept WebSocket connections".to_string(),
+            );
+        };
+        if let Some(price) = config.price {
+            parts.extensions.insert(PriceOverride(price));
+        }
+        Some(Duration::from_secs(config.session_seconds))
+    } else {
+        None
+    };
+    if api.kind == ApiKind::GraphQL && session.is_none() {
+        let default_price = api
+            .payment_config
+            .as_ref()
+            .map_or(0.0, |payment| payment.cost_per_request);
+        let checked = match (api.graphql.as_ref(), state.graphql.get(&api)) {
+            (Some(config), Some(schema)) => {
+                graphql::check_request(config, &schema, &upstream, default_price)
+            }
+            _ => Err(EndpointRejection::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "The API's GraphQL schema is invalid",
+            )),
+        };
+        match checked {
+            Ok(operation) => {
+                tracing::debug!(
+                    "GraphQL operation {:?} on API {}: complexity {}, depth {}",
+                    operation.name,
+                    api.id,
+                    operation.complexity,
+                    operation.depth
+                );
+                if let Some(price) = operation.price {
+                    parts.extensions.insert(PriceOverride(price));
+                }
+            }
+            Err(rejection) => return rejection.into_response(),
+        }
+    }
+    let recording =
+        sandbox::recording(&api, endpoint.as_ref(), &upstream).filter(|_| session.is_none());
+    // Calls to load balanced APIs go to the picked target from here on.
+    if let Some(target) = state.balancer.pick(&api, &state.upstream).await {
+        api.base_url = target;
+    }
+    // Hostnames are checked by the upstream resolver when connecting; IP literals
+    // never reach it, so they are refused here before any payment is taken.
+    let base_url = Url::parse(&api.base_url).ok();
+    if let Some(base_url) = &base_url {
+        if let Err(e) = state.upstream.policy().check_url(base_url) {
+            tracing::warn!("Refusing to proxy API {}: {}", api.id, e);
+            return StatusCode::BAD_GATEWAY.into_response();
+        }
+    }
+
+    let forwarded = ForwardedInfo {
+        client_ip: parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|info| info.0.ip()),
+        host: parts.headers.get(header::HOST).cloned(),
+        proto: state.base_url.scheme().to_string(),
     };
+    let context = TemplateContext::new(api.id, &upstream, forwarded.client_ip);
+    upstream.headers = headers::request_headers(
+        std::mem::take(&mut upstream.headers),
+        &header_policy(&api).request,
+        &forwarded,
+    );
+    // Rewrites happen before the cache key is computed so requests that end up
+    // identical upstream share cache entries.
+    if let Some(transforms) = api.proxy_config.as_ref().and_then(|c| c.transforms.as_ref()) {
+        transform::apply_request(&transforms.request, &mut upstream, &context);
+    }
+
+    // Cache hits are looked up here so the paygate can charge the hit price, or
+    // nothing at all when hits are free.
+    let cache = endpoint
+        .and_then(|endpoint| endpoint.cache)
+        .filter(|_| session.is_none() && matches!(upstream.method, Method::GET | Method::HEAD))
+        .map(|config| {
+            let key = ResponseCache::key(api.id, &upstream, &config.vary_headers);
+            let hit = state.cache.get(&key);
+            if let (Some(_), Some(price)) = (&hit, config.hit_price) {
+                parts.extensions.insert(PriceOverride(price));
+            }
+            CachePlan {
+                key,
+                ttl: Duration::from_secs(config.ttl_seconds.min(MAX_TTL_SECONDS)),
+                hit,
+            }
+        });
+
+    // A cached response can still be served while the upstream is down.
+    let has_hit = cache.as_ref().is_some_and(|plan| plan.hit.is_some());
+    if let (Some(base_url), false) = (&base_url, has_hit) {
+        let settings = state.upstream.settings(api.proxy_config.as_ref());
+        let origin = base_url.origin().ascii_serialization();
+        let breakers = state.upstream.breakers();
+        if let Some(wait) = breakers.retry_after(api.id, &origin, &settings.breaker) {
+            let body = serde_json::json!({ "error": "Upstream is temporarily unavailable" });
+            let retry_after = wait.as_secs().max(1).to_string();
+            return (
+                StatusCode::SERVICE_UNAVAILABLE,
+                [(header::RETRY_AFTER, retry_after)],
+                Json(body),
+            )
+                .into_response();
+        }
+    }
+
+    parts.extensions.insert(PreparedRequest {
+        api,
+        upstream,
+        cache,
+        session,
+        context,
+        recording,
+        call,
+    });
+    next.run(Request::from_parts(parts, Body::empty())).await
+}
+
+/// Forwards a request prepared by `prepare_proxy_request` or
+/// `prepare_transparent_request`, or relays it as a WebSocket session.
+pub async fn proxy_request(
+    State(state): State<AppState>,
+    Extension(prepared): Extension<PreparedRequest>,
+    req: Request,
+) -> Result<Response, StatusCode> {
+    let Some(window) = prepared.session else {
+        return forward(&state, prepared).await;
+    };
+    let (mut parts, _) = req.into_parts();
+    match WebSocketUpgrade::from_request_parts(&mut parts, &state).await {
+        Ok(ws) => websocket::relay(&state, prepared, window, ws).await,
+        Err(rejection) => Ok(rejection.into_response()),
+    }
+}
+
+/// Sends the request upstream with the API's timeouts and size limit. Timeouts
+/// map to 504 and every other upstream failure to 502. For streamed responses
+/// the total timeout covers the time until the upstream starts answering.
+///
+/// Idempotent requests are retried per the API's `RetryConfig` while time is
+/// left before the deadline. Every attempt is reported to the origin's circuit
+/// breaker, and an open breaker ends the request with 503.
+///
+/// Cache hits are answered without contacting the upstream. On a miss, a
+/// cacheable response is buffered so it can be stored before it is returned.
+/// Successful responses are also buffered while the API's sandbox is recording,
+/// and any response whose call log entry keeps the bodies.
+async fn forward(state: &AppState, prepared: PreparedRequest) -> Result<Response, StatusCode> {
+    let PreparedRequest {
+        api,
+        upstream: mut upstream_request,
+        cache,
+        context,
+        recording,
+        call,
+        ..
+    } = prepared;
+    let response_format = upstream_request.response_format;
+
+    if let Some(hit) = cache.as_ref().and_then(|plan| plan.hit.clone()) {
+        return Ok(cached_response(hit, response_format));
+    }
+    let header_rules = header_policy(&api).response;
+
+    inject_secrets(state, &api, &mut upstream_request).await?;
+
+    let settings = state.upstream.settings(api.proxy_config.as_ref());
+    let client = state.tls.client(&state.upstream, &api, &settings).await?;
+    let deadline = Instant::now() + settings.timeout;
+    let full_url = build_upstream_url(
+        &api.base_url,
+        &upstream_request.path,
+        upstream_request.query.as_deref(),
+    )?;
+
+    let origin = full_url.origin().ascii_serialization();
+    let breakers = state.upstream.breakers();
+    let idempotent = is_idempotent(&upstream_request.method);
+    let mut attempt = 1;
+    let response = loop {
+        if !breakers.try_acquire(api.id, &origin, &settings.breaker) {
+            return Err(StatusCode::SERVICE_UNAVAILABLE);
+        }
+
+        let started = Instant::now();
+        let send = client
+            .request(upstream_request.method.clone(), full_url.clone())
+            .headers(upstream_request.headers.clone())
+            .body(upstream_request.body.clone())
+            .send();
+        let result = match timeout_at(deadline, send).await {
+            Ok(Ok(response)) => Ok(response),
+            Ok(Err(e)) if e.is_timeout() => Err(StatusCode::GATEWAY_TIMEOUT),
+            Ok(Err(_)) => Err(StatusCode::BAD_GATEWAY),
+            Err(_) => Err(StatusCode::GATEWAY_TIMEOUT),
+        };
+
+        let failed = match &result {
+            Ok(response) => is_upstream_failure(response.status()),
+            Err(_) => true,
+        };
+        if !failed {
+            breakers.record_success(api.id, &origin);
+            state.balancer.record_latency(&api, started.elapsed());
+            break result?;
+        }
+        if breakers.record_failure(api.id, &origin, &settings.breaker) {
+            let open_for = settings.breaker.open_duration;
+            webhooks::notify_api_suspended(&state.webhook_repo, &api, &origin, open_for);
+        }
+
+        let delay = settings.retry.backoff(attempt);
+        if !idempotent
+            || attempt >= settings.retry.max_attempts
+            || Instant::now() + delay >= deadline
+        {
+            break result?;
+        }
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    };
+    if let Some(call) = &call {
+        call.upstream_status(response.status());
+    }
+
+    let max_response_bytes = settings.max_response_bytes;
+    if response
+        .content_length()
+        .is_some_and(|length| length > max_response_bytes)
+    {
+        tracing::warn!("Upstream response for API {} exceeds the size limit", api.id);
+        return Ok(json_error(
+            StatusCode::BAD_GATEWAY,
+            format!(
+                "Upstream response exceeds the limit of {} bytes",
+                max_response_bytes
+            ),
+        ));
+    }
 
     let status = response.status();
-    let response_headers: HashMap<String, String> = response
-        .headers()
+    let cache_status = match cache {
+        Some(_) => CacheStatus::Miss,
+        None => CacheStatus::Bypass,
+    };
+
+    let transforms = api
+        .proxy_config
+        .as_ref()
+        .and_then(|config| config.transforms.as_ref())
+        .map(|transforms| transforms.response.as_slice())
+        .unwrap_or_default();
+    let mut headers = headers::response_headers(response.headers(), &header_rules);
+    transform::apply_response_headers(transforms, &mut headers, &context, status);
+    // Rewritten bodies are buffered, so their length is only known afterwards.
+    let rewrite_body = transform::rewrites_body(transforms, &headers);
+    if rewrite_body {
+        headers.remove(header::CONTENT_LENGTH);
+    }
+    let recording = recording.filter(|_| status.is_success());
+    let sampled = call.as_ref().is_some_and(|call| call.is_sampled());
+    let buffer_body = rewrite_body || recording.is_some() || sampled;
+    let finish_body = |body: Vec<u8>| {
+        let body = if rewrite_body {
+            transform::apply_response_body(transforms, body)
+        } else {
+            body
+        };
+        if let Some(recording) = recording.clone() {
+            sandbox::record(state, &api, recording, status, &headers, &body);
+        }
+        if let Some(call) = &call {
+            call.response_body(&body);
+        }
+        body
+    };
+
+    if let Some(plan) = cache {
+        let ttl = (status == StatusCode::OK)
+            .then(|| cacheable_ttl(response.headers(), plan.ttl))
+            .flatten();
+        let fits = response
+            .content_length()
+            .is_none_or(|length| length <= state.cache.max_entry_bytes() as u64);
+        if let (Some(ttl), true) = (ttl, fits) {
+            let body = read_body(response, max_response_bytes, deadline).await?;
+            let body = Bytes::from(finish_body(body));
+            state
+                .cache
+                .insert(plan.key, status, headers.clone(), body.clone(), ttl);
+            return Ok(buffered_response(
+                status,
+                headers,
+                body,
+                response_format,
+                CacheStatus::Miss,
+            ));
+        }
+    }
+
+    if response_format == ResponseFormat::Passthrough && buffer_body {
+        let body = Bytes::from(finish_body(
+            read_body(response, max_response_bytes, deadline).await?,
+        ));
+        return Ok(buffered_response(
+            status,
+            headers,
+            body,
+            response_format,
+            cache_status,
+        ));
+    }
+    if response_format == ResponseFormat::Passthrough {
+        let mut response = passthrough_response(response, headers, max_response_bytes);
+        if cache_status == CacheStatus::Miss {
+            response
+                .headers_mut()
+                .insert(CACHE_STATUS_HEADER, HeaderValue::from_static("MISS"));
+        }
+        return Ok(response);
+    }
+
+    let body = finish_body(read_body(response, max_response_bytes, deadline).await?);
+    Ok(envelope_response(status, &headers, &body, cache_status))
+}
+
+fn cached_response(hit: CachedResponse, response_format: ResponseFormat) -> Response {
+    let age = hit.age().as_secs();
+    let mut response = buffered_response(
+        hit.status,
+        hit.headers,
+        hit.body,
+        response_format,
+        CacheStatus::Hit,
+    );
+    if response_format == ResponseFormat::Passthrough {
+        response
+            .headers_mut()
+            .insert(header::AGE, HeaderValue::from(age));
+    }
+    response
+}
+
+fn buffered_response(
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+    response_format: ResponseFormat,
+    cache_status: CacheStatus,
+) -> Response {
+    if response_format == ResponseFormat::Envelope {
+        return envelope_response(status, &headers, &body, cache_status);
+    }
+
+    let mut response = Response::new(Body::from(body));
+    *response.status_mut() = status;
+    *response.headers_mut() = headers;
+    if cache_status != CacheStatus::Bypass {
+        response.headers_mut().insert(
+            CACHE_STATUS_HEADER,
+            HeaderValue::from_static(cache_status.as_str()),
+        );
+    }
+    response
+}
+
+fn envelope_response(
+    status: StatusCode,
+    headers: &HeaderMap,
+    body: &[u8],
+    cache_status: CacheStatus,
+) -> Response {
+    let response_headers: HashMap<String, String> = headers
         .iter()
         .map(|(k, v)| (k.to_string(), v.to_str().unwrap_or("").to_string()))
         .collect();
 
-    let response_body = match response.text().await {
-        Ok(body) => body,
-        Err(_) => return Err(StatusCode::BAD_GATEWAY),
-    };
-
+    let response_body = String::from_utf8_lossy(body).into_owned();
     let json_response: Value = match serde_json::from_str(&response_body) {
         Ok(json) => json,
         Err(_) => Value::String(response_body),
@@ -120,8 +689,286 @@ pub async fn proxy_request(
     let proxy_response = serde_json::json!({
         "status": status.as_u16(),
         "headers": response_headers,
-        "body": json_response
+        "body": json_response,
+        "cache": cache_status.as_str(),
+    });
+
+    Json(proxy_response).into_response()
+}
+
+fn header_policy(api: &Api) -> HeaderPolicy {
+    api.proxy_config
+        .as_ref()
+        .and_then(|config| config.headers.clone())
+        .unwrap_or_default()
+}
+
+fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::PUT | Method::DELETE | Method::OPTIONS | Method::TRACE
+    )
+}
+
+/// Upstream statuses that mean the upstream itself is unavailable, as opposed to
+/// an error about this particular request.
+fn is_upstream_failure(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+/// Encodes a JSON object of query parameters. Strings are used verbatim rather than
+/// JSON-quoted, arrays become repeated keys, nulls are skipped and nested objects
+/// are sent as their JSON text.
+fn encode_query_params(query_params: &Value) -> Option<String> {
+    let params = query_params.as_object()?;
+    let mut serializer = url::form_urlencoded::Serializer::new(String::new());
+    let mut appended = false;
+
+    for (key, value) in params {
+        let values = match value {
+            Value::Array(items) => items.iter().collect(),
+            other => vec![other],
+        };
+        for value in values {
+            let encoded = match value {
+                Value::Null => continue,
+                Value::String(s) => s.clone(),
+                other => other.to_string(),
+            };
+            serializer.append_pair(key, &encoded);
+            appended = true;
+        }
+    }
+
+    appended.then(|| serializer.finish())
+}
+
+/// Joins `path` onto the API's `base_url`, keeping any path prefix of the base
+/// (`https://host/v1` + `/users` is `https://host/v1/users`) and collapsing the
+/// slashes between them. Paths with `.` or `..` segments are rejected, since
+/// they would be resolved to a path other than the one endpoints were matched
+/// against.
+pub fn build_upstream_url(
+    base_url: &str,
+    path: &str,
+    query: Option<&str>,
+) -> Result<Url, StatusCode> {
+    if endpoints::has_dot_segment(path) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let mut url = Url::parse(base_url).map_err(|_| StatusCode::BAD_GATEWAY)?;
+    if url.cannot_be_a_base() {
+        return Err(StatusCode::BAD_GATEWAY);
+    }
+
+    let base_path = url.path().trim_end_matches('/').to_string();
+    let relative = path.trim_start_matches('/');
+    url.set_path(&format!("{}/{}", base_path, relative));
+    if !url.path().starts_with(&format!("{}/", base_path)) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    url.set_query(query.filter(|q| !q.is_empty()));
+    url.set_fragment(None);
+    Ok(url)
+}
+
+/// Adds the provider's stored credentials to the upstream request. Anything the
+/// client sent under the same header or query parameter name is dropped first,
+/// so consumers can neither read nor override the provider's credential.
+pub async fn inject_secrets(
+    state: &AppState,
+    api: &Api,
+    upstream_request: &mut UpstreamRequest,
+) -> Result<(), StatusCode> {
+    let secrets = match state.secret_repo.get_encrypted_secrets(api.id).await {
+        Ok(secrets) => secrets,
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+    };
+    if secrets.is_empty() {
+        return Ok(());
+    }
+    let Some(keyring) = state.secrets.as_ref() else {
+        return Err(StatusCode::SERVICE_UNAVAILABLE);
+    };
+
+    let mut query_secrets = Vec::new();
+    for secret in secrets {
+        let value = keyring
+            .decrypt(api.id, &secret.key_id, &secret.nonce, &secret.ciphertext)
+            .ok()
+            .and_then(|value| String::from_utf8(value).ok())
+            .ok_or(StatusCode::INTERNAL_SERVER_ERROR)?;
+
+        match SecretLocation::parse(&secret.location) {
+            Some(SecretLocation::Header) => {
+                let name = HeaderName::try_from(secret.name.as_str())
+                    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+                let value = HeaderValue::try_from(value)
+                    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+                upstream_request.headers.insert(name, value);
+            }
+            Some(SecretLocation::Bearer) => {
+                let value = HeaderValue::try_from(format!("Bearer {}", value))
+                    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+                upstream_request.headers.insert(header::AUTHORIZATION, value);
+            }
+            Some(SecretLocation::Query) => query_secrets.push((secret.name, value)),
+            None => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+        }
+    }
+
+    if !query_secrets.is_empty() {
+        let existing = upstream_request.query.take().unwrap_or_default();
+        let mut serializer = url::form_urlencoded::Serializer::new(String::new());
+        for (key, value) in url::form_urlencoded::parse(existing.as_bytes()) {
+            if !query_secrets.iter().any(|(name, _)| *name == key) {
+                serializer.append_pair(&key, &value);
+            }
+        }
+        for (name, value) in &query_secrets {
+            serializer.append_pair(name, value);
+        }
+        upstream_request.query = Some(serializer.finish());
+    }
+
+    Ok(())
+}
+
+async fn read_body(
+    mut response: reqwest::Response,
+    max_bytes: u64,
+    deadline: Instant,
+) -> Result<Vec<u8>, StatusCode> {
+    let mut body = Vec::new();
+    loop {
+        match timeout_at(deadline, response.chunk()).await {
+            Ok(Ok(Some(chunk))) => {
+                body.extend_from_slice(&chunk);
+                if body.len() as u64 > max_bytes {
+                    tracing::warn!("Upstream response exceeded {} bytes", max_bytes);
+                    return Err(StatusCode::BAD_GATEWAY);
+                }
+            }
+            Ok(Ok(None)) => return Ok(body),
+            Ok(Err(e)) if e.is_timeout() => return Err(StatusCode::GATEWAY_TIMEOUT),
+            Ok(Err(_)) => return Err(StatusCode::BAD_GATEWAY),
+            Err(_) => return Err(StatusCode::GATEWAY_TIMEOUT),
+        }
+    }
+}
+
+/// Streams the upstream body back as it arrives. Once the status line has been
+/// sent an oversized body can only be cut off, which aborts the stream.
+fn passthrough_response(response: reqwest::Response, headers: HeaderMap, max_bytes: u64) -> Response {
+    let mut builder = Response::builder().status(response.status());
+    for (name, value) in &headers {
+        builder = builder.header(name, value);
+    }
+
+    let mut received: u64 = 0;
+    let stream = response.bytes_stream().map(move |chunk| {
+        let chunk = chunk.map_err(std::io::Error::other)?;
+        received += chunk.len() as u64;
+        if received > max_bytes {
+            return Err(std::io::Error::other(
+                "upstream response exceeded the size limit",
+            ));
+        }
+        Ok(chunk)
     });
 
-    Ok(Json(proxy_response))
+    builder
+        .body(Body::from_stream(stream))
+        .unwrap_or_else(|_| StatusCode::BAD_GATEWAY.into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn encodes_reserved_characters() {
+        let query = encode_query_params(&json!({
+            "q": "a b&c=d/e?f#g",
+            "emoji": "é",
+        }));
+        assert_eq!(
+            query.as_deref(),
+            Some("emoji=%C3%A9&q=a+b%26c%3Dd%2Fe%3Ff%23g")
+        );
+    }
+
+    #[test]
+    fn encodes_arrays_as_repeated_keys() {
+        let query = encode_query_params(&json!({
+            "id": [1, "two", null, true],
+            "filter": { "a": 1 },
+            "skip": null,
+        }));
+        assert_eq!(
+            query.as_deref(),
+            Some("filter=%7B%22a%22%3A1%7D&id=1&id=two&id=true")
+        );
+    }
+
+    #[test]
+    fn encodes_nothing_without_params() {
+        assert_eq!(encode_query_params(&json!({})), None);
+        assert_eq!(encode_query_params(&json!({ "a": null, "b": [] })), None);
+        assert_eq!(encode_query_params(&json!("a=1")), None);
+    }
+
+    #[test]
+    fn joins_paths_onto_the_base_path() {
+        for base in ["https://api.test/v1", "https://api.test/v1/"] {
+            for path in ["/users", "users", "//users"] {
+                let url = build_upstream_url(base, path, Some("a=1")).unwrap();
+                assert_eq!(url.as_str(), "https://api.test/v1/users?a=1");
+            }
+        }
+        let url = build_upstream_url("https://api.test", "/users/", None).unwrap();
+        assert_eq!(url.as_str(), "https://api.test/users/");
+    }
+
+    #[test]
+    fn drops_the_base_query_and_fragment() {
+        let url = build_upstream_url("https://api.test/v1?key=1#top", "/users", Some("")).unwrap();
+        assert_eq!(url.as_str(), "https://api.test/v1/users");
+    }
+
+    #[test]
+    fn refuses_paths_that_leave_the_base_path() {
+        for path in [
+            "/../admin",
+            "/users/../../admin",
+            "/%2e%2e/admin",
+            "/%2E./admin",
+            "/./users",
+            "/..\\admin",
+        ] {
+            assert_eq!(
+                build_upstream_url("https://api.test/v1", path, None),
+                Err(StatusCode::BAD_REQUEST),
+                "{}",
+                path
+            );
+        }
+        let url = build_upstream_url("https://api.test/v1", "/users/..data", None).unwrap();
+        assert_eq!(url.as_str(), "https://api.test/v1/users/..data");
+    }
+
+    #[test]
+    fn refuses_unusable_base_urls() {
+        for base in ["not a url", "mailto:ops@api.test"] {
+            assert_eq!(
+                build_upstream_url(base, "/users", None),
+                Err(StatusCode::BAD_GATEWAY)
+            );
+        }
+    }
 }
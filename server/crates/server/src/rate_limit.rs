@@ -0,0 +1,511 @@
+use axum::{
+    extract::ConnectInfo,
+    http::{header, request::Parts, HeaderMap, StatusCode},
+    response::{IntoResponse, Json, Response},
+};
+use database::{RateLimitRepository, WebhookRepository};
+use serde_json::json;
+use shared::{Api, ApiEndpoint, ProxyConfig, RateLimit, RateLimitKey, WebhookEvent};
+use solana_sdk::transaction::VersionedTransaction;
+use std::collections::HashMap;
+use std::env;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use x402_rs::types::{Base64Bytes, ExactPaymentPayload, PaymentPayload};
+
+/// How often buckets that have refilled completely are dropped.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A provider hears about each exhausted bucket at most this often.
+const NOTIFY_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Longest `period_seconds` a limit may have.
+const MAX_PERIOD_SECONDS: u64 = 30 * 24 * 60 * 60;
+
+/// `ip_requests` for payer-keyed limits that leave it unset, as a multiple of
+/// `requests`.
+const IP_REQUESTS_FACTOR: u32 = 10;
+
+/// Where token buckets are kept. `Memory` limits each server instance on its
+/// own; `Postgres` shares the buckets between instances.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RateLimitBackend {
+    Memory,
+    Postgres,
+}
+
+impl RateLimitBackend {
+    /// Reads `RATE_LIMIT_BACKEND` (`memory`, the default, or `postgres`).
+    pub fn from_env() -> Result<Self, String> {
+        match env::var("RATE_LIMIT_BACKEND").ok().as_deref() {
+            None | Some("") | Some("memory") => Ok(RateLimitBackend::Memory),
+            Some("postgres") => Ok(RateLimitBackend::Postgres),
+            Some(other) => Err(format!("Unknown rate limit backend {}", other)),
+        }
+    }
+}
+
+/// Enforces the rate limits of APIs and their endpoints. Every limit is a token
+/// bucket per API, endpoint and caller (as chosen by its `RateLimitKey`).
+#[derive(Clone, Debug)]
+pub struct RateLimiter {
+    backend: RateLimitBackend,
+    rate_limit_repo: RateLimitRepository,
+    webhook_repo: WebhookRepository,
+    state: Arc<Mutex<LimiterState>>,
+}
+
+#[derive(Debug)]
+struct LimiterState {
+    buckets: HashMap<String, Bucket>,
+    notified: HashMap<String, Instant>,
+    pruned_at: Instant,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+    full_at: Instant,
+}
+
+/// Who is making a call, as far as rate limits are concerned.
+#[derive(Clone, Debug, Default)]
+pub struct Caller {
+    pub ip: Option<IpAddr>,
+    /// The wallet named in the call's `X-Payment` payload. It is read before
+    /// the payment is verified, so it is only as trustworthy as the payload.
+    pub payer: Option<String>,
+}
+
+impl Caller {
+    pub fn from_parts(parts: &Parts) -> Self {
+        Self {
+            ip: parts
+                .extensions
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|info| info.0.ip()),
+            payer: payer(&parts.headers),
+        }
+    }
+
+    /// The buckets a call is counted against for `limit`, with the limit that
+    /// applies to each.
+    ///
+    /// The payer isn't verified yet, so a payer-keyed call is also counted
+    /// against its IP, with the limit's `ip_requests`. Otherwise naming a new
+    /// random wallet on every call would get a fresh bucket each time.
+    fn subjects(&self, limit: &RateLimit) -> Vec<(String, RateLimit)> {
+        let ip = self
+            .ip
+            .map(|ip| ip.to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+        let by_ip = || {
+            let requests = limit
+                .ip_requests
+                .unwrap_or(limit.requests.saturating_mul(IP_REQUESTS_FACTOR));
+            let limit = RateLimit {
+                requests,
+                period_seconds: limit.period_seconds,
+                burst: None,
+                key: RateLimitKey::Ip,
+                ip_requests: None,
+            };
+            (format!("ip:{}", ip), limit)
+        };
+        match (limit.key, &self.payer) {
+            (RateLimitKey::Payer, Some(payer)) => vec![(payer.clone(), limit.clone()), by_ip()],
+            (RateLimitKey::Payer, None) => vec![by_ip()],
+            (RateLimitKey::Ip, _) => vec![(ip, limit.clone())],
+            (RateLimitKey::Api, _) => vec![("*".to_string(), limit.clone())],
+        }
+    }
+}
+
+/// A call refused because one of its buckets is empty.
+#[derive(Debug)]
+pub struct RateLimited {
+    pub retry_after: Duration,
+}
+
+impl IntoResponse for RateLimited {
+    fn into_response(self) -> Response {
+        let retry_after = self.retry_after.as_secs_f64().ceil().max(1.0) as u64;
+        (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, retry_after.to_string())],
+            Json(json!({ "error": "Rate limit exceeded" })),
+        )
+            .into_response()
+    }
+}
+
+impl RateLimiter {
+    pub fn new(
+        backend: RateLimitBackend,
+        rate_limit_repo: RateLimitRepository,
+        webhook_repo: WebhookRepository,
+    ) -> Self {
+        Self {
+            backend,
+            rate_limit_repo,
+            webhook_repo,
+            state: Arc::new(Mutex::new(LimiterState {
+                buckets: HashMap::new(),
+                notified: HashMap::new(),
+                pruned_at: Instant::now(),
+            })),
+        }
+    }
+
+    /// Takes a token for the call from each of the API's buckets and then the
+    /// endpoint's, stopping at the first one that is empty. Payer-keyed limits
+    /// take from the payer's bucket and the caller IP's own. The provider's
+    /// `quota.exceeded` webhooks are notified when that happens.
+    pub async fn check(
+        &self,
+        api: &Api,
+        endpoint: Option<&ApiEndpoint>,
+        caller: &Caller,
+    ) -> Result<(), RateLimited> {
+        let api_limits = api
+            .proxy_config
+            .as_ref()
+            .map(|config| config.rate_limits.as_slice())
+            .unwrap_or_default();
+        let endpoint_limits = endpoint
+            .map(|endpoint| endpoint.rate_limits.as_slice())
+            .unwrap_or_default();
+        if api_limits.is_empty() && endpoint_limits.is_empty() {
+            return Ok(());
+        }
+        self.prune().await;
+
+        let endpoint_scope =
+            endpoint.map(|endpoint| format!("{} {}", endpoint.method.as_str(), endpoint.path));
+        let scoped = api_limits
+            .iter()
+            .enumerate()
+            .map(|(index, limit)| (None, index, limit))
+            .chain(
+                endpoint_limits
+                    .iter()
+                    .enumerate()
+                    .map(|(index, limit)| (endpoint_scope.as_deref(), index, limit)),
+            );
+        for (scope, index, limit) in scoped {
+            for (subject, limit) in caller.subjects(limit) {
+                let key = format!("{}:{}:{}:{}", api.id, scope.unwrap_or("*"), index, subject);
+                if let Some(retry_after) = self.take(&key, &limit).await {
+                    self.notify(api, scope, &limit, &key, &subject, retry_after);
+                    return Err(RateLimited { retry_after });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Takes a token from the bucket `key`, or returns how long until one is
+    /// available. The shared backend lets calls through when it can't be
+    /// reached.
+    async fn take(&self, key: &str, limit: &RateLimit) -> Option<Duration> {
+        let capacity = f64::from(limit.burst.unwrap_or(limit.requests));
+        let rate = f64::from(limit.requests) / limit.period_seconds as f64;
+
+        if self.backend == RateLimitBackend::Postgres {
+            return match self.rate_limit_repo.take_token(key, capacity, rate).await {
+                Ok(wait) => wait.map(seconds),
+                Err(e) => {
+                    tracing::warn!("Failed to check rate limit {}: {e:#}", key);
+                    None
+                }
+            };
+        }
+
+        let now = Instant::now();
+        let mut state = self.state.lock().expect("rate limiter poisoned");
+        let bucket = state.buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: capacity,
+            updated_at: now,
+            full_at: now,
+        });
+        let refilled = now.duration_since(bucket.updated_at).as_secs_f64() * rate;
+        bucket.tokens = (bucket.tokens + refilled).min(capacity);
+        bucket.updated_at = now;
+        if bucket.tokens < 1.0 {
+            return Some(seconds((1.0 - bucket.tokens) / rate));
+        }
+        bucket.tokens -= 1.0;
+        bucket.full_at = now
+            .checked_add(seconds((capacity - bucket.tokens) / rate))
+            .unwrap_or(now + Duration::from_secs(MAX_PERIOD_SECONDS));
+        None
+    }
+
+    async fn prune(&self) {
+        {
+            let mut state = self.state.lock().expect("rate limiter poisoned");
+            if state.pruned_at.elapsed() < PRUNE_INTERVAL {
+                return;
+            }
+            let now = Instant::now();
+            state.pruned_at = now;
+            state.buckets.retain(|_, bucket| bucket.full_at > now);
+            state
+                .notified
+                .retain(|_, notified_at| notified_at.elapsed() < NOTIFY_INTERVAL);
+        }
+
+        if self.backend == RateLimitBackend::Postgres {
+            if let Err(e) = self.rate_limit_repo.prune_buckets().await {
+                tracing::warn!("Failed to prune rate limit buckets: {e:#}");
+            }
+        }
+    }
+
+    fn notify(
+        &self,
+        api: &Api,
+        endpoint: Option<&str>,
+        limit: &RateLimit,
+        key: &str,
+        subject: &str,
+        retry_after: Duration,
+    ) {
+        {
+            let mut state = self.state.lock().expect("rate limiter poisoned");
+            if state
+                .notified
+                .get(key)
+                .is_some_and(|notified_at| notified_at.elapsed() < NOTIFY_INTERVAL)
+            {
+                return;
+            }
+            state.notified.insert(key.to_string(), Instant::now());
+        }
+
+        let webhook_repo = self.webhook_repo.clone();
+        let (user_id, api_id) = (api.user_id, api.id);
+        let data = json!({
+            "api_id": api_id,
+            "user_id": user_id,
+            "endpoint": endpoint,
+            "key": limit.key.as_str(),
+            "subject": subject,
+            "requests": limit.requests,
+            "period_seconds": limit.period_seconds,
+            "retry_after_seconds": retry_after.as_secs_f64().ceil() as u64,
+        });
+        tokio::spawn(async move {
+            if let Err(e) = webhook_repo
+                .enqueue_event(user_id, Some(api_id), WebhookEvent::QuotaExceeded, data)
+                .await
+            {
+                tracing::warn!("Failed to enqueue quota.exceeded for API {}: {e:#}", api_id);
+            }
+        });
+    }
+}
+
+/// A wait in seconds as a `Duration`, saturating when it's too long to represent.
+fn seconds(wait: f64) -> Duration {
+    Duration::try_from_secs_f64(wait).unwrap_or(Duration::MAX)
+}
+
+/// The paying wallet named in an `X-Payment` header: the `from` address of an
+/// EVM authorization, or the authority of a Solana payment's transfer.
+fn payer(headers: &HeaderMap) -> Option<String> {
+    let header = headers.get("X-Payment")?;
+    let payload = PaymentPayload::try_from(Base64Bytes::from(header.as_bytes())).ok()?;
+    match payload.payload {
+        ExactPaymentPayload::Evm(evm) => Some(evm.authorization.from.to_string()),
+        ExactPaymentPayload::Solana(solana) => {
+            let bytes = Base64Bytes::from(solana.transaction.as_bytes())
+                .decode()
+                .ok()?;
+            let transaction: VersionedTransaction = bincode::deserialize(&bytes).ok()?;
+            // The transfer comes last, and its fourth account is the authority.
+            let transfer = transaction.message.instructions().last()?;
+            let authority = *transfer.accounts.get(3)?;
+            transaction
+                .message
+                .static_account_keys()
+                .get(usize::from(authority))
+                .map(|key| key.to_string())
+        }
+    }
+}
+
+/// Checks an API's rate limits before they are saved.
+pub fn validate(config: Option<&ProxyConfig>, endpoints: &[ApiEndpoint]) -> Result<(), String> {
+    let api_limits = config.map(|config| config.rate_limits.as_slice());
+    let limits = api_limits
+        .unwrap_or_default()
+        .iter()
+        .chain(endpoints.iter().flat_map(|endpoint| &endpoint.rate_limits));
+    for limit in limits {
+        if limit.requests == 0 || limit.period_seconds == 0 {
+            return Err("rate limits need at least one request per period".to_string());
+        }
+        if limit.period_seconds > MAX_PERIOD_SECONDS {
+            return Err(format!(
+                "a rate limit's period can be at most {} seconds",
+                MAX_PERIOD_SECONDS
+            ));
+        }
+        if limit.burst == Some(0) {
+            return Err("a rate limit's burst must be at least 1".to_string());
+        }
+        match (limit.key, limit.ip_requests) {
+            (_, None) => {}
+            (RateLimitKey::Payer, Some(0)) => {
+                return Err("a rate limit's ip_requests must be at least 1".to_string());
+            }
+            (RateLimitKey::Payer, Some(_)) => {}
+            (_, Some(_)) => {
+                return Err("ip_requests only applies to Payer rate limits".to_string());
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::postgres::PgPoolOptions;
+
+    fn limiter() -> RateLimiter {
+        // The in-memory backend never touches the database.
+        let pool = PgPoolOptions::new()
+            .connect_lazy("postgres://localhost/unused")
+            .unwrap();
+        RateLimiter::new(
+            RateLimitBackend::Memory,
+            RateLimitRepository::new(pool.clone()),
+            WebhookRepository::new(pool),
+        )
+    }
+
+    fn limit(requests: u32, period_seconds: u64, burst: Option<u32>) -> RateLimit {
+        RateLimit {
+            requests,
+            period_seconds,
+            burst,
+            key: RateLimitKey::Ip,
+            ip_requests: None,
+        }
+    }
+
+    fn limit_with_key(key: RateLimitKey, requests: u32, period_seconds: u64) -> RateLimit {
+        RateLimit {
+            key,
+            ..limit(requests, period_seconds, None)
+        }
+    }
+
+    #[tokio::test]
+    async fn buckets_hold_a_burst_and_refill_at_the_rate() {
+        let limiter = limiter();
+        let limit = limit(10, 1, Some(3));
+        for _ in 0..3 {
+            assert_eq!(limiter.take("a", &limit).await, None);
+        }
+        let wait = limiter.take("a", &limit).await.unwrap();
+        assert!(wait > Duration::ZERO && wait <= Duration::from_millis(100));
+        // Other keys have their own bucket.
+        assert_eq!(limiter.take("b", &limit).await, None);
+
+        tokio::time::sleep(Duration::from_millis(110)).await;
+        assert_eq!(limiter.take("a", &limit).await, None);
+        assert!(limiter.take("a", &limit).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn burst_defaults_to_requests() {
+        let limiter = limiter();
+        let limit = limit(2, 60, None);
+        assert_eq!(limiter.take("a", &limit).await, None);
+        assert_eq!(limiter.take("a", &limit).await, None);
+        let wait = limiter.take("a", &limit).await.unwrap();
+        assert!(wait > Duration::from_secs(29) && wait <= Duration::from_secs(30));
+    }
+
+    #[tokio::test]
+    async fn long_waits_saturate() {
+        let limiter = limiter();
+        let limit = limit(1, u64::MAX, Some(u32::MAX));
+        assert_eq!(limiter.take("a", &limit).await, None);
+
+        let limit = limit_with_key(RateLimitKey::Ip, 1, u64::MAX);
+        assert_eq!(limiter.take("b", &limit).await, None);
+        assert!(limiter.take("b", &limit).await.is_some());
+    }
+
+    #[test]
+    fn payers_are_also_counted_by_ip() {
+        let caller = Caller {
+            ip: Some(IpAddr::from([203, 0, 113, 7])),
+            payer: Some("0xabc".to_string()),
+        };
+        let summary = |limit: &RateLimit| -> Vec<(String, u32)> {
+            caller
+                .subjects(limit)
+                .into_iter()
+                .map(|(subject, limit)| (subject, limit.requests))
+                .collect()
+        };
+        let payer = limit_with_key(RateLimitKey::Payer, 5, 60);
+        assert_eq!(
+            summary(&payer),
+            [("0xabc".to_string(), 5), ("ip:203.0.113.7".to_string(), 50)]
+        );
+        let capped = RateLimit {
+            ip_requests: Some(20),
+            ..payer.clone()
+        };
+        assert_eq!(summary(&capped)[1], ("ip:203.0.113.7".to_string(), 20));
+        assert_eq!(
+            summary(&limit_with_key(RateLimitKey::Ip, 5, 60)),
+            [("203.0.113.7".to_string(), 5)]
+        );
+        assert_eq!(
+            summary(&limit_with_key(RateLimitKey::Api, 5, 60)),
+            [("*".to_string(), 5)]
+        );
+
+        let anonymous = Caller::default();
+        let subjects = anonymous.subjects(&payer);
+        assert_eq!(subjects.len(), 1);
+        assert_eq!(subjects[0].0, "ip:unknown");
+        assert_eq!(subjects[0].1.requests, 50);
+    }
+
+    #[test]
+    fn validates_limits() {
+        let endpoint = |limits| ApiEndpoint {
+            rate_limits: limits,
+            ..serde_json::from_value(json!({ "path": "/a", "method": "GET" })).unwrap()
+        };
+        assert!(validate(None, &[endpoint(vec![limit(1, 1, None)])]).is_ok());
+        assert!(validate(None, &[endpoint(vec![limit(0, 1, None)])]).is_err());
+        assert!(validate(None, &[endpoint(vec![limit(1, 0, None)])]).is_err());
+        assert!(validate(None, &[endpoint(vec![limit(1, 1, Some(0))])]).is_err());
+        let period = MAX_PERIOD_SECONDS;
+        assert!(validate(None, &[endpoint(vec![limit(1, period, None)])]).is_ok());
+        assert!(validate(None, &[endpoint(vec![limit(1, period + 1, None)])]).is_err());
+
+        let payer = |ip_requests| RateLimit {
+            ip_requests,
+            ..limit_with_key(RateLimitKey::Payer, 1, 1)
+        };
+        assert!(validate(None, &[endpoint(vec![payer(Some(100))])]).is_ok());
+        assert!(validate(None, &[endpoint(vec![payer(Some(0))])]).is_err());
+        let by_ip = RateLimit {
+            ip_requests: Some(100),
+            ..limit(1, 1, None)
+        };
+        assert!(validate(None, &[endpoint(vec![by_ip])]).is_err());
+    }
+}
@@ -0,0 +1,183 @@
+use crate::app::AppState;
+use crate::endpoints;
+use crate::proxy::{
+    json_error, read_request_body, transparent_path, TransparentPath, UpstreamRequest,
+};
+use axum::{
+    body::{Body, Bytes},
+    extract::{Path, Request, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use shared::{Api, ApiEndpoint, HttpMethod, ResponseFormat, SandboxConfig};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Set on every response served by a sandbox.
+pub const SANDBOX_HEADER: &str = "x-enigma-sandbox";
+
+/// Upper bound on the configured sandbox latency, so a sandbox call can't hold
+/// a connection open indefinitely.
+const MAX_LATENCY: Duration = Duration::from_secs(30);
+
+/// Response headers that describe one particular response rather than the
+/// endpoint, and are left out of recorded mocks.
+const UNRECORDED_HEADERS: &[HeaderName] = &[
+    header::CONTENT_LENGTH,
+    header::DATE,
+    header::SET_COOKIE,
+    header::TRANSFER_ENCODING,
+];
+
+/// Where a live response should be saved as a mock.
+#[derive(Clone, Debug)]
+pub struct Recording {
+    pub method: HttpMethod,
+    pub path: String,
+}
+
+pub fn config(api: &Api) -> Option<&SandboxConfig> {
+    api.proxy_config.as_ref()?.sandbox.as_ref()
+}
+
+/// The mock a call matching `endpoint` is served from: the endpoint's method and
+/// path template, or the literal method and path for APIs without endpoints.
+fn mock_key(endpoint: Option<&ApiEndpoint>, request: &UpstreamRequest) -> Option<Recording> {
+    match endpoint {
+        Some(endpoint) => Some(Recording {
+            method: endpoint.method.clone(),
+            path: endpoint.path.clone(),
+        }),
+        None => Some(Recording {
+            method: HttpMethod::parse(request.method.as_str())?,
+            path: request.path.clone(),
+        }),
+    }
+}
+
+/// Where a live call's response should be recorded, when the API's sandbox is
+/// recording.
+pub fn recording(
+    api: &Api,
+    endpoint: Option<&ApiEndpoint>,
+    request: &UpstreamRequest,
+) -> Option<Recording> {
+    config(api)
+        .filter(|sandbox| sandbox.record)
+        .and_then(|_| mock_key(endpoint, request))
+}
+
+/// Saves a successful live response as a mock in the background. Endpoints that
+/// already have a mock keep it, and bodies that aren't UTF-8 are skipped.
+pub fn record(
+    state: &AppState,
+    api: &Api,
+    recording: Recording,
+    status: StatusCode,
+    headers: &HeaderMap,
+    body: &[u8],
+) {
+    if !status.is_success() {
+        return;
+    }
+    let Ok(body) = String::from_utf8(body.to_vec()) else {
+        return;
+    };
+    let headers: HashMap<String, String> = headers
+        .iter()
+        .filter(|(name, _)| !UNRECORDED_HEADERS.contains(name))
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+        .collect();
+
+    let mock_repo = state.mock_repo.clone();
+    let api_id = api.id;
+    tokio::spawn(async move {
+        if let Err(e) = mock_repo
+            .insert_recorded_mock(
+                api_id,
+                &recording.method,
+                &recording.path,
+                status.as_u16(),
+                &headers,
+                &body,
+            )
+            .await
+        {
+            tracing::warn!("Failed to record a mock for API {}: {e:#}", api_id);
+        }
+    });
+}
+
+/// `ANY /sandbox/{api_id}/{*path}` answers like the transparent proxy, but from
+/// the API's mocks and without payment. Requests are still checked against the
+/// published endpoints so callers find mistakes before paying for live calls.
+pub async fn sandbox_request(
+    State(state): State<AppState>,
+    Path(params): Path<TransparentPath>,
+    req: Request,
+) -> Response {
+    let (parts, body) = req.into_parts();
+    let api = match state.api_repo.get_api_by_id(params.api_id).await {
+        Ok(Some(api)) => api,
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+    let Some(sandbox) = config(&api) else {
+        return json_error(StatusCode::NOT_FOUND, "This API has no sandbox".to_string());
+    };
+    let latency = Duration::from_millis(sandbox.latency_ms).min(MAX_LATENCY);
+
+    let body = match read_request_body(&state, &api, &parts, body).await {
+        Ok(body) => body,
+        Err(response) => return response,
+    };
+    let request = UpstreamRequest {
+        method: parts.method.clone(),
+        path: transparent_path(&parts),
+        query: parts.uri.query().map(|q| q.to_string()),
+        headers: parts.headers.clone(),
+        body,
+        response_format: ResponseFormat::Passthrough,
+    };
+    let endpoint = match endpoints::validate_request(&api, &request) {
+        Ok(endpoint) => endpoint,
+        Err(rejection) => return rejection.into_response(),
+    };
+
+    let not_found = || {
+        json_error(
+            StatusCode::NOT_FOUND,
+            format!("No mock response for {} {}", request.method, request.path),
+        )
+    };
+    let Some(key) = mock_key(endpoint.as_ref(), &request) else {
+        return not_found();
+    };
+    let mock = match state
+        .mock_repo
+        .get_mock(api.id, &key.method, &key.path)
+        .await
+    {
+        Ok(Some(mock)) => mock,
+        Ok(None) => return not_found(),
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    tokio::time::sleep(latency).await;
+
+    let mut response = Response::new(Body::from(Bytes::from(mock.body)));
+    *response.status_mut() =
+        StatusCode::from_u16(mock.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    for (name, value) in &mock.headers {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::try_from(name.as_str()),
+            HeaderValue::try_from(value.as_str()),
+        ) {
+            response.headers_mut().insert(name, value);
+        }
+    }
+    response
+        .headers_mut()
+        .insert(SANDBOX_HEADER, HeaderValue::from_static("true"));
+    response
+}
@@ -0,0 +1,153 @@
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use anyhow::{anyhow, bail, Context, Result};
+use base64::Engine;
+use database::ApiSecretRepository;
+use std::collections::HashMap;
+use std::env;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Master keys used to encrypt provider secrets at rest.
+///
+/// Configured through `SECRETS_MASTER_KEYS` as a comma separated list of
+/// `<key id>:<base64 32-byte key>` entries. New secrets are encrypted under
+/// `SECRETS_ACTIVE_KEY_ID` (the first entry by default); older keys stay listed
+/// only until [`rotate_secrets`] and [`rotate_tls_configs`] have re-encrypted
+/// everything under the active one. Upstream TLS material uses the same keys.
+///
+/// [`rotate_tls_configs`]: crate::tls::rotate_tls_configs
+#[derive(Clone)]
+pub struct SecretKeyring {
+    active_key_id: String,
+    keys: Arc<HashMap<String, Aes256Gcm>>,
+}
+
+pub struct EncryptedSecret {
+    pub ciphertext: Vec<u8>,
+    pub nonce: Vec<u8>,
+    pub key_id: String,
+}
+
+impl std::fmt::Debug for SecretKeyring {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SecretKeyring")
+            .field("active_key_id", &self.active_key_id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SecretKeyring {
+    /// Returns `Ok(None)` when no master key is configured.
+    pub fn from_env() -> Result<Option<Self>> {
+        let Ok(raw_keys) = env::var("SECRETS_MASTER_KEYS") else {
+            return Ok(None);
+        };
+
+        let mut keys = HashMap::new();
+        let mut first_key_id = None;
+        for entry in raw_keys.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (key_id, encoded) = entry.split_once(':').ok_or_else(|| {
+                anyhow!("SECRETS_MASTER_KEYS entries must be <key id>:<base64 key>")
+            })?;
+            let bytes = base64::engine::general_purpose::STANDARD
+                .decode(encoded)
+                .with_context(|| format!("Master key {key_id} is not valid base64"))?;
+            if bytes.len() != 32 {
+                bail!("Master key {key_id} must be 32 bytes");
+            }
+            let key = Key::<Aes256Gcm>::from_slice(&bytes);
+            keys.insert(key_id.to_string(), Aes256Gcm::new(key));
+            first_key_id.get_or_insert_with(|| key_id.to_string());
+        }
+
+        let active_key_id = match env::var("SECRETS_ACTIVE_KEY_ID") {
+            Ok(key_id) => key_id,
+            Err(_) => first_key_id.ok_or_else(|| anyhow!("SECRETS_MASTER_KEYS is empty"))?,
+        };
+        if !keys.contains_key(&active_key_id) {
+            bail!("SECRETS_ACTIVE_KEY_ID {active_key_id} is not one of SECRETS_MASTER_KEYS");
+        }
+
+        Ok(Some(Self {
+            active_key_id,
+            keys: Arc::new(keys),
+        }))
+    }
+
+    pub fn active_key_id(&self) -> &str {
+        &self.active_key_id
+    }
+
+    /// Encrypts `plaintext` under the active key. The API id is bound as associated
+    /// data so a ciphertext copied onto another API fails to decrypt.
+    pub fn encrypt(&self, api_id: Uuid, plaintext: &[u8]) -> Result<EncryptedSecret> {
+        let cipher = &self.keys[&self.active_key_id];
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: plaintext,
+                    aad: api_id.as_bytes(),
+                },
+            )
+            .map_err(|_| anyhow!("Failed to encrypt secret"))?;
+
+        Ok(EncryptedSecret {
+            ciphertext,
+            nonce: nonce.to_vec(),
+            key_id: self.active_key_id.clone(),
+        })
+    }
+
+    pub fn decrypt(
+        &self,
+        api_id: Uuid,
+        key_id: &str,
+        nonce: &[u8],
+        ciphertext: &[u8],
+    ) -> Result<Vec<u8>> {
+        let cipher = self
+            .keys
+            .get(key_id)
+            .ok_or_else(|| anyhow!("Unknown master key {key_id}"))?;
+        if nonce.len() != 12 {
+            bail!("Invalid nonce length");
+        }
+        cipher
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: api_id.as_bytes(),
+                },
+            )
+            .map_err(|_| anyhow!("Failed to decrypt secret"))
+    }
+}
+
+/// Re-encrypts every secret that is not yet under the active master key, so
+/// retired keys can be dropped from the configuration afterwards.
+pub async fn rotate_secrets(repo: &ApiSecretRepository, keyring: &SecretKeyring) -> Result<usize> {
+    let stale = repo
+        .get_secrets_not_under_key(keyring.active_key_id())
+        .await?;
+    for secret in &stale {
+        let plaintext = keyring.decrypt(
+            secret.api_id,
+            &secret.key_id,
+            &secret.nonce,
+            &secret.ciphertext,
+        )?;
+        let encrypted = keyring.encrypt(secret.api_id, &plaintext)?;
+        repo.update_ciphertext(
+            secret.id,
+            &encrypted.ciphertext,
+            &encrypted.nonce,
+            &encrypted.key_id,
+        )
+        .await?;
+    }
+    Ok(stale.len())
+}
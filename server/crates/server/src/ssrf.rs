@@ -0,0 +1,338 @@
+use anyhow::{anyhow, Result};
+use ipnet::IpNet;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use std::env;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use url::{Host, Url};
+
+const MAX_REDIRECTS: usize = 10;
+
+#[derive(Debug, thiserror::Error)]
+pub enum SsrfError {
+    #[error("Invalid upstream URL: {0}")]
+    InvalidUrl(String),
+    #[error("Upstream URL must use http or https, not {0}")]
+    UnsupportedScheme(String),
+    #[error("Upstream {0} is a private, loopback or link-local address")]
+    ForbiddenAddress(String),
+    #[error("Could not resolve upstream host {0}")]
+    Unresolvable(String),
+    #[error("Too many redirects")]
+    TooManyRedirects,
+}
+
+/// Decides which upstream hosts the proxy may connect to.
+///
+/// Private, loopback, link-local and other non-public addresses are refused
+/// unless the operator allowlists them through `UPSTREAM_ALLOWLIST`, a comma
+/// separated list of hostnames (`.example.internal` also matches subdomains),
+/// IP addresses and CIDR ranges.
+#[derive(Clone, Debug, Default)]
+pub struct UpstreamPolicy {
+    hosts: Arc<Vec<String>>,
+    networks: Arc<Vec<IpNet>>,
+}
+
+impl UpstreamPolicy {
+    pub fn from_env() -> Result<Self> {
+        Self::with_allowlist(&env::var("UPSTREAM_ALLOWLIST").unwrap_or_default())
+    }
+
+    /// A policy allowing the entries of `allowlist`, written like
+    /// `UPSTREAM_ALLOWLIST`.
+    pub fn with_allowlist(allowlist: &str) -> Result<Self> {
+        let mut hosts = Vec::new();
+        let mut networks = Vec::new();
+        for entry in allowlist.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            if let Ok(network) = entry.parse::<IpNet>() {
+                networks.push(network);
+            } else if let Ok(ip) = entry.parse::<IpAddr>() {
+                networks.push(IpNet::from(ip));
+            } else if entry.contains('/') {
+                return Err(anyhow!("UPSTREAM_ALLOWLIST entry {entry} is not a valid CIDR"));
+            } else {
+                hosts.push(entry.to_ascii_lowercase());
+            }
+        }
+
+        Ok(Self {
+            hosts: Arc::new(hosts),
+            networks: Arc::new(networks),
+        })
+    }
+
+    fn host_allowed(&self, host: &str) -> bool {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        self.hosts.iter().any(|allowed| match allowed.strip_prefix('.') {
+            Some(domain) => host == domain || host.ends_with(allowed.as_str()),
+            None => host == *allowed,
+        })
+    }
+
+    fn ip_allowed(&self, ip: IpAddr) -> bool {
+        !is_internal(ip) || self.networks.iter().any(|network| network.contains(&ip))
+    }
+
+    /// Checks what can be decided without DNS: the scheme, and the address when
+    /// the host is an IP literal. Hostnames are checked once resolved.
+    pub fn check_url(&self, url: &Url) -> Result<(), SsrfError> {
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(SsrfError::UnsupportedScheme(url.scheme().to_string()));
+        }
+
+        let ip = match url.host() {
+            Some(Host::Ipv4(ip)) => IpAddr::V4(ip),
+            Some(Host::Ipv6(ip)) => IpAddr::V6(ip),
+            Some(Host::Domain(_)) => return Ok(()),
+            None => return Err(SsrfError::InvalidUrl(url.to_string())),
+        };
+        if self.ip_allowed(ip) {
+            Ok(())
+        } else {
+            Err(SsrfError::ForbiddenAddress(ip.to_string()))
+        }
+    }
+
+    /// Publish-time check for an API's `base_url`, including a DNS lookup of its
+    /// host. Connections are checked again by the resolver, since DNS answers can
+    /// change after publishing.
+    pub async fn check_base_url(&self, base_url: &str) -> Result<(), SsrfError> {
+        let url = Url::parse(base_url).map_err(|_| SsrfError::InvalidUrl(base_url.to_string()))?;
+        self.check_url(&url)?;
+
+        let Some(Host::Domain(host)) = url.host() else {
+            return Ok(());
+        };
+        if self.host_allowed(host) {
+            return Ok(());
+        }
+
+        let port = url.port_or_known_default().unwrap_or(80);
+        let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, port))
+            .await
+            .map_err(|_| SsrfError::Unresolvable(host.to_string()))?
+            .collect();
+        if addrs.is_empty() {
+            return Err(SsrfError::Unresolvable(host.to_string()));
+        }
+        match addrs.iter().find(|addr| !self.ip_allowed(addr.ip())) {
+            Some(addr) => Err(SsrfError::ForbiddenAddress(format!("{} ({})", host, addr.ip()))),
+            None => Ok(()),
+        }
+    }
+
+    /// Resolves `host` to the addresses the policy lets the proxy connect to, for
+    /// connections made outside the upstream HTTP clients.
+    pub async fn resolve(&self, host: &str, port: u16) -> Result<Vec<SocketAddr>, SsrfError> {
+        let addrs = tokio::net::lookup_host((host, port))
+            .await
+            .map_err(|_| SsrfError::Unresolvable(host.to_string()))?;
+        let host_allowed = self.host_allowed(host);
+        let addrs: Vec<SocketAddr> = addrs
+            .filter(|addr| host_allowed || self.ip_allowed(addr.ip()))
+            .collect();
+        if addrs.is_empty() {
+            return Err(SsrfError::ForbiddenAddress(host.to_string()));
+        }
+        Ok(addrs)
+    }
+
+    pub fn resolver(&self) -> Arc<GuardedResolver> {
+        Arc::new(GuardedResolver {
+            policy: self.clone(),
+        })
+    }
+
+    /// Follows redirects only to http(s) URLs that pass `check_url`; redirects to
+    /// hostnames are checked by the resolver when the connection is made.
+    pub fn redirect_policy(&self) -> reqwest::redirect::Policy {
+        let policy = self.clone();
+        reqwest::redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() >= MAX_REDIRECTS {
+                return attempt.error(SsrfError::TooManyRedirects);
+            }
+            match policy.check_url(attempt.url()) {
+                Ok(()) => attempt.follow(),
+                Err(e) => attempt.error(e),
+            }
+        })
+    }
+}
+
+/// DNS resolver for upstream clients that drops addresses the policy forbids,
+/// so a hostname can't be pointed at an internal service after it was published.
+#[derive(Debug)]
+pub struct GuardedResolver {
+    policy: UpstreamPolicy,
+}
+
+impl Resolve for GuardedResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let policy = self.policy.clone();
+        Box::pin(async move {
+            let host = name.as_str();
+            let addrs = policy.resolve(host, 0).await.inspect_err(|e| {
+                if matches!(e, SsrfError::ForbiddenAddress(_)) {
+                    tracing::warn!("Refusing to connect to upstream {}", host);
+                }
+            })?;
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+fn is_internal(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_internal_v4(ip),
+        IpAddr::V6(ip) => match embedded_v4(ip) {
+            Some(ip) => is_internal_v4(ip),
+            None => is_internal_v6(ip),
+        },
+    }
+}
+
+/// The IPv4 address an IPv6 address reaches: IPv4-mapped (`::ffff:0:0/96`),
+/// NAT64 (`64:ff9b::/96`) and 6to4 (`2002::/16`) addresses are routed to the
+/// IPv4 address they embed.
+fn embedded_v4(ip: Ipv6Addr) -> Option<Ipv4Addr> {
+    let bits = u128::from(ip);
+    if let Some(ip) = ip.to_ipv4_mapped() {
+        Some(ip)
+    } else if bits >> 32 == 0x0064_ff9b << 64 {
+        Some(Ipv4Addr::from(bits as u32))
+    } else if bits >> 112 == 0x2002 {
+        Some(Ipv4Addr::from((bits >> 80) as u32))
+    } else {
+        None
+    }
+}
+
+fn is_internal_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        || a == 0
+        // 100.64.0.0/10, carrier-grade NAT
+        || (a == 100 && (b & 0xc0) == 64)
+        // 198.18.0.0/15, benchmarking
+        || (a == 198 && (b & 0xfe) == 18)
+        // 240.0.0.0/4, reserved
+        || a >= 240
+}
+
+fn is_internal_v6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        // fc00::/7, unique local
+        || (first & 0xfe00) == 0xfc00
+        // fe80::/10, link-local
+        || (first & 0xffc0) == 0xfe80
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn internal(ip: &str) -> bool {
+        is_internal(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn finds_internal_ipv4_addresses() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "0.0.0.0",
+            "0.1.2.3",
+            "100.64.0.1",
+            "100.127.255.255",
+            "198.18.0.1",
+            "198.19.255.255",
+            "224.0.0.1",
+            "240.0.0.1",
+            "255.255.255.255",
+        ] {
+            assert!(internal(ip), "{}", ip);
+        }
+        for ip in ["8.8.8.8", "100.128.0.1", "198.17.255.255", "198.20.0.1"] {
+            assert!(!internal(ip), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn finds_internal_ipv6_addresses() {
+        for ip in ["::1", "::", "ff02::1", "fd00::1", "fe80::1"] {
+            assert!(internal(ip), "{}", ip);
+        }
+        assert!(!internal("2001:4860:4860::8888"));
+    }
+
+    #[test]
+    fn checks_the_ipv4_address_embedded_in_ipv6() {
+        for ip in [
+            "::ffff:127.0.0.1",
+            "::ffff:169.254.169.254",
+            "64:ff9b::10.0.0.1",
+            "64:ff9b::a9fe:a9fe",
+            "2002:7f00:1::",
+            "2002:c0a8:101::1",
+        ] {
+            assert!(internal(ip), "{}", ip);
+        }
+        for ip in ["::ffff:8.8.8.8", "64:ff9b::808:808", "2002:808:808::1"] {
+            assert!(!internal(ip), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn checks_urls_without_dns() {
+        let policy = UpstreamPolicy::default();
+        let check = |url: &str| policy.check_url(&Url::parse(url).unwrap());
+        assert!(check("https://api.example.com/v1").is_ok());
+        assert!(check("https://8.8.8.8/").is_ok());
+        assert!(matches!(
+            check("ftp://api.example.com/"),
+            Err(SsrfError::UnsupportedScheme(_))
+        ));
+        for url in [
+            "http://127.0.0.1/",
+            "http://[::1]/",
+            "http://[64:ff9b::7f00:1]/",
+            "http://[2002:a00:1::]/",
+            "http://198.18.0.1/",
+        ] {
+            assert!(
+                matches!(check(url), Err(SsrfError::ForbiddenAddress(_))),
+                "{}",
+                url
+            );
+        }
+    }
+
+    #[test]
+    fn allowlists_hosts_and_networks() {
+        let policy =
+            UpstreamPolicy::with_allowlist("10.0.0.0/8, 127.0.0.1, .internal.test, api.local")
+                .unwrap();
+        assert!(policy.ip_allowed("10.2.3.4".parse().unwrap()));
+        assert!(policy.ip_allowed("127.0.0.1".parse().unwrap()));
+        assert!(!policy.ip_allowed("127.0.0.2".parse().unwrap()));
+        assert!(policy.host_allowed("internal.test"));
+        assert!(policy.host_allowed("svc.Internal.test."));
+        assert!(!policy.host_allowed("notinternal.test"));
+        assert!(policy.host_allowed("api.local"));
+        assert!(!policy.host_allowed("x.api.local"));
+
+        assert!(UpstreamPolicy::with_allowlist("10.0.0.0/33").is_err());
+    }
+}
@@ -0,0 +1,186 @@
+use crate::secrets::SecretKeyring;
+use crate::upstream::{UpstreamClients, UpstreamSettings};
+use anyhow::{anyhow, Context, Result};
+use axum::http::StatusCode;
+use database::{ApiTlsConfigRow, ApiTlsRepository};
+use reqwest::{Client, ClientBuilder};
+use shared::{Api, PutApiTlsRequest};
+
+const BEGIN_CERTIFICATE: &str = "-----BEGIN CERTIFICATE-----";
+const END_CERTIFICATE: &str = "-----END CERTIFICATE-----";
+
+/// TLS settings providers upload for upstreams behind a private CA or requiring
+/// client certificates. The PEM material is stored encrypted under the secrets
+/// keyring, as one JSON document per API.
+#[derive(Clone, Debug)]
+pub struct UpstreamTls {
+    tls_repo: ApiTlsRepository,
+    keyring: Option<SecretKeyring>,
+}
+
+impl UpstreamTls {
+    pub fn new(tls_repo: ApiTlsRepository, keyring: Option<SecretKeyring>) -> Self {
+        Self { tls_repo, keyring }
+    }
+
+    /// The HTTP client for calls to `api`'s upstream: the shared one, or one
+    /// that also trusts the API's CA bundle and presents its client certificate.
+    pub async fn client(
+        &self,
+        upstream: &UpstreamClients,
+        api: &Api,
+        settings: &UpstreamSettings,
+    ) -> Result<Client, StatusCode> {
+        let Some(row) = self.load(api).await? else {
+            return upstream.client(settings).map_err(|e| {
+                tracing::warn!("Failed to build the upstream client: {e:#}");
+                StatusCode::INTERNAL_SERVER_ERROR
+            });
+        };
+        let keyring = self
+            .keyring
+            .as_ref()
+            .ok_or(StatusCode::SERVICE_UNAVAILABLE)?;
+        upstream
+            .tls_client(api.id, row.updated_at, settings, |builder| {
+                configure(builder, &decrypt(keyring, &row)?)
+            })
+            .map_err(|e| {
+                tracing::warn!("Failed to build the TLS client for API {}: {e:#}", api.id);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })
+    }
+
+    /// The connector for WebSocket connections to `api`'s upstream, when the
+    /// API has TLS settings of its own.
+    pub async fn connector(
+        &self,
+        api: &Api,
+    ) -> Result<Option<native_tls::TlsConnector>, StatusCode> {
+        let Some(row) = self.load(api).await? else {
+            return Ok(None);
+        };
+        let keyring = self
+            .keyring
+            .as_ref()
+            .ok_or(StatusCode::SERVICE_UNAVAILABLE)?;
+        decrypt(keyring, &row)
+            .and_then(|material| connector(&material))
+            .map(Some)
+            .map_err(|e| {
+                tracing::warn!(
+                    "Failed to build the TLS connector for API {}: {e:#}",
+                    api.id
+                );
+                StatusCode::INTERNAL_SERVER_ERROR
+            })
+    }
+
+    async fn load(&self, api: &Api) -> Result<Option<ApiTlsConfigRow>, StatusCode> {
+        self.tls_repo
+            .get_encrypted_tls_config(api.id)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+    }
+}
+
+/// Checks uploaded TLS material before it is saved, by building the client and
+/// connector the proxy would use with it.
+pub fn validate(request: &PutApiTlsRequest) -> Result<(), String> {
+    match (&request.client_certificate, &request.client_key) {
+        (Some(_), None) | (None, Some(_)) => {
+            return Err("client_certificate and client_key must be given together".to_string())
+        }
+        (None, None) if request.ca_bundle.is_none() => {
+            return Err("a CA bundle or a client certificate is required".to_string())
+        }
+        _ => {}
+    }
+    configure(Client::builder(), request)
+        .and_then(|builder| Ok(builder.build()?))
+        .and_then(|_| connector(request))
+        .map(|_| ())
+        .map_err(|e| format!("{e:#}"))
+}
+
+fn decrypt(keyring: &SecretKeyring, row: &ApiTlsConfigRow) -> Result<PutApiTlsRequest> {
+    let plaintext = keyring.decrypt(row.api_id, &row.key_id, &row.nonce, &row.ciphertext)?;
+    serde_json::from_slice(&plaintext).context("Stored TLS settings are not valid JSON")
+}
+
+/// The certificates in a PEM bundle, each as its own PEM block.
+fn certificates(bundle: &str) -> Result<Vec<&str>> {
+    let mut certificates = Vec::new();
+    let mut rest = bundle;
+    while let Some(start) = rest.find(BEGIN_CERTIFICATE) {
+        let end = rest[start..]
+            .find(END_CERTIFICATE)
+            .map(|end| start + end + END_CERTIFICATE.len())
+            .ok_or_else(|| anyhow!("ca_bundle has an unterminated certificate"))?;
+        certificates.push(&rest[start..end]);
+        rest = &rest[end..];
+    }
+    if certificates.is_empty() {
+        return Err(anyhow!("ca_bundle contains no PEM certificates"));
+    }
+    Ok(certificates)
+}
+
+fn configure(mut builder: ClientBuilder, material: &PutApiTlsRequest) -> Result<ClientBuilder> {
+    builder = builder.use_native_tls();
+    if let Some(bundle) = &material.ca_bundle {
+        for pem in certificates(bundle)? {
+            let certificate = reqwest::Certificate::from_pem(pem.as_bytes())
+                .context("ca_bundle contains an invalid certificate")?;
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+    if let (Some(certificate), Some(key)) = (&material.client_certificate, &material.client_key) {
+        let identity = reqwest::Identity::from_pkcs8_pem(certificate.as_bytes(), key.as_bytes())
+            .context("Invalid client certificate or PKCS#8 key")?;
+        builder = builder.identity(identity);
+    }
+    Ok(builder)
+}
+
+fn connector(material: &PutApiTlsRequest) -> Result<native_tls::TlsConnector> {
+    let mut builder = native_tls::TlsConnector::builder();
+    if let Some(bundle) = &material.ca_bundle {
+        for pem in certificates(bundle)? {
+            let certificate = native_tls::Certificate::from_pem(pem.as_bytes())
+                .context("ca_bundle contains an invalid certificate")?;
+            builder.add_root_certificate(certificate);
+        }
+    }
+    if let (Some(certificate), Some(key)) = (&material.client_certificate, &material.client_key) {
+        let identity = native_tls::Identity::from_pkcs8(certificate.as_bytes(), key.as_bytes())
+            .context("Invalid client certificate or PKCS#8 key")?;
+        builder.identity(identity);
+    }
+    Ok(builder.build()?)
+}
+
+/// Re-encrypts every API's TLS settings that are not yet under the active
+/// master key, like `rotate_secrets` does for secrets.
+pub async fn rotate_tls_configs(repo: &ApiTlsRepository, keyring: &SecretKeyring) -> Result<usize> {
+    let stale = repo
+        .get_tls_configs_not_under_key(keyring.active_key_id())
+        .await?;
+    for config in &stale {
+        let plaintext = keyring.decrypt(
+            config.api_id,
+            &config.key_id,
+            &config.nonce,
+            &config.ciphertext,
+        )?;
+        let encrypted = keyring.encrypt(config.api_id, &plaintext)?;
+        repo.update_ciphertext(
+            config.api_id,
+            &encrypted.ciphertext,
+            &encrypted.nonce,
+            &encrypted.key_id,
+        )
+        .await?;
+    }
+    Ok(stale.len())
+}
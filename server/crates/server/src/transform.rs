@@ -0,0 +1,618 @@
+use crate::proxy::UpstreamRequest;
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode};
+use serde_json::Value;
+use shared::{ProxyConfig, RequestTransform, ResponseTransform};
+use std::net::IpAddr;
+use uuid::Uuid;
+
+/// The caller's request as it reached Enigma, which transformation templates
+/// refer to.
+#[derive(Clone, Debug)]
+pub struct TemplateContext {
+    api_id: Uuid,
+    method: String,
+    path: String,
+    query: Option<String>,
+    headers: HeaderMap,
+    client_ip: Option<IpAddr>,
+}
+
+impl TemplateContext {
+    pub fn new(api_id: Uuid, request: &UpstreamRequest, client_ip: Option<IpAddr>) -> Self {
+        Self {
+            api_id,
+            method: request.method.to_string(),
+            path: request.path.clone(),
+            query: request.query.clone(),
+            headers: request.headers.clone(),
+            client_ip,
+        }
+    }
+
+    /// Replaces `{{variable}}` placeholders. Unknown variables render as empty
+    /// strings.
+    pub fn render(&self, template: &str, status: Option<StatusCode>) -> String {
+        let mut rendered = String::with_capacity(template.len());
+        let mut rest = template;
+        while let Some(start) = rest.find("{{") {
+            let Some(end) = rest[start..].find("}}") else {
+                break;
+            };
+            rendered.push_str(&rest[..start]);
+            let variable = rest[start + 2..start + end].trim();
+            rendered.push_str(&self.variable(variable, status));
+            rest = &rest[start + end + 2..];
+        }
+        rendered.push_str(rest);
+        rendered
+    }
+
+    fn variable(&self, name: &str, status: Option<StatusCode>) -> String {
+        if let Some(param) = name.strip_prefix("query.") {
+            return url::form_urlencoded::parse(
+                self.query.as_deref().unwrap_or_default().as_bytes(),
+            )
+            .find(|(key, _)| key == param)
+            .map(|(_, value)| value.into_owned())
+            .unwrap_or_default();
+        }
+        if let Some(header) = name.strip_prefix("header.") {
+            return self
+                .headers
+                .get(header)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or_default()
+                .to_string();
+        }
+        match name {
+            "api_id" => self.api_id.to_string(),
+            "method" => self.method.clone(),
+            "path" => self.path.clone(),
+            "client_ip" => self.client_ip.map(|ip| ip.to_string()).unwrap_or_default(),
+            "timestamp" => chrono::Utc::now().timestamp().to_string(),
+            "uuid" => Uuid::new_v4().to_string(),
+            "status" => status
+                .map(|status| status.as_u16().to_string())
+                .unwrap_or_default(),
+            _ => String::new(),
+        }
+    }
+}
+
+/// Checks an API's transformation rules before it is saved.
+pub fn validate(config: Option<&ProxyConfig>) -> Result<(), String> {
+    let Some(transforms) = config.and_then(|config| config.transforms.as_ref()) else {
+        return Ok(());
+    };
+
+    for rule in &transforms.request {
+        match rule {
+            RequestTransform::RewritePath { from, to } => {
+                if !from.starts_with('/') || !to.starts_with('/') {
+                    return Err("rewrite_path prefixes must start with /".to_string());
+                }
+            }
+            RequestTransform::RenameQuery { from, to } => {
+                if from.is_empty() || to.is_empty() {
+                    return Err("rename_query needs both names".to_string());
+                }
+            }
+            RequestTransform::SetQuery { name, .. } | RequestTransform::RemoveQuery { name } => {
+                if name.is_empty() {
+                    return Err("query parameter names can't be empty".to_string());
+                }
+            }
+            RequestTransform::SetHeader { name, .. } | RequestTransform::RemoveHeader { name } => {
+                header_name(name)?;
+            }
+        }
+    }
+    for rule in &transforms.response {
+        match rule {
+            ResponseTransform::SetHeader { name, .. }
+            | ResponseTransform::RemoveHeader { name } => {
+                header_name(name)?;
+            }
+            ResponseTransform::Select { path } => {
+                parse_path(path)?;
+            }
+            ResponseTransform::Project { fields } => {
+                for path in fields.values() {
+                    parse_path(path)?;
+                }
+            }
+            ResponseTransform::Remove { paths } => {
+                for path in paths {
+                    parse_path(path)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn header_name(name: &str) -> Result<HeaderName, String> {
+    HeaderName::try_from(name).map_err(|_| format!("{} is not a valid header name", name))
+}
+
+pub fn apply_request(
+    rules: &[RequestTransform],
+    request: &mut UpstreamRequest,
+    context: &TemplateContext,
+) {
+    for rule in rules {
+        match rule {
+            RequestTransform::RewritePath { from, to } => {
+                let from = from.trim_end_matches('/');
+                let rest = match request.path.strip_prefix(from) {
+                    Some(rest) if rest.is_empty() || rest.starts_with('/') => rest,
+                    _ => continue,
+                };
+                request.path = format!("{}{}", to.trim_end_matches('/'), rest);
+                if request.path.is_empty() {
+                    request.path = "/".to_string();
+                }
+            }
+            RequestTransform::RenameQuery { from, to } => {
+                edit_query(request, |params| {
+                    for (key, _) in params.iter_mut().filter(|(key, _)| key == from) {
+                        *key = to.clone();
+                    }
+                });
+            }
+            RequestTransform::SetQuery { name, value } => {
+                let value = context.render(value, None);
+                edit_query(request, |params| {
+                    params.retain(|(key, _)| key != name);
+                    params.push((name.clone(), value));
+                });
+            }
+            RequestTransform::RemoveQuery { name } => {
+                edit_query(request, |params| params.retain(|(key, _)| key != name));
+            }
+            RequestTransform::SetHeader { name, value } => {
+                set_header(&mut request.headers, name, &context.render(value, None));
+            }
+            RequestTransform::RemoveHeader { name } => {
+                request.headers.remove(name.as_str());
+            }
+        }
+    }
+}
+
+fn edit_query(request: &mut UpstreamRequest, edit: impl FnOnce(&mut Vec<(String, String)>)) {
+    let mut params: Vec<(String, String)> =
+        url::form_urlencoded::parse(request.query.as_deref().unwrap_or_default().as_bytes())
+            .into_owned()
+            .collect();
+    edit(&mut params);
+    request.query = (!params.is_empty()).then(|| {
+        url::form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(&params)
+            .finish()
+    });
+}
+
+fn set_header(headers: &mut HeaderMap, name: &str, value: &str) {
+    if let (Ok(name), Ok(value)) = (HeaderName::try_from(name), HeaderValue::try_from(value)) {
+        headers.insert(name, value);
+    }
+}
+
+pub fn apply_response_headers(
+    rules: &[ResponseTransform],
+    headers: &mut HeaderMap,
+    context: &TemplateContext,
+    status: StatusCode,
+) {
+    for rule in rules {
+        match rule {
+            ResponseTransform::SetHeader { name, value } => {
+                set_header(headers, name, &context.render(value, Some(status)));
+            }
+            ResponseTransform::RemoveHeader { name } => {
+                headers.remove(name.as_str());
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Whether any rule rewrites the body of responses with these headers. Only
+/// uncompressed JSON bodies are rewritten.
+pub fn rewrites_body(rules: &[ResponseTransform], headers: &HeaderMap) -> bool {
+    let is_json = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.to_ascii_lowercase().contains("json"));
+    let encoded = headers
+        .get(header::CONTENT_ENCODING)
+        .is_some_and(|value| value != "identity");
+    is_json
+        && !encoded
+        && rules.iter().any(|rule| {
+            matches!(
+                rule,
+                ResponseTransform::Select { .. }
+                    | ResponseTransform::Project { .. }
+                    | ResponseTransform::Remove { .. }
+            )
+        })
+}
+
+/// Applies the body rules to a JSON body. Bodies that aren't valid JSON are
+/// returned unchanged.
+pub fn apply_response_body(rules: &[ResponseTransform], body: Vec<u8>) -> Vec<u8> {
+    let Ok(mut value) = serde_json::from_slice::<Value>(&body) else {
+        return body;
+    };
+    for rule in rules {
+        match rule {
+            ResponseTransform::Select { path } => {
+                if let Ok(path) = parse_path(path) {
+                    value = evaluate(&value, &path);
+                }
+            }
+            ResponseTransform::Project { fields } => {
+                let projected = fields
+                    .iter()
+                    .filter_map(|(name, path)| {
+                        let path = parse_path(path).ok()?;
+                        Some((name.clone(), evaluate(&value, &path)))
+                    })
+                    .collect();
+                value = Value::Object(projected);
+            }
+            ResponseTransform::Remove { paths } => {
+                for path in paths {
+                    if let Ok(path) = parse_path(path) {
+                        remove(&mut value, &path);
+                    }
+                }
+            }
+            ResponseTransform::SetHeader { .. } | ResponseTransform::RemoveHeader { .. } => {}
+        }
+    }
+    serde_json::to_vec(&value).unwrap_or(body)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Segment {
+    Field(String),
+    Index(usize),
+    Wildcard,
+}
+
+/// Parses `$.a.b[0]`, `$.items[*].id` or `$['odd key']`. The leading `$` is
+/// optional.
+fn parse_path(path: &str) -> Result<Vec<Segment>, String> {
+    let invalid = || format!("{} is not a valid JSON path", path);
+    let trimmed = path.trim();
+    let normalized = match trimmed.strip_prefix('$') {
+        Some(rest) => rest.to_string(),
+        None if trimmed.starts_with(['.', '[']) => trimmed.to_string(),
+        None => format!(".{}", trimmed),
+    };
+
+    let mut rest = normalized.as_str();
+    let mut segments = Vec::new();
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix('.') {
+            let end = after.find(['.', '[']).unwrap_or(after.len());
+            let name = &after[..end];
+            segments.push(match name {
+                "" => return Err(invalid()),
+                "*" => Segment::Wildcard,
+                _ => Segment::Field(name.to_string()),
+            });
+            rest = &after[end..];
+        } else if let Some(after) = rest.strip_prefix('[') {
+            let end = after.find(']').ok_or_else(invalid)?;
+            let inner = after[..end].trim();
+            segments.push(if inner == "*" {
+                Segment::Wildcard
+            } else if let Ok(index) = inner.parse() {
+                Segment::Index(index)
+            } else if inner.len() >= 2
+                && ((inner.starts_with('\'') && inner.ends_with('\''))
+                    || (inner.starts_with('"') && inner.ends_with('"')))
+            {
+                Segment::Field(inner[1..inner.len() - 1].to_string())
+            } else {
+                return Err(invalid());
+            });
+            rest = &after[end + 1..];
+        } else {
+            return Err(invalid());
+        }
+    }
+    Ok(segments)
+}
+
+/// The value at `path`, or `null` when there is none. Paths with a wildcard
+/// evaluate to an array of every match.
+fn evaluate(value: &Value, path: &[Segment]) -> Value {
+    let mut matches = Vec::new();
+    select(value, path, &mut matches);
+    if path.contains(&Segment::Wildcard) {
+        Value::Array(matches.into_iter().cloned().collect())
+    } else {
+        matches.first().map(|v| (*v).clone()).unwrap_or(Value::Null)
+    }
+}
+
+fn select<'a>(value: &'a Value, path: &[Segment], matches: &mut Vec<&'a Value>) {
+    let Some((segment, rest)) = path.split_first() else {
+        matches.push(value);
+        return;
+    };
+    match (segment, value) {
+        (Segment::Field(name), Value::Object(map)) => {
+            if let Some(child) = map.get(name) {
+                select(child, rest, matches);
+            }
+        }
+        (Segment::Index(index), Value::Array(items)) => {
+            if let Some(child) = items.get(*index) {
+                select(child, rest, matches);
+            }
+        }
+        (Segment::Wildcard, Value::Array(items)) => {
+            for child in items {
+                select(child, rest, matches);
+            }
+        }
+        (Segment::Wildcard, Value::Object(map)) => {
+            for child in map.values() {
+                select(child, rest, matches);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn remove(value: &mut Value, path: &[Segment]) {
+    let Some((segment, rest)) = path.split_first() else {
+        return;
+    };
+    if rest.is_empty() {
+        match (segment, value) {
+            (Segment::Field(name), Value::Object(map)) => {
+                map.remove(name);
+            }
+            (Segment::Index(index), Value::Array(items)) if *index < items.len() => {
+                items.remove(*index);
+            }
+            (Segment::Wildcard, Value::Array(items)) => items.clear(),
+            (Segment::Wildcard, Value::Object(map)) => map.clear(),
+            _ => {}
+        }
+        return;
+    }
+
+    match (segment, value) {
+        (Segment::Field(name), Value::Object(map)) => {
+            if let Some(child) = map.get_mut(name) {
+                remove(child, rest);
+            }
+        }
+        (Segment::Index(index), Value::Array(items)) => {
+            if let Some(child) = items.get_mut(*index) {
+                remove(child, rest);
+            }
+        }
+        (Segment::Wildcard, Value::Array(items)) => {
+            for child in items {
+                remove(child, rest);
+            }
+        }
+        (Segment::Wildcard, Value::Object(map)) => {
+            for child in map.values_mut() {
+                remove(child, rest);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use shared::ResponseFormat;
+
+    fn response_rules(rules: Value) -> Vec<ResponseTransform> {
+        serde_json::from_value(rules).unwrap()
+    }
+
+    fn transform_body(rules: Value, body: Value) -> Value {
+        let body = apply_response_body(&response_rules(rules), body.to_string().into_bytes());
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    fn request(path: &str, query: Option<&str>) -> UpstreamRequest {
+        UpstreamRequest {
+            method: reqwest::Method::GET,
+            path: path.to_string(),
+            query: query.map(str::to_string),
+            headers: HeaderMap::new(),
+            body: Default::default(),
+            response_format: ResponseFormat::Passthrough,
+        }
+    }
+
+    #[test]
+    fn parses_paths() {
+        use Segment::*;
+        let field = |name: &str| Field(name.to_string());
+        assert_eq!(
+            parse_path("$.data.items[0].id").unwrap(),
+            [field("data"), field("items"), Index(0), field("id")]
+        );
+        assert_eq!(
+            parse_path("items[*].id").unwrap(),
+            [field("items"), Wildcard, field("id")]
+        );
+        assert_eq!(parse_path(".a.*").unwrap(), [field("a"), Wildcard]);
+        assert_eq!(
+            parse_path("$['odd key'][\"x.y\"]").unwrap(),
+            [field("odd key"), field("x.y")]
+        );
+        assert_eq!(parse_path("$").unwrap(), []);
+        for invalid in ["$.", "$.a..b", "$[0", "$[abc]", "$a", "$['x]"] {
+            assert!(parse_path(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn selects_and_projects_bodies() {
+        let body = json!({
+            "data": { "items": [{ "id": 1, "name": "a" }, { "id": 2, "name": "b" }] },
+            "meta": { "total": 2 },
+        });
+        assert_eq!(
+            transform_body(
+                json!([{ "type": "select", "path": "$.data.items[*].id" }]),
+                body.clone()
+            ),
+            json!([1, 2])
+        );
+        assert_eq!(
+            transform_body(
+                json!([{ "type": "select", "path": "$.data.items[1]" }]),
+                body.clone()
+            ),
+            json!({ "id": 2, "name": "b" })
+        );
+        assert_eq!(
+            transform_body(
+                json!([{ "type": "select", "path": "$.missing" }]),
+                body.clone()
+            ),
+            Value::Null
+        );
+        assert_eq!(
+            transform_body(
+                json!([{ "type": "project", "fields": {
+                    "names": "$.data.items[*].name",
+                    "total": "$.meta.total",
+                } }]),
+                body
+            ),
+            json!({ "names": ["a", "b"], "total": 2 })
+        );
+    }
+
+    #[test]
+    fn removes_paths_from_bodies() {
+        let body = json!({
+            "items": [{ "id": 1, "secret": "x" }, { "id": 2, "secret": "y" }],
+            "debug": true,
+            "list": [1, 2, 3],
+        });
+        assert_eq!(
+            transform_body(
+                json!([{ "type": "remove", "paths": ["$.items[*].secret", "debug", "$.list[1]"] }]),
+                body
+            ),
+            json!({ "items": [{ "id": 1 }, { "id": 2 }], "list": [1, 3] })
+        );
+    }
+
+    #[test]
+    fn leaves_bodies_that_arent_json() {
+        let rules = response_rules(json!([{ "type": "select", "path": "$.a" }]));
+        assert_eq!(
+            apply_response_body(&rules, b"not json".to_vec()),
+            b"not json"
+        );
+    }
+
+    #[test]
+    fn only_rewrites_plain_json_bodies() {
+        let rules = response_rules(json!([{ "type": "remove", "paths": ["a"] }]));
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        );
+        assert!(rewrites_body(&rules, &headers));
+        headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+        assert!(!rewrites_body(&rules, &headers));
+        headers.remove(header::CONTENT_ENCODING);
+        headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("text/html"));
+        assert!(!rewrites_body(&rules, &headers));
+        let header_only = response_rules(json!([{ "type": "remove_header", "name": "x-a" }]));
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        );
+        assert!(!rewrites_body(&header_only, &headers));
+    }
+
+    #[test]
+    fn rewrites_requests() {
+        let rules: Vec<RequestTransform> = serde_json::from_value(json!([
+            { "type": "rewrite_path", "from": "/v1", "to": "/api/v2" },
+            { "type": "rename_query", "from": "q", "to": "query" },
+            { "type": "set_query", "name": "caller", "value": "{{header.x-user}}" },
+            { "type": "remove_query", "name": "debug" },
+            { "type": "set_header", "name": "x-path", "value": "{{method}} {{path}}" },
+        ]))
+        .unwrap();
+        let mut upstream = request("/v1/users", Some("q=a+b&debug=1"));
+        upstream
+            .headers
+            .insert("x-user", HeaderValue::from_static("alice"));
+        let context = TemplateContext::new(Uuid::nil(), &upstream, None);
+        apply_request(&rules, &mut upstream, &context);
+
+        assert_eq!(upstream.path, "/api/v2/users");
+        assert_eq!(upstream.query.as_deref(), Some("query=a+b&caller=alice"));
+        assert_eq!(upstream.headers["x-path"], "GET /v1/users");
+
+        // Only whole path segments are rewritten.
+        let mut other = request("/v10/users", None);
+        apply_request(&rules, &mut other, &context);
+        assert_eq!(other.path, "/v10/users");
+    }
+
+    #[test]
+    fn renders_templates() {
+        let upstream = request("/users", Some("page=2"));
+        let context = TemplateContext::new(Uuid::nil(), &upstream, "10.0.0.1".parse().ok());
+        assert_eq!(
+            context.render(
+                "{{ method }} {{path}}?{{query.page}} from {{client_ip}} -> {{status}}{{unknown}}",
+                Some(StatusCode::CREATED)
+            ),
+            "GET /users?2 from 10.0.0.1 -> 201"
+        );
+        assert_eq!(context.render("{{unclosed", None), "{{unclosed");
+    }
+
+    #[test]
+    fn validates_rules() {
+        let config = |transforms: Value| ProxyConfig {
+            transforms: Some(serde_json::from_value(transforms).unwrap()),
+            ..Default::default()
+        };
+        assert!(validate(Some(&config(json!({
+            "request": [{ "type": "rewrite_path", "from": "/a", "to": "/b" }],
+            "response": [{ "type": "select", "path": "$.data" }],
+        }))))
+        .is_ok());
+        assert!(validate(Some(&config(json!({
+            "request": [{ "type": "rewrite_path", "from": "a", "to": "/b" }],
+        }))))
+        .is_err());
+        assert!(validate(Some(&config(json!({
+            "request": [{ "type": "set_header", "name": "bad header", "value": "x" }],
+        }))))
+        .is_err());
+        assert!(validate(Some(&config(json!({
+            "response": [{ "type": "remove", "paths": ["$[oops]"] }],
+        }))))
+        .is_err());
+    }
+}
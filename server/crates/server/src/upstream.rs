@@ -0,0 +1,345 @@
+use crate::breaker::{BreakerSettings, CircuitBreakers, MAX_FAILURE_THRESHOLD, MAX_OPEN_SECONDS};
+use crate::ssrf::UpstreamPolicy;
+use chrono::{DateTime, Utc};
+use reqwest::{Client, ClientBuilder};
+use shared::{ApiLimits, ProxyConfig};
+use std::collections::HashMap;
+use std::env;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Connect timeouts are rounded up to one of these, and capped at the last, so
+/// the pool holds a bounded number of clients whatever providers configure.
+const CONNECT_TIMEOUT_BUCKETS: [Duration; 5] = [
+    Duration::from_secs(1),
+    Duration::from_secs(2),
+    Duration::from_secs(5),
+    Duration::from_secs(10),
+    Duration::from_secs(30),
+];
+
+/// Server-wide fallbacks for settings an API leaves unset in its `ProxyConfig`.
+#[derive(Clone, Debug)]
+pub struct UpstreamDefaults {
+    pub connect_timeout: Duration,
+    pub timeout: Duration,
+    pub max_request_bytes: u64,
+    pub max_response_bytes: u64,
+    pub breaker: BreakerSettings,
+}
+
+impl UpstreamDefaults {
+    pub fn from_env() -> Self {
+        let number = |name: &str, default: u64| {
+            env::var(name)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default)
+        };
+        Self {
+            connect_timeout: Duration::from_millis(number("UPSTREAM_CONNECT_TIMEOUT_MS", 5_000)),
+            timeout: Duration::from_millis(number("UPSTREAM_TIMEOUT_MS", 30_000)),
+            max_request_bytes: number("PROXY_MAX_REQUEST_BYTES", 2 * 1024 * 1024),
+            max_response_bytes: number("PROXY_MAX_RESPONSE_BYTES", 10 * 1024 * 1024),
+            breaker: BreakerSettings {
+                failure_threshold: number("CIRCUIT_BREAKER_FAILURE_THRESHOLD", 5) as u32,
+                open_duration: Duration::from_secs(number("CIRCUIT_BREAKER_OPEN_SECS", 30)),
+            },
+        }
+    }
+}
+
+/// The effective settings for one upstream call, after applying the defaults.
+#[derive(Clone, Debug)]
+pub struct UpstreamSettings {
+    pub connect_timeout: Duration,
+    pub timeout: Duration,
+    pub max_request_bytes: u64,
+    pub max_response_bytes: u64,
+    pub retry: RetrySettings,
+    pub breaker: BreakerSettings,
+}
+
+impl UpstreamSettings {
+    pub fn limits(&self) -> ApiLimits {
+        ApiLimits {
+            max_request_bytes: self.max_request_bytes,
+            max_response_bytes: self.max_response_bytes,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RetrySettings {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl RetrySettings {
+    /// Delay before retry number `retry` (starting at 1): the base delay doubled
+    /// for every earlier retry, capped at the maximum.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let factor = 2u32.saturating_pow(retry.saturating_sub(1).min(16));
+        self.base_delay.saturating_mul(factor).min(self.max_delay)
+    }
+}
+
+/// A client built for one version of an API's TLS settings.
+type TlsClient = (DateTime<Utc>, Client);
+
+/// Pool of upstream HTTP clients shared by every proxied request.
+///
+/// `reqwest::Client` already pools connections and TLS sessions internally, but
+/// the connect timeout is fixed when a client is built, so one client is kept per
+/// connect timeout bucket. APIs with their own TLS settings get clients of
+/// their own. Every client resolves hosts and follows redirects through the
+/// `UpstreamPolicy`.
+#[derive(Clone, Debug)]
+pub struct UpstreamClients {
+    defaults: UpstreamDefaults,
+    policy: UpstreamPolicy,
+    breakers: CircuitBreakers,
+    clients: Arc<RwLock<HashMap<Duration, Client>>>,
+    tls_clients: Arc<RwLock<HashMap<(Uuid, Duration), TlsClient>>>,
+}
+
+impl UpstreamClients {
+    pub fn new(defaults: UpstreamDefaults, policy: UpstreamPolicy) -> Self {
+        Self {
+            defaults,
+            policy,
+            breakers: CircuitBreakers::default(),
+            clients: Arc::new(RwLock::new(HashMap::new())),
+            tls_clients: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    pub fn policy(&self) -> &UpstreamPolicy {
+        &self.policy
+    }
+
+    pub fn breakers(&self) -> &CircuitBreakers {
+        &self.breakers
+    }
+
+    /// The settings for a call to an API with `config`. The total timeout and
+    /// size limits can only be lowered below the server's, and retry delays
+    /// never exceed the timeout.
+    pub fn settings(&self, config: Option<&ProxyConfig>) -> UpstreamSettings {
+        let config = config.cloned().unwrap_or_default();
+        let timeout = config.timeout_ms.map_or(self.defaults.timeout, |ms| {
+            Duration::from_millis(ms).min(self.defaults.timeout)
+        });
+        UpstreamSettings {
+            connect_timeout: connect_timeout_bucket(
+                config
+                    .connect_timeout_ms
+                    .map(Duration::from_millis)
+                    .unwrap_or(self.defaults.connect_timeout),
+            ),
+            timeout,
+            max_request_bytes: config
+                .max_request_bytes
+                .map_or(self.defaults.max_request_bytes, |max| {
+                    max.min(self.defaults.max_request_bytes)
+                }),
+            max_response_bytes: config
+                .max_response_bytes
+                .map_or(self.defaults.max_response_bytes, |max| {
+                    max.min(self.defaults.max_response_bytes)
+                }),
+            retry: match config.retry {
+                Some(retry) => RetrySettings {
+                    max_attempts: retry.max_attempts.max(1),
+                    base_delay: Duration::from_millis(retry.base_delay_ms.unwrap_or(100))
+                        .min(timeout),
+                    max_delay: Duration::from_millis(retry.max_delay_ms.unwrap_or(2_000))
+                        .min(timeout),
+                },
+                None => RetrySettings {
+                    max_attempts: 1,
+                    base_delay: Duration::ZERO,
+                    max_delay: Duration::ZERO,
+                },
+            },
+            breaker: {
+                let overrides = config.circuit_breaker.unwrap_or_default();
+                BreakerSettings {
+                    failure_threshold: overrides
+                        .failure_threshold
+                        .map_or(self.defaults.breaker.failure_threshold, |threshold| {
+                            threshold.min(MAX_FAILURE_THRESHOLD)
+                        })
+                        .max(1),
+                    open_duration: overrides
+                        .open_seconds
+                        .map_or(self.defaults.breaker.open_duration, |seconds| {
+                            Duration::from_secs(seconds.min(MAX_OPEN_SECONDS))
+                        }),
+                }
+            },
+        }
+    }
+
+    pub fn client(&self, settings: &UpstreamSettings) -> anyhow::Result<Client> {
+        if let Some(client) = self
+            .clients
+            .read()
+            .expect("upstream client pool poisoned")
+            .get(&settings.connect_timeout)
+        {
+            return Ok(client.clone());
+        }
+
+        let client = self.builder(settings).build()?;
+        let mut clients = self.clients.write().expect("upstream client pool poisoned");
+        Ok(clients
+            .entry(settings.connect_timeout)
+            .or_insert(client)
+            .clone())
+    }
+
+    /// The client for an API with its own TLS settings. `version` identifies
+    /// the settings; `configure` is only called to build a client when there is
+    /// none for this version yet.
+    pub fn tls_client(
+        &self,
+        api_id: Uuid,
+        version: DateTime<Utc>,
+        settings: &UpstreamSettings,
+        configure: impl FnOnce(ClientBuilder) -> anyhow::Result<ClientBuilder>,
+    ) -> anyhow::Result<Client> {
+        let key = (api_id, settings.connect_timeout);
+        if let Some((built_for, client)) = self
+            .tls_clients
+            .read()
+            .expect("upstream client pool poisoned")
+            .get(&key)
+        {
+            if *built_for == version {
+                return Ok(client.clone());
+            }
+        }
+
+        let client = configure(self.builder(settings))?.build()?;
+        self.tls_clients
+            .write()
+            .expect("upstream client pool poisoned")
+            .insert(key, (version, client.clone()));
+        Ok(client)
+    }
+
+    fn builder(&self, settings: &UpstreamSettings) -> ClientBuilder {
+        Client::builder()
+            .connect_timeout(settings.connect_timeout)
+            .dns_resolver(self.policy.resolver())
+            .redirect(self.policy.redirect_policy())
+    }
+}
+
+/// The smallest bucket at least as long as `requested`, or the longest bucket.
+fn connect_timeout_bucket(requested: Duration) -> Duration {
+    CONNECT_TIMEOUT_BUCKETS
+        .into_iter()
+        .find(|bucket| *bucket >= requested)
+        .unwrap_or(CONNECT_TIMEOUT_BUCKETS[CONNECT_TIMEOUT_BUCKETS.len() - 1])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::RetryConfig;
+
+    fn clients() -> UpstreamClients {
+        UpstreamClients::new(
+            UpstreamDefaults {
+                connect_timeout: Duration::from_millis(3_000),
+                timeout: Duration::from_secs(30),
+                max_request_bytes: 1024,
+                max_response_bytes: 4096,
+                breaker: BreakerSettings {
+                    failure_threshold: 5,
+                    open_duration: Duration::from_secs(30),
+                },
+            },
+            UpstreamPolicy::default(),
+        )
+    }
+
+    #[test]
+    fn rounds_connect_timeouts_up_to_a_bucket() {
+        let bucket = |ms| connect_timeout_bucket(Duration::from_millis(ms)).as_secs();
+        assert_eq!(bucket(0), 1);
+        assert_eq!(bucket(1_000), 1);
+        assert_eq!(bucket(1_001), 2);
+        assert_eq!(bucket(7_500), 10);
+        assert_eq!(bucket(600_000), 30);
+    }
+
+    #[test]
+    fn applies_defaults_and_caps() {
+        let clients = clients();
+        let settings = clients.settings(None);
+        assert_eq!(settings.connect_timeout, Duration::from_secs(5));
+        assert_eq!(settings.timeout, Duration::from_secs(30));
+        assert_eq!(settings.retry.max_attempts, 1);
+
+        let settings = clients.settings(Some(&ProxyConfig {
+            connect_timeout_ms: Some(1_234),
+            max_request_bytes: Some(1 << 20),
+            max_response_bytes: Some(100),
+            ..Default::default()
+        }));
+        assert_eq!(settings.connect_timeout, Duration::from_secs(2));
+        assert_eq!(settings.max_request_bytes, 1024);
+        assert_eq!(settings.max_response_bytes, 100);
+
+        let settings = clients.settings(Some(&ProxyConfig {
+            timeout_ms: Some(5_000),
+            ..Default::default()
+        }));
+        assert_eq!(settings.timeout, Duration::from_secs(5));
+
+        let settings = clients.settings(Some(&ProxyConfig {
+            timeout_ms: Some(u64::MAX),
+            retry: Some(RetryConfig {
+                max_attempts: 3,
+                base_delay_ms: Some(u64::MAX),
+                max_delay_ms: Some(u64::MAX),
+            }),
+            ..Default::default()
+        }));
+        assert_eq!(settings.timeout, Duration::from_secs(30));
+        assert_eq!(settings.retry.base_delay, Duration::from_secs(30));
+        assert_eq!(settings.retry.max_delay, Duration::from_secs(30));
+    }
+
+    #[test]
+    fn keeps_one_client_per_bucket() {
+        let clients = clients();
+        for ms in [1, 1_500, 1_999, 2_000, 9_000, 60_000, 3_600_000] {
+            let settings = clients.settings(Some(&ProxyConfig {
+                connect_timeout_ms: Some(ms),
+                ..Default::default()
+            }));
+            clients.client(&settings).unwrap();
+        }
+        assert_eq!(clients.clients.read().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn backs_off_exponentially_up_to_the_max_delay() {
+        let settings = RetrySettings {
+            max_attempts: 5,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(500),
+        };
+        let delays: Vec<u128> = [1, 2, 3, 4, 100]
+            .into_iter()
+            .map(|retry| settings.backoff(retry).as_millis())
+            .collect();
+        assert_eq!(delays, [100, 200, 400, 500, 500]);
+    }
+}
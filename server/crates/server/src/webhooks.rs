@@ -0,0 +1,370 @@
+use crate::ssrf::UpstreamPolicy;
+use database::{DueDeliveryRow, WebhookRepository};
+use hmac::{Hmac, Mac};
+use reqwest::{Client, StatusCode};
+use serde_json::json;
+use sha2::Sha256;
+use shared::{Api, WebhookEvent};
+use std::env;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use url::Url;
+
+pub const SIGNATURE_HEADER: &str = "X-Enigma-Signature";
+pub const EVENT_HEADER: &str = "X-Enigma-Event";
+pub const DELIVERY_HEADER: &str = "X-Enigma-Delivery";
+
+const BATCH_SIZE: i64 = 50;
+const BASE_BACKOFF_SECS: i64 = 30;
+const MAX_BACKOFF_SECS: i64 = 6 * 60 * 60;
+
+#[derive(Clone, Debug)]
+pub struct DispatcherConfig {
+    pub poll_interval: Duration,
+    pub request_timeout: Duration,
+    pub max_attempts: i32,
+}
+
+impl DispatcherConfig {
+    pub fn from_env() -> Self {
+        let secs = |name: &str, default: u64| {
+            env::var(name)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default)
+        };
+        Self {
+            poll_interval: Duration::from_secs(secs("WEBHOOK_POLL_INTERVAL_SECS", 5)),
+            request_timeout: Duration::from_secs(secs("WEBHOOK_TIMEOUT_SECS", 10)),
+            max_attempts: secs("WEBHOOK_MAX_ATTEMPTS", 8) as i32,
+        }
+    }
+}
+
+/// Signs `body` the way receivers are expected to verify it:
+/// `t=<unix seconds>,v1=<hex HMAC-SHA256 of "<t>.<body>" keyed with the webhook secret>`.
+pub fn sign_payload(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    format!(
+        "t={},v1={}",
+        timestamp,
+        hex::encode(mac.finalize().into_bytes())
+    )
+}
+
+pub fn generate_secret() -> String {
+    format!(
+        "whsec_{}{}",
+        uuid::Uuid::new_v4().simple(),
+        uuid::Uuid::new_v4().simple()
+    )
+}
+
+/// Tells the provider's `api.suspended` webhooks that `api`'s circuit breaker
+/// for `origin` opened, cutting it off for `open_for`.
+pub fn notify_api_suspended(
+    webhook_repo: &WebhookRepository,
+    api: &Api,
+    origin: &str,
+    open_for: Duration,
+) {
+    let webhook_repo = webhook_repo.clone();
+    let (user_id, api_id) = (api.user_id, api.id);
+    let data = api_suspended_payload(api, origin, open_for);
+    tokio::spawn(async move {
+        if let Err(e) = webhook_repo
+            .enqueue_event(user_id, Some(api_id), WebhookEvent::ApiSuspended, data)
+            .await
+        {
+            tracing::warn!("Failed to enqueue api.suspended for API {}: {e:#}", api_id);
+        }
+    });
+}
+
+fn api_suspended_payload(api: &Api, origin: &str, open_for: Duration) -> serde_json::Value {
+    json!({
+        "api_id": api.id,
+        "user_id": api.user_id,
+        "reason": "circuit_breaker_open",
+        "upstream": origin,
+        "retry_after_seconds": open_for.as_secs(),
+    })
+}
+
+fn backoff(attempts: i32) -> chrono::Duration {
+    let exponent = attempts.clamp(0, 20) as u32;
+    let secs = BASE_BACKOFF_SECS
+        .saturating_mul(2i64.saturating_pow(exponent))
+        .min(MAX_BACKOFF_SECS);
+    chrono::Duration::seconds(secs)
+}
+
+/// Delivers queued webhook events. Receivers are reached through `policy`, like
+/// upstreams, so a webhook can't be pointed at an internal service.
+pub fn spawn_dispatcher(
+    webhook_repo: WebhookRepository,
+    config: DispatcherConfig,
+    policy: UpstreamPolicy,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let client = build_client(&config, &policy).expect("Failed to build webhook HTTP client");
+        let lease_seconds = config.request_timeout.as_secs() as i64 * 2 + 5;
+        let mut interval = tokio::time::interval(config.poll_interval);
+
+        loop {
+            interval.tick().await;
+            let due = match webhook_repo
+                .claim_due_deliveries(BATCH_SIZE, lease_seconds)
+                .await
+            {
+                Ok(due) => due,
+                Err(e) => {
+                    tracing::warn!("Failed to claim webhook deliveries: {e:#}");
+                    continue;
+                }
+            };
+            for delivery in due {
+                deliver(&client, &policy, &webhook_repo, &config, delivery).await;
+            }
+        }
+    })
+}
+
+fn build_client(config: &DispatcherConfig, policy: &UpstreamPolicy) -> reqwest::Result<Client> {
+    Client::builder()
+        .timeout(config.request_timeout)
+        .dns_resolver(policy.resolver())
+        .redirect(policy.redirect_policy())
+        .build()
+}
+
+async fn deliver(
+    client: &Client,
+    policy: &UpstreamPolicy,
+    webhook_repo: &WebhookRepository,
+    config: &DispatcherConfig,
+    delivery: DueDeliveryRow,
+) {
+    let (status_code, error) = match send(client, policy, &delivery).await {
+        Ok(status) if status.is_success() => {
+            let status = status.as_u16() as i32;
+            if let Err(e) = webhook_repo.mark_delivered(delivery.id, status).await {
+                tracing::warn!("Failed to record webhook delivery {}: {e:#}", delivery.id);
+            }
+            return;
+        }
+        Ok(status) => (
+            Some(status.as_u16() as i32),
+            format!("Receiver responded with {}", status),
+        ),
+        Err(e) => (None, e),
+    };
+
+    let attempts = delivery.attempts + 1;
+    let retry_at =
+        (attempts < config.max_attempts).then(|| chrono::Utc::now() + backoff(attempts - 1));
+    if let Err(e) = webhook_repo
+        .mark_attempt_failed(delivery.id, status_code, &error, retry_at)
+        .await
+    {
+        tracing::warn!("Failed to record webhook delivery {}: {e:#}", delivery.id);
+    }
+}
+
+/// Posts the signed event to the receiver and returns its status. IP literals
+/// are checked here; hostnames are checked by the client's resolver.
+async fn send(
+    client: &Client,
+    policy: &UpstreamPolicy,
+    delivery: &DueDeliveryRow,
+) -> Result<StatusCode, String> {
+    let url = Url::parse(&delivery.url).map_err(|e| format!("Invalid URL: {}", e))?;
+    policy.check_url(&url).map_err(|e| e.to_string())?;
+
+    let now = chrono::Utc::now();
+    let body = serde_json::json!({
+        "id": delivery.id,
+        "type": delivery.event,
+        "created_at": now,
+        "data": delivery.payload,
+    });
+    let body = serde_json::to_vec(&body).unwrap_or_default();
+    let signature = sign_payload(&delivery.secret, now.timestamp(), &body);
+
+    let response = client
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(EVENT_HEADER, &delivery.event)
+        .header(DELIVERY_HEADER, delivery.id.to_string())
+        .header(SIGNATURE_HEADER, signature)
+        .body(body)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(response.status())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderMap;
+    use axum::routing::post;
+    use axum::Router;
+    use tokio::sync::mpsc;
+    use uuid::Uuid;
+
+    fn config() -> DispatcherConfig {
+        DispatcherConfig {
+            poll_interval: Duration::from_secs(1),
+            request_timeout: Duration::from_secs(5),
+            max_attempts: 3,
+        }
+    }
+
+    fn delivery(url: String) -> DueDeliveryRow {
+        DueDeliveryRow {
+            id: Uuid::new_v4(),
+            event: "payment.settled".to_string(),
+            payload: serde_json::json!({ "amount": "0.01" }),
+            attempts: 0,
+            url,
+            secret: "whsec_test".to_string(),
+        }
+    }
+
+    /// A receiver on a local port that answers `status` and passes on what it
+    /// was sent.
+    async fn receiver(
+        status: StatusCode,
+    ) -> (String, mpsc::UnboundedReceiver<(HeaderMap, Vec<u8>)>) {
+        let (sender, received) = mpsc::unbounded_channel();
+        let app = Router::new().route(
+            "/hook",
+            post(
+                move |headers: HeaderMap, body: axum::body::Bytes| async move {
+                    let _ = sender.send((headers, body.to_vec()));
+                    status
+                },
+            ),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (format!("http://{}/hook", addr), received)
+    }
+
+    #[tokio::test]
+    async fn delivers_signed_events() {
+        let (url, mut received) = receiver(StatusCode::NO_CONTENT).await;
+        let policy = UpstreamPolicy::with_allowlist("127.0.0.1").unwrap();
+        let client = build_client(&config(), &policy).unwrap();
+        let delivery = delivery(url);
+
+        let status = send(&client, &policy, &delivery).await.unwrap();
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        let (headers, body) = received.recv().await.unwrap();
+        assert_eq!(headers[EVENT_HEADER], "payment.settled");
+        assert_eq!(headers[DELIVERY_HEADER], delivery.id.to_string());
+        let signature = headers[SIGNATURE_HEADER].to_str().unwrap();
+        let timestamp: i64 = signature
+            .strip_prefix("t=")
+            .and_then(|rest| rest.split(',').next())
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(signature, sign_payload("whsec_test", timestamp, &body));
+
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["id"], delivery.id.to_string());
+        assert_eq!(body["type"], "payment.settled");
+        assert_eq!(body["data"]["amount"], "0.01");
+    }
+
+    #[tokio::test]
+    async fn reports_receiver_errors() {
+        let (url, _received) = receiver(StatusCode::INTERNAL_SERVER_ERROR).await;
+        let policy = UpstreamPolicy::with_allowlist("127.0.0.1").unwrap();
+        let client = build_client(&config(), &policy).unwrap();
+
+        let status = send(&client, &policy, &delivery(url)).await.unwrap();
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn refuses_internal_receivers() {
+        let (url, mut received) = receiver(StatusCode::OK).await;
+        let policy = UpstreamPolicy::default();
+        let client = build_client(&config(), &policy).unwrap();
+
+        assert!(send(&client, &policy, &delivery(url.clone()))
+            .await
+            .is_err());
+        let by_name = url.replace("127.0.0.1", "localhost");
+        assert!(send(&client, &policy, &delivery(by_name)).await.is_err());
+        assert!(received.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn delivers_api_suspended_events() {
+        let (url, mut received) = receiver(StatusCode::OK).await;
+        let policy = UpstreamPolicy::with_allowlist("127.0.0.1").unwrap();
+        let client = build_client(&config(), &policy).unwrap();
+        let api: Api = serde_json::from_value(json!({
+            "id": Uuid::new_v4(),
+            "user_id": Uuid::new_v4(),
+            "name": "Flaky",
+            "description": null,
+            "category": "Data",
+            "kind": "Rest",
+            "base_url": "https://a.test/v1",
+            "endpoints": [],
+            "payment_config": null,
+            "proxy_config": null,
+            "graphql": null,
+            "created_at": chrono::Utc::now(),
+            "updated_at": chrono::Utc::now(),
+        }))
+        .unwrap();
+        let delivery = DueDeliveryRow {
+            event: WebhookEvent::ApiSuspended.as_str().to_string(),
+            payload: api_suspended_payload(&api, "https://a.test", Duration::from_secs(30)),
+            ..delivery(url)
+        };
+
+        send(&client, &policy, &delivery).await.unwrap();
+
+        let (headers, body) = received.recv().await.unwrap();
+        assert_eq!(headers[EVENT_HEADER], "api.suspended");
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["type"], "api.suspended");
+        assert_eq!(body["data"]["api_id"], api.id.to_string());
+        assert_eq!(body["data"]["upstream"], "https://a.test");
+        assert_eq!(body["data"]["retry_after_seconds"], 30);
+        assert_eq!(
+            WebhookEvent::parse("api.suspended"),
+            Some(WebhookEvent::ApiSuspended)
+        );
+    }
+
+    #[test]
+    fn signs_the_timestamp_and_body() {
+        let signature = sign_payload("secret", 1_700_000_000, b"{}");
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"secret").unwrap();
+        mac.update(b"1700000000.{}");
+        let expected = hex::encode(mac.finalize().into_bytes());
+        assert_eq!(signature, format!("t=1700000000,v1={}", expected));
+        assert_ne!(signature, sign_payload("other", 1_700_000_000, b"{}"));
+    }
+
+    #[test]
+    fn backs_off_exponentially_up_to_a_cap() {
+        assert_eq!(backoff(0).num_seconds(), BASE_BACKOFF_SECS);
+        assert_eq!(backoff(3).num_seconds(), BASE_BACKOFF_SECS * 8);
+        assert_eq!(backoff(30).num_seconds(), MAX_BACKOFF_SECS);
+    }
+}
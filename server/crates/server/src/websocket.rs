@@ -0,0 +1,233 @@
+use crate::app::AppState;
+use crate::proxy::{build_upstream_url, inject_secrets, PreparedRequest};
+use crate::upstream::UpstreamSettings;
+use crate::webhooks;
+use axum::{
+    extract::ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::Response,
+};
+use futures_util::{SinkExt, StreamExt};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::time::{timeout, timeout_at, Instant};
+use tokio_tungstenite::tungstenite::{
+    self, client::IntoClientRequest, protocol::WebSocketConfig as UpstreamSocketConfig,
+};
+use tokio_tungstenite::{Connector, MaybeTlsStream, WebSocketStream};
+use url::Url;
+use uuid::Uuid;
+
+type UpstreamSocket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Handshake headers the upstream connection generates itself.
+const HANDSHAKE_HEADERS: &[&str] = &[
+    "sec-websocket-key",
+    "sec-websocket-version",
+    "sec-websocket-extensions",
+];
+
+/// Close code (policy violation) sent to clients whose paid window ran out.
+const SESSION_EXPIRED: u16 = 1008;
+
+pub fn is_upgrade_request(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::UPGRADE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.eq_ignore_ascii_case("websocket"))
+}
+
+/// Connects to the upstream WebSocket and, once that has worked, upgrades the
+/// client connection and relays frames both ways until either side closes or
+/// the paid `window` runs out. The upstream is connected before the client gets
+/// its 101 so an unreachable upstream fails with 502 and no payment is settled.
+///
+/// Client messages are limited to the API's `max_request_bytes` and upstream
+/// messages to its `max_response_bytes`.
+pub async fn relay(
+    state: &AppState,
+    prepared: PreparedRequest,
+    window: Duration,
+    ws: WebSocketUpgrade,
+) -> Result<Response, StatusCode> {
+    let PreparedRequest {
+        api,
+        upstream: mut upstream_request,
+        ..
+    } = prepared;
+
+    inject_secrets(state, &api, &mut upstream_request).await?;
+
+    let settings = state.upstream.settings(api.proxy_config.as_ref());
+    let mut url = build_upstream_url(
+        &api.base_url,
+        &upstream_request.path,
+        upstream_request.query.as_deref(),
+    )?;
+    let origin = url.origin().ascii_serialization();
+    let scheme = if url.scheme() == "https" { "wss" } else { "ws" };
+    url.set_scheme(scheme)
+        .map_err(|_| StatusCode::BAD_GATEWAY)?;
+
+    let mut headers = upstream_request.headers;
+    for name in HANDSHAKE_HEADERS {
+        headers.remove(*name);
+    }
+
+    let tls = state.tls.connector(&api).await?;
+
+    let breakers = state.upstream.breakers();
+    if !breakers.try_acquire(api.id, &origin, &settings.breaker) {
+        return Err(StatusCode::SERVICE_UNAVAILABLE);
+    }
+    let (upstream, protocol) = match connect(state, &url, headers, &settings, tls).await {
+        Ok(connected) => {
+            breakers.record_success(api.id, &origin);
+            connected
+        }
+        Err(status) => {
+            if breakers.record_failure(api.id, &origin, &settings.breaker) {
+                let open_for = settings.breaker.open_duration;
+                webhooks::notify_api_suspended(&state.webhook_repo, &api, &origin, open_for);
+            }
+            return Err(status);
+        }
+    };
+
+    let mut ws =
+        ws.max_message_size(usize::try_from(settings.max_request_bytes).unwrap_or(usize::MAX));
+    if let Some(protocol) = protocol.and_then(|value| value.to_str().ok().map(str::to_string)) {
+        ws = ws.protocols([protocol]);
+    }
+    let api_id = api.id;
+    Ok(ws.on_upgrade(move |client| pump(client, upstream, window, api_id)))
+}
+
+/// Opens the upstream connection to an address the `UpstreamPolicy` allows,
+/// within the API's connect and total timeouts, using the API's own TLS
+/// connector if it has one. Returns the socket and the subprotocol the upstream
+/// picked, if any.
+async fn connect(
+    state: &AppState,
+    url: &Url,
+    headers: HeaderMap,
+    settings: &UpstreamSettings,
+    tls: Option<native_tls::TlsConnector>,
+) -> Result<(UpstreamSocket, Option<HeaderValue>), StatusCode> {
+    let deadline = Instant::now() + settings.timeout;
+    let host = url.host_str().ok_or(StatusCode::BAD_GATEWAY)?;
+    let port = url.port_or_known_default().unwrap_or(80);
+    let addrs = state
+        .upstream
+        .policy()
+        .resolve(host, port)
+        .await
+        .map_err(|e| {
+            tracing::warn!("Refusing WebSocket connection to {}: {}", host, e);
+            StatusCode::BAD_GATEWAY
+        })?;
+
+    let stream = match timeout(settings.connect_timeout, TcpStream::connect(&addrs[..])).await {
+        Ok(Ok(stream)) => stream,
+        Ok(Err(_)) => return Err(StatusCode::BAD_GATEWAY),
+        Err(_) => return Err(StatusCode::GATEWAY_TIMEOUT),
+    };
+
+    let mut request = url
+        .as_str()
+        .into_client_request()
+        .map_err(|_| StatusCode::BAD_GATEWAY)?;
+    request.headers_mut().extend(headers);
+    let config = UpstreamSocketConfig::default().max_message_size(Some(
+        usize::try_from(settings.max_response_bytes).unwrap_or(usize::MAX),
+    ));
+
+    let connector = tls.map(Connector::NativeTls);
+    let handshake =
+        tokio_tungstenite::client_async_tls_with_config(request, stream, Some(config), connector);
+    match timeout_at(deadline, handshake).await {
+        Ok(Ok((socket, response))) => {
+            let protocol = response
+                .headers()
+                .get(header::SEC_WEBSOCKET_PROTOCOL)
+                .cloned();
+            Ok((socket, protocol))
+        }
+        Ok(Err(e)) => {
+            tracing::warn!("WebSocket handshake with {} failed: {}", url, e);
+            Err(StatusCode::BAD_GATEWAY)
+        }
+        Err(_) => Err(StatusCode::GATEWAY_TIMEOUT),
+    }
+}
+
+async fn pump(client: WebSocket, upstream: UpstreamSocket, window: Duration, api_id: Uuid) {
+    let (mut client_tx, mut client_rx) = client.split();
+    let (mut upstream_tx, mut upstream_rx) = upstream.split();
+
+    let to_upstream = async {
+        while let Some(Ok(message)) = client_rx.next().await {
+            let closing = matches!(message, Message::Close(_));
+            if upstream_tx.send(into_upstream(message)).await.is_err() || closing {
+                break;
+            }
+        }
+    };
+    let to_client = async {
+        while let Some(Ok(message)) = upstream_rx.next().await {
+            let Some(message) = from_upstream(message) else {
+                continue;
+            };
+            let closing = matches!(message, Message::Close(_));
+            if client_tx.send(message).await.is_err() || closing {
+                break;
+            }
+        }
+    };
+
+    let expired = tokio::select! {
+        _ = to_upstream => false,
+        _ = to_client => false,
+        _ = tokio::time::sleep(window) => true,
+    };
+
+    if expired {
+        tracing::info!("WebSocket session for API {} expired", api_id);
+        let frame = CloseFrame {
+            code: SESSION_EXPIRED,
+            reason: "Paid session expired".into(),
+        };
+        let _ = client_tx.send(Message::Close(Some(frame))).await;
+    }
+    let _ = client_tx.close().await;
+    let _ = upstream_tx.close().await;
+}
+
+fn into_upstream(message: Message) -> tungstenite::Message {
+    match message {
+        Message::Text(text) => tungstenite::Message::Text(text.as_str().into()),
+        Message::Binary(data) => tungstenite::Message::Binary(data),
+        Message::Ping(data) => tungstenite::Message::Ping(data),
+        Message::Pong(data) => tungstenite::Message::Pong(data),
+        Message::Close(frame) => {
+            tungstenite::Message::Close(frame.map(|frame| tungstenite::protocol::CloseFrame {
+                code: frame.code.into(),
+                reason: frame.reason.as_str().into(),
+            }))
+        }
+    }
+}
+
+fn from_upstream(message: tungstenite::Message) -> Option<Message> {
+    Some(match message {
+        tungstenite::Message::Text(text) => Message::Text(text.as_str().into()),
+        tungstenite::Message::Binary(data) => Message::Binary(data),
+        tungstenite::Message::Ping(data) => Message::Ping(data),
+        tungstenite::Message::Pong(data) => Message::Pong(data),
+        tungstenite::Message::Close(frame) => Message::Close(frame.map(|frame| CloseFrame {
+            code: frame.code.into(),
+            reason: frame.reason.as_str().into(),
+        })),
+        tungstenite::Message::Frame(_) => return None,
+    })
+}
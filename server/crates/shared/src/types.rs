@@ -62,6 +62,32 @@ pub enum ApiCategory {
     Cybersecurity,
 }
 
+/// How callers talk to an API. REST APIs are described by their `endpoints`,
+/// GraphQL APIs by the schema in their `graphql` config.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub enum ApiKind {
+    #[default]
+    Rest,
+    GraphQL,
+}
+
+impl ApiKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiKind::Rest => "rest",
+            ApiKind::GraphQL => "graphql",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "rest" => Some(ApiKind::Rest),
+            "graphql" => Some(ApiKind::GraphQL),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Api {
     pub id: Uuid,
@@ -69,13 +95,97 @@ pub struct Api {
     pub name: String,
     pub description: Option<String>,
     pub category: ApiCategory,
+    pub kind: ApiKind,
     pub base_url: String,
     pub endpoints: Vec<ApiEndpoint>,
     pub payment_config: Option<PaymentConfig>,
+    pub proxy_config: Option<ProxyConfig>,
+    pub graphql: Option<GraphQLConfig>,
+    /// Effective size limits for calls through the proxy, filled in when APIs
+    /// are listed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limits: Option<ApiLimits>,
+    /// Result of the latest health check, filled in when APIs are listed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub health: Option<HealthState>,
     pub created_at: chrono::DateTime<chrono::Utc>,
     pub updated_at: chrono::DateTime<chrono::Utc>,
 }
 
+/// `Degraded` APIs failed their latest health check. `Unknown` ones haven't been
+/// checked recently, or have health checks turned off.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum HealthState {
+    Healthy,
+    Degraded,
+    Unknown,
+}
+
+/// Public health report for an API over the last `window_hours`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiStatus {
+    pub api_id: Uuid,
+    pub state: HealthState,
+    pub last_checked_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub last_status_code: Option<i32>,
+    pub last_error: Option<String>,
+    pub window_hours: u32,
+    pub checks: i64,
+    /// Share of checks in the window that succeeded, from 0 to 100.
+    pub uptime_percent: Option<f64>,
+    pub latency_ms: LatencyPercentiles,
+}
+
+/// One call made through the proxy to an API. `status` is what the caller got
+/// back, and `upstream_status` what the upstream answered, if it was reached.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CallLog {
+    pub id: Uuid,
+    pub api_id: Uuid,
+    pub method: String,
+    pub path: String,
+    /// Path template of the endpoint the call matched.
+    pub endpoint: Option<String>,
+    pub status: u16,
+    pub upstream_status: Option<u16>,
+    pub latency_ms: u64,
+    pub request_bytes: Option<u64>,
+    pub response_bytes: Option<u64>,
+    /// Wallet that paid for the call.
+    pub payer: Option<String>,
+    /// Transaction that settled the payment.
+    pub payment_id: Option<String>,
+    /// Only kept for sampled calls, and cut off at the server's size limit.
+    pub request_body: Option<String>,
+    pub response_body: Option<String>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Filters for a provider's view of an API's call log. Results are newest
+/// first; pass the oldest `created_at` seen as `until` to page back.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CallLogFilter {
+    pub status: Option<u16>,
+    pub payer: Option<String>,
+    pub endpoint: Option<String>,
+    pub since: Option<chrono::DateTime<chrono::Utc>>,
+    pub until: Option<chrono::DateTime<chrono::Utc>>,
+    pub limit: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LatencyPercentiles {
+    pub p50: Option<f64>,
+    pub p95: Option<f64>,
+    pub p99: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ApiLimits {
+    pub max_request_bytes: u64,
+    pub max_response_bytes: u64,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ApiEndpoint {
     pub path: String,
@@ -83,6 +193,23 @@ pub struct ApiEndpoint {
     pub headers: Option<serde_json::Value>,
     pub body_schema: Option<serde_json::Value>,
     pub query_params: Option<serde_json::Value>,
+    pub cache: Option<EndpointCache>,
+    /// Limits on calls to this endpoint, on top of the API's own.
+    #[serde(default)]
+    pub rate_limits: Vec<RateLimit>,
+}
+
+/// Opt-in response caching for a GET or HEAD endpoint. Upstream `Cache-Control`
+/// can shorten `ttl_seconds` or prevent caching, but never extend it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EndpointCache {
+    /// At most a day.
+    pub ttl_seconds: u64,
+    /// Request headers whose values become part of the cache key.
+    #[serde(default)]
+    pub vary_headers: Vec<String>,
+    /// Price of a cache hit in USDC. Unset charges `cost_per_request`, zero makes hits free.
+    pub hit_price: Option<f64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -96,6 +223,33 @@ pub enum HttpMethod {
     OPTIONS,
 }
 
+impl HttpMethod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HttpMethod::GET => "GET",
+            HttpMethod::POST => "POST",
+            HttpMethod::PUT => "PUT",
+            HttpMethod::DELETE => "DELETE",
+            HttpMethod::PATCH => "PATCH",
+            HttpMethod::HEAD => "HEAD",
+            HttpMethod::OPTIONS => "OPTIONS",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "GET" => Some(HttpMethod::GET),
+            "POST" => Some(HttpMethod::POST),
+            "PUT" => Some(HttpMethod::PUT),
+            "DELETE" => Some(HttpMethod::DELETE),
+            "PATCH" => Some(HttpMethod::PATCH),
+            "HEAD" => Some(HttpMethod::HEAD),
+            "OPTIONS" => Some(HttpMethod::OPTIONS),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CreateUserRequest {
     pub name: String,
@@ -107,9 +261,40 @@ pub struct CreateApiRequest {
     pub name: String,
     pub description: Option<String>,
     pub category: ApiCategory,
+    #[serde(default)]
+    pub kind: ApiKind,
     pub base_url: String,
     pub endpoints: Vec<ApiEndpoint>,
     pub payment_config: Option<PaymentConfig>,
+    pub proxy_config: Option<ProxyConfig>,
+    pub graphql: Option<GraphQLConfig>,
+}
+
+/// Schema and pricing for a GraphQL API. The schema can be given as SDL or as
+/// the JSON result of an introspection query, which is converted to SDL when
+/// the API is saved. Operations sent to `path` are validated against it.
+///
+/// Operations cost `complexity_price` per field they select, with list fields
+/// counted once per item requested through `first`, `last` or `limit`, or the
+/// API's `cost_per_request` when no complexity price is set. An operation named
+/// in `operation_prices` costs that much USDC, but never less than it would
+/// without the name, since callers choose the names.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct GraphQLConfig {
+    #[serde(default = "default_graphql_path")]
+    pub path: String,
+    pub sdl: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub introspection: Option<serde_json::Value>,
+    #[serde(default)]
+    pub operation_prices: std::collections::HashMap<String, f64>,
+    pub complexity_price: Option<f64>,
+    pub max_complexity: Option<u64>,
+    pub max_depth: Option<u32>,
+}
+
+fn default_graphql_path() -> String {
+    "/graphql".to_string()
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -119,11 +304,584 @@ pub struct PaymentConfig {
     pub enabled: bool,
 }
 
+/// Per-API settings for how the proxy talks to the upstream. Unset values fall
+/// back to the server-wide defaults; size limits can only be lowered.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ProxyConfig {
+    /// Rounded up to 1, 2, 5, 10 or 30 seconds, and capped at 30.
+    pub connect_timeout_ms: Option<u64>,
+    /// At most the server's `UPSTREAM_TIMEOUT_MS`.
+    pub timeout_ms: Option<u64>,
+    pub max_request_bytes: Option<u64>,
+    pub max_response_bytes: Option<u64>,
+    pub retry: Option<RetryConfig>,
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+    pub websocket: Option<WebSocketConfig>,
+    pub transforms: Option<TransformConfig>,
+    pub health_check: Option<HealthCheckConfig>,
+    pub load_balancing: Option<LoadBalancingConfig>,
+    pub sandbox: Option<SandboxConfig>,
+    pub call_logs: Option<CallLogConfig>,
+    /// Limits on calls to any of the API's endpoints.
+    #[serde(default)]
+    pub rate_limits: Vec<RateLimit>,
+    pub headers: Option<HeaderPolicy>,
+}
+
+/// Which headers the proxy passes on in each direction, on top of the fixed
+/// rules (hop-by-hop and payment headers are always dropped).
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct HeaderPolicy {
+    /// Client headers sent to the upstream.
+    #[serde(default)]
+    pub request: HeaderRules,
+    /// Upstream headers returned to the client.
+    #[serde(default)]
+    pub response: HeaderRules,
+}
+
+/// Header names are matched case-insensitively.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct HeaderRules {
+    /// When set, only these headers are passed on.
+    pub allow: Option<Vec<String>>,
+    /// Headers that are never passed on. For responses an unset list drops
+    /// `Set-Cookie`, `Server` and `X-Powered-By` unless they are allowed.
+    pub deny: Option<Vec<String>>,
+}
+
+/// Retries for idempotent methods after connection failures, timeouts and
+/// 502/503/504 responses, with exponential backoff between attempts. Retries
+/// never run past the API's total timeout.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RetryConfig {
+    /// Total attempts, including the first one.
+    pub max_attempts: u32,
+    pub base_delay_ms: Option<u64>,
+    pub max_delay_ms: Option<u64>,
+}
+
+/// Overrides for the breakers the API keeps per upstream origin. After
+/// `failure_threshold` (at most 1000) consecutive failures the origin is cut
+/// off for `open_seconds` (at most 3600), then a single probe request decides
+/// whether it recovered.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct CircuitBreakerConfig {
+    pub failure_threshold: Option<u32>,
+    pub open_seconds: Option<u64>,
+}
+
+/// Rewrites applied by the proxy, in order, to requests before they are sent
+/// upstream and to upstream responses before they are returned or cached.
+///
+/// Header and query values are templates: `{{api_id}}`, `{{method}}`,
+/// `{{path}}`, `{{client_ip}}`, `{{timestamp}}`, `{{uuid}}`, `{{query.NAME}}`
+/// and `{{header.NAME}}` refer to the caller's original request, and
+/// `{{status}}` to the upstream status in response rules. JSON paths use a
+/// JSONPath subset: `$.data.items[0]`, `$.items[*].id`, `$['odd key']`.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct TransformConfig {
+    #[serde(default)]
+    pub request: Vec<RequestTransform>,
+    #[serde(default)]
+    pub response: Vec<ResponseTransform>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RequestTransform {
+    /// Replaces a leading `from` path prefix with `to`.
+    RewritePath {
+        from: String,
+        to: String,
+    },
+    RenameQuery {
+        from: String,
+        to: String,
+    },
+    SetQuery {
+        name: String,
+        value: String,
+    },
+    RemoveQuery {
+        name: String,
+    },
+    SetHeader {
+        name: String,
+        value: String,
+    },
+    RemoveHeader {
+        name: String,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseTransform {
+    SetHeader {
+        name: String,
+        value: String,
+    },
+    RemoveHeader {
+        name: String,
+    },
+    /// Replaces a JSON body with the value at `path`.
+    Select {
+        path: String,
+    },
+    /// Replaces a JSON body with an object built from the values at the given
+    /// paths. Paths with `[*]` produce arrays.
+    Project {
+        fields: std::collections::BTreeMap<String, String>,
+    },
+    /// Deletes the values at the given paths from a JSON body.
+    Remove {
+        paths: Vec<String>,
+    },
+}
+
+/// Overrides for the background health checks, which probe `base_url` by
+/// default. Any response below 500 counts as healthy.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct HealthCheckConfig {
+    /// Path probed instead, such as `/health`.
+    pub path: Option<String>,
+    /// Turns the checks off. The API is then listed as `Unknown`.
+    #[serde(default)]
+    pub disabled: bool,
+}
+
+/// Spreads calls over several upstream targets instead of `base_url`. Targets
+/// whose circuit breaker is open or whose latest health check failed are left
+/// out, unless every target is.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LoadBalancingConfig {
+    pub targets: Vec<UpstreamTarget>,
+    #[serde(default)]
+    pub strategy: BalancingStrategy,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UpstreamTarget {
+    /// Base URL of the target, used the same way as the API's `base_url`.
+    pub url: String,
+    #[serde(default = "default_target_weight")]
+    pub weight: u32,
+}
+
+fn default_target_weight() -> u32 {
+    1
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum BalancingStrategy {
+    /// Targets take turns in proportion to their weight.
+    #[default]
+    RoundRobin,
+    /// The target with the lowest recent latency, divided by its weight.
+    LeastLatency,
+    /// The first target in the list that is in rotation.
+    Failover,
+}
+
+/// Lets callers try the API for free on `/sandbox/{api_id}/...`, which answers
+/// with the API's mock responses instead of calling the upstream.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct SandboxConfig {
+    /// Delay added to every sandbox response.
+    #[serde(default)]
+    pub latency_ms: u64,
+    /// Saves successful live responses as mocks for endpoints without one.
+    #[serde(default)]
+    pub record: bool,
+}
+
+/// A token bucket holding `burst` calls (`requests` when unset) that refills at
+/// `requests` per `period_seconds` (at most 30 days). Calls over the limit are
+/// refused with 429 before payment is verified.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RateLimit {
+    pub requests: u32,
+    pub period_seconds: u64,
+    pub burst: Option<u32>,
+    /// Who shares a bucket.
+    #[serde(default)]
+    pub key: RateLimitKey,
+    /// For `Payer` limits, calls per `period_seconds` from one IP address,
+    /// whatever wallets they name. Unset allows ten times `requests`.
+    pub ip_requests: Option<u32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RateLimitKey {
+    /// Each paying wallet, taken from the `X-Payment` payload. Every call is
+    /// also counted against its IP address, with the limit's `ip_requests`, as
+    /// the payload isn't verified when limits are checked.
+    Payer,
+    /// Each client IP address.
+    #[default]
+    Ip,
+    /// All callers together.
+    Api,
+}
+
+impl RateLimitKey {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RateLimitKey::Payer => "payer",
+            RateLimitKey::Ip => "ip",
+            RateLimitKey::Api => "api",
+        }
+    }
+}
+
+/// What the API's call log keeps besides the metadata of every call.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct CallLogConfig {
+    /// Share of calls, from 0 to 1, whose request and response bodies are kept.
+    #[serde(default)]
+    pub body_sample_rate: f64,
+}
+
+/// Lets callers open WebSocket connections through the proxy. Each connection
+/// is paid for once, at `price` USDC (the API's `cost_per_request` when unset),
+/// and is closed by Enigma after `session_seconds`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WebSocketConfig {
+    pub session_seconds: u64,
+    pub price: Option<f64>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ProxyRequest {
     pub method: HttpMethod,
     pub path: Option<String>,
     pub headers: Option<serde_json::Value>,
+    /// Sent upstream as described by `body_encoding`.
     pub body: Option<serde_json::Value>,
+    #[serde(default)]
+    pub body_encoding: BodyEncoding,
+    /// Sent upstream as `Content-Type`. Defaults to the one given in `headers`,
+    /// and then to the one implied by `body_encoding`.
+    pub content_type: Option<String>,
     pub query_params: Option<serde_json::Value>,
+    pub response_format: Option<ResponseFormat>,
+}
+
+/// How `ProxyRequest.body` becomes the upstream request body.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum BodyEncoding {
+    /// Any JSON value, sent as `application/json`.
+    #[default]
+    Json,
+    /// A string, sent as it is as `text/plain`.
+    Text,
+    /// A base64 string, decoded and sent as `application/octet-stream`. For
+    /// binary payloads such as images.
+    Base64,
+    /// A list of `MultipartField`s, sent as `multipart/form-data`.
+    Multipart,
+}
+
+/// One field of a multipart/form-data body.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MultipartField {
+    pub name: String,
+    /// Text, or base64 when `base64` is set.
+    pub value: String,
+    #[serde(default)]
+    pub base64: bool,
+    /// Makes the field a file upload.
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+}
+
+/// How the proxy hands the upstream response back to the caller.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ResponseFormat {
+    /// Upstream status, headers and body are streamed back unchanged.
+    #[default]
+    Passthrough,
+    /// The response is buffered and wrapped in a `{status, headers, body, cache}` JSON document.
+    Envelope,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum WebhookEvent {
+    #[serde(rename = "payment.settled")]
+    PaymentSettled,
+    #[serde(rename = "settlement.failed")]
+    SettlementFailed,
+    /// An upstream of the API failed often enough for its circuit breaker to
+    /// open, and calls to it are refused until the breaker closes.
+    #[serde(rename = "api.suspended")]
+    ApiSuspended,
+    #[serde(rename = "quota.exceeded")]
+    QuotaExceeded,
+}
+
+impl WebhookEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WebhookEvent::PaymentSettled => "payment.settled",
+            WebhookEvent::SettlementFailed => "settlement.failed",
+            WebhookEvent::ApiSuspended => "api.suspended",
+            WebhookEvent::QuotaExceeded => "quota.exceeded",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "payment.settled" => Some(WebhookEvent::PaymentSettled),
+            "settlement.failed" => Some(WebhookEvent::SettlementFailed),
+            "api.suspended" => Some(WebhookEvent::ApiSuspended),
+            "quota.exceeded" => Some(WebhookEvent::QuotaExceeded),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Webhook {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    /// When set, only events for this API are delivered; otherwise every API of the user.
+    pub api_id: Option<Uuid>,
+    pub url: String,
+    pub events: Vec<WebhookEvent>,
+    /// Only returned when the webhook is created.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+    pub enabled: bool,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateWebhookRequest {
+    pub api_id: Option<Uuid>,
+    pub url: String,
+    pub events: Vec<WebhookEvent>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum DeliveryStatus {
+    Pending,
+    Delivered,
+    Failed,
+}
+
+impl DeliveryStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryStatus::Pending => "pending",
+            DeliveryStatus::Delivered => "delivered",
+            DeliveryStatus::Failed => "failed",
+        }
+    }
+
+    pub fn parse(value: &str) -> Self {
+        match value {
+            "delivered" => DeliveryStatus::Delivered,
+            "failed" => DeliveryStatus::Failed,
+            _ => DeliveryStatus::Pending,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookDelivery {
+    pub id: Uuid,
+    pub webhook_id: Uuid,
+    pub event: WebhookEvent,
+    pub payload: serde_json::Value,
+    pub status: DeliveryStatus,
+    pub attempts: i32,
+    pub last_status_code: Option<i32>,
+    pub last_error: Option<String>,
+    pub next_attempt_at: chrono::DateTime<chrono::Utc>,
+    pub delivered_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Where the proxy places a provider credential on the upstream request.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum SecretLocation {
+    Header,
+    Query,
+    Bearer,
+}
+
+impl SecretLocation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SecretLocation::Header => "header",
+            SecretLocation::Query => "query",
+            SecretLocation::Bearer => "bearer",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "header" => Some(SecretLocation::Header),
+            "query" => Some(SecretLocation::Query),
+            "bearer" => Some(SecretLocation::Bearer),
+            _ => None,
+        }
+    }
+}
+
+/// A stored upstream credential. The value itself is never returned.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiSecret {
+    pub id: Uuid,
+    pub api_id: Uuid,
+    pub location: SecretLocation,
+    pub name: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// An API's custom TLS settings for its upstream. Like secrets, the PEM
+/// material itself is never returned.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiTlsConfig {
+    pub api_id: Uuid,
+    pub has_ca_bundle: bool,
+    pub has_client_certificate: bool,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// A canned response served by an API's sandbox. `path` is the path of one of
+/// the API's endpoints, or a literal path for APIs without endpoints.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiMock {
+    pub id: Uuid,
+    pub api_id: Uuid,
+    pub method: HttpMethod,
+    pub path: String,
+    pub status: u16,
+    pub headers: std::collections::HashMap<String, String>,
+    pub body: String,
+    pub source: MockSource,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum MockSource {
+    /// Uploaded by the provider.
+    Uploaded,
+    /// Saved from a live response while the sandbox was recording.
+    Recorded,
+}
+
+impl MockSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MockSource::Uploaded => "uploaded",
+            MockSource::Recorded => "recorded",
+        }
+    }
+
+    pub fn parse(value: &str) -> Self {
+        match value {
+            "recorded" => MockSource::Recorded,
+            _ => MockSource::Uploaded,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateApiMockRequest {
+    pub method: HttpMethod,
+    pub path: String,
+    /// Defaults to 200.
+    pub status: Option<u16>,
+    #[serde(default)]
+    pub headers: std::collections::HashMap<String, String>,
+    /// Strings are served as they are, anything else as JSON.
+    pub body: serde_json::Value,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateApiSecretRequest {
+    pub location: SecretLocation,
+    /// Header or query parameter name; ignored for `Bearer`, which always uses `Authorization`.
+    pub name: Option<String>,
+    pub value: String,
+}
+
+/// PEM encoded TLS material for an API's upstream. The client certificate and
+/// key are given together; the key must be PKCS#8.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PutApiTlsRequest {
+    /// Certificates trusted for the upstream in addition to the system roots.
+    pub ca_bundle: Option<String>,
+    pub client_certificate: Option<String>,
+    pub client_key: Option<String>,
+}
+
+/// What a provider API key may do. Clerk sessions may do everything.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ApiKeyScope {
+    /// Read an API's secrets, TLS settings and mocks.
+    #[serde(rename = "apis:read")]
+    ApisRead,
+    /// Publish, update and delete APIs and their secrets, TLS settings and mocks.
+    #[serde(rename = "apis:write")]
+    ApisWrite,
+    /// Read call logs.
+    #[serde(rename = "analytics:read")]
+    AnalyticsRead,
+    #[serde(rename = "webhooks:read")]
+    WebhooksRead,
+    #[serde(rename = "webhooks:write")]
+    WebhooksWrite,
+}
+
+impl ApiKeyScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiKeyScope::ApisRead => "apis:read",
+            ApiKeyScope::ApisWrite => "apis:write",
+            ApiKeyScope::AnalyticsRead => "analytics:read",
+            ApiKeyScope::WebhooksRead => "webhooks:read",
+            ApiKeyScope::WebhooksWrite => "webhooks:write",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "apis:read" => Some(ApiKeyScope::ApisRead),
+            "apis:write" => Some(ApiKeyScope::ApisWrite),
+            "analytics:read" => Some(ApiKeyScope::AnalyticsRead),
+            "webhooks:read" => Some(ApiKeyScope::WebhooksRead),
+            "webhooks:write" => Some(ApiKeyScope::WebhooksWrite),
+            _ => None,
+        }
+    }
+}
+
+/// A key providers use instead of a Clerk session, e.g. to publish from CI.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub name: String,
+    /// The first characters of the key, to tell keys apart.
+    pub prefix: String,
+    pub scopes: Vec<ApiKeyScope>,
+    /// Only returned when the key is created.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    pub last_used_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub revoked_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateApiKeyRequest {
+    pub name: String,
+    pub scopes: Vec<ApiKeyScope>,
 }
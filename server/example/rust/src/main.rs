@@ -25,9 +25,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("Status: {}", get_response.status);
 
     if let Ok(json) = serde_json::from_str::<Value>(&get_response.body) {
-        if let Some(body) = json.get("body") {
-            println!("\n{}", serde_json::to_string_pretty(body)?);
-        }
+        println!("\n{}", serde_json::to_string_pretty(&json)?);
     }
 
     println!("\n=== POST Request ===");
@@ -35,9 +33,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("Status: {}", post_response.status);
 
     if let Ok(json) = serde_json::from_str::<Value>(&post_response.body) {
-        if let Some(body) = json.get("body") {
-            println!("\n{}", serde_json::to_string_pretty(body)?);
-        }
+        println!("\n{}", serde_json::to_string_pretty(&json)?);
     }
 
     Ok(())
@@ -156,7 +156,7 @@ impl X402Client {
 
         let response = self
             .http_client
-            .post(&self.config.endpoint_url())
+            .post(self.config.endpoint_url())
             .header("Content-Type", "application/json")
             .body(body_string)
             .send()
@@ -1,11 +1,19 @@
 use axum::serve;
-use dotenvy;
 use sqlx::PgPool;
 use std::net::SocketAddr;
 use tokio::net::TcpListener;
-use tracing_subscriber;
-use database::migrations;
+use database::{
+    migrations, ApiRepository, ApiSecretRepository, ApiTlsRepository, CallLogRepository,
+    HealthCheckRepository, WebhookRepository,
+};
+use server::call_log::{spawn_call_log_pruner, CallLoggerConfig};
 use server::create_app;
+use server::health::{spawn_health_checker, HealthCheckerConfig};
+use server::secrets::{rotate_secrets, SecretKeyring};
+use server::ssrf::UpstreamPolicy;
+use server::tls::{rotate_tls_configs, UpstreamTls};
+use server::upstream::{UpstreamClients, UpstreamDefaults};
+use server::webhooks::{spawn_dispatcher, DispatcherConfig};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -19,11 +27,47 @@ async fn main() -> anyhow::Result<()> {
 
     migrations::run_migrations(&pool).await?;
 
+    let keyring = SecretKeyring::from_env()?;
+    if let Some(keyring) = &keyring {
+        let rotated = rotate_secrets(&ApiSecretRepository::new(pool.clone()), keyring).await?;
+        if rotated > 0 {
+            tracing::info!("Re-encrypted {} API secrets under the active master key", rotated);
+        }
+        let rotated = rotate_tls_configs(&ApiTlsRepository::new(pool.clone()), keyring).await?;
+        if rotated > 0 {
+            tracing::info!(
+                "Re-encrypted {} TLS settings under the active master key",
+                rotated
+            );
+        }
+    }
+
+    spawn_dispatcher(
+        WebhookRepository::new(pool.clone()),
+        DispatcherConfig::from_env(),
+        UpstreamPolicy::from_env()?,
+    );
+    spawn_health_checker(
+        ApiRepository::new(pool.clone()),
+        HealthCheckRepository::new(pool.clone()),
+        UpstreamClients::new(UpstreamDefaults::from_env(), UpstreamPolicy::from_env()?),
+        UpstreamTls::new(ApiTlsRepository::new(pool.clone()), keyring),
+        HealthCheckerConfig::from_env(),
+    );
+    spawn_call_log_pruner(
+        CallLogRepository::new(pool.clone()),
+        CallLoggerConfig::from_env(),
+    );
+
     let app = create_app(pool);
     let addr = SocketAddr::from(([0, 0, 0, 0], 8080));
     tracing::info!("Server starting on {}", addr);
 
     let listener = TcpListener::bind(&addr).await?;
-    serve(listener, app).await?;
+    serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
     Ok(())
 }